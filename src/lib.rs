@@ -0,0 +1,11 @@
+extern crate alga;
+extern crate exr;
+extern crate image;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+extern crate rayon;
+
+pub mod number;
+pub mod render;
+mod vector;
@@ -1,23 +1,34 @@
-extern crate alga;
-extern crate image;
 extern crate nalgebra as na;
-extern crate num;
-
-mod number;
-mod render;
-mod vector;
+extern crate rust_tracer;
 
 use na::{Point3, Vector3};
-use render::camera::*;
-use render::renderer::*;
-use render::scene::*;
+use rust_tracer::render::camera::*;
+use rust_tracer::render::filter::*;
+use rust_tracer::render::material::*;
+use rust_tracer::render::plane::*;
+use rust_tracer::render::renderable::*;
+use rust_tracer::render::renderer::*;
+use rust_tracer::render::scene::*;
+use rust_tracer::render::sphere::*;
 use std::f64::consts::PI;
 use std::path::Path;
+use std::sync::Arc;
 
 fn main() {
-    let scene: Scene<f64> =
-        Scene::load(&Path::new("./example_resources/cube.obj")).expect("Could not load obj file");
-    let cam = Camera::look_at(
+    let mut scene: Scene<f64> =
+        Scene::load(Path::new("./example_resources/cube.obj")).expect("Could not load obj file");
+    for warning in scene.warnings() {
+        eprintln!("warning: {:?}", warning);
+    }
+    scene.add_object(ShapeMat::new(
+        Sphere::new(Point3::new(1.5, 0.5, -0.5), 0.5),
+        UniformMaterial::new(Lambert::new(0.2, 0.2, 1.0)),
+    ));
+    scene.add_object(ShapeMat::new(
+        Plane::new_at_point(Point3::origin(), Vector3::new(0.0, 1.0, 0.0)),
+        UniformMaterial::new(Lambert::new(0.8, 0.8, 0.8)),
+    ));
+    let cam = PerspectiveCamera::look_at(
         Point3::new(1.0, 2.0, -2.0),
         Point3::new(0.5, 0.5, 0.5),
         Vector3::new(0.0, 1.0, 0.0),
@@ -25,6 +36,11 @@ fn main() {
         2.0,
         PI / 2.0,
     );
-    let img = render(cam, &scene);
+    let settings = RenderSettings {
+        samples_per_pixel: 16,
+        filter: Arc::new(GaussianFilter::new(1.0, 2.0)),
+        ..RenderSettings::new(320, 180)
+    };
+    let img = render(cam, &scene, &settings);
     img.save("./out/test.png").expect("Failed to save to file.");
 }
@@ -1,2 +1,90 @@
-pub const EPSILON_64: f64 = 0.00001;
+use na::RealField;
+
 pub const EPSILON_32: f32 = 0.00001;
+
+/// Floating point types, with the extra operations needed to bound rounding error.
+pub trait Float: RealField + From<f32> {
+    /// The smallest representable value greater than `self`.
+    fn next_up(self) -> Self;
+
+    /// The largest representable value less than `self`.
+    fn next_down(self) -> Self;
+
+    /// Bound on the relative rounding error of a single operation. This is half the machine
+    /// epsilon.
+    fn unit_roundoff() -> Self;
+
+    /// Bound on the relative error accumulated over `n` operations, written as gamma n in
+    /// Physically Based Rendering.
+    fn gamma_bound(n: u8) -> Self {
+        let n_u = Self::unit_roundoff() * Self::from(f32::from(n));
+        n_u / (Self::one() - n_u)
+    }
+}
+
+macro_rules! impl_float {
+    ($t:ty) => {
+        impl Float for $t {
+            fn next_up(self) -> $t {
+                if self.is_infinite() && self > 0.0 {
+                    return self;
+                }
+                // -0.0 and 0.0 both step to the smallest positive value
+                let v = if self == -0.0 { 0.0 } else { self };
+                let bits = v.to_bits();
+                <$t>::from_bits(if v >= 0.0 { bits + 1 } else { bits - 1 })
+            }
+
+            fn next_down(self) -> $t {
+                -Float::next_up(-self)
+            }
+
+            fn unit_roundoff() -> $t {
+                <$t>::EPSILON * 0.5
+            }
+        }
+    };
+}
+
+impl_float!(f32);
+impl_float!(f64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn next_around_zero() {
+        assert!(Float::next_up(0.0f64) > 0.0);
+        assert!(Float::next_up(-0.0f64) > 0.0);
+        assert!(Float::next_down(0.0f32) < 0.0);
+        assert_eq!(Float::next_up(f64::INFINITY), f64::INFINITY);
+    }
+
+    #[test]
+    fn gamma_grows_with_operations() {
+        assert!(f64::gamma_bound(1) > f64::unit_roundoff());
+        assert!(f64::gamma_bound(5) > f64::gamma_bound(3));
+        assert!(f32::gamma_bound(3) > f64::gamma_bound(3) as f32);
+    }
+
+    proptest! {
+        #[test]
+        fn next_is_adjacent(x in -1e30f64..1e30) {
+            let up = Float::next_up(x);
+            let down = Float::next_down(x);
+            prop_assert!(up > x && down < x);
+            // nothing lies between x and its neighbours
+            prop_assert_eq!((x + up) / 2.0 == x || (x + up) / 2.0 == up, true);
+            prop_assert_eq!(Float::next_up(down), x);
+            // neighbours are one step apart in the bits, which count away from zero
+            if x != 0.0 {
+                let bits = x.to_bits();
+                let (above, below) = if x > 0.0 { (bits + 1, bits - 1) } else { (bits - 1, bits + 1) };
+                prop_assert_eq!(up, f64::from_bits(above));
+                prop_assert_eq!(down, f64::from_bits(below));
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use super::ray::Ray;
+use na::{Point3, RealField, Scalar, Vector3};
+
+/// Axis aligned bounding box.
+#[derive(Debug, Clone)]
+pub struct Aabb<T: Scalar> {
+    pub min: Point3<T>,
+    pub max: Point3<T>,
+}
+
+impl<T: Scalar> Aabb<T> {
+    pub fn new(min: Point3<T>, max: Point3<T>) -> Aabb<T> {
+        Aabb { min, max }
+    }
+}
+
+impl<T: RealField> Aabb<T> {
+    /// A box that contains nothing. This is the identity for `union`.
+    pub fn empty() -> Aabb<T> {
+        let max = T::max_value();
+        let min = T::min_value();
+        Aabb::new(Point3::new(max, max, max), Point3::new(min, min, min))
+    }
+
+    /// A box that contains all of space, for shapes like planes that extend forever.
+    pub fn unbounded() -> Aabb<T> {
+        let max = T::max_value();
+        let min = T::min_value();
+        Aabb::new(Point3::new(min, min, min), Point3::new(max, max, max))
+    }
+
+    /// Returns true if the box extends forever in any direction.
+    pub fn is_unbounded(&self) -> bool {
+        let (min, max) = (T::min_value(), T::max_value());
+        (0..3).any(|axis| self.min[axis] <= min || self.max[axis] >= max)
+    }
+
+    /// The smallest box containing all of the given points.
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Point3<T>>>(points: I) -> Aabb<T> {
+        points
+            .into_iter()
+            .fold(Aabb::empty(), |bounds, p| bounds.union_point(p))
+    }
+
+    pub fn union(&self, other: &Aabb<T>) -> Aabb<T> {
+        Aabb::new(
+            Point3::from(self.min.coords.zip_map(&other.min.coords, |a, b| a.min(b))),
+            Point3::from(self.max.coords.zip_map(&other.max.coords, |a, b| a.max(b))),
+        )
+    }
+
+    pub fn union_point(&self, point: &Point3<T>) -> Aabb<T> {
+        Aabb::new(
+            Point3::from(self.min.coords.zip_map(&point.coords, |a, b| a.min(b))),
+            Point3::from(self.max.coords.zip_map(&point.coords, |a, b| a.max(b))),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn diagonal(&self) -> Vector3<T> {
+        self.max - self.min
+    }
+
+    pub fn centroid(&self) -> Point3<T> {
+        self.min + self.diagonal() * T::from_subset(&0.5)
+    }
+
+    pub fn surface_area(&self) -> T {
+        if self.is_empty() {
+            return T::zero();
+        }
+        let d = self.diagonal();
+        (d.x * d.y + d.x * d.z + d.y * d.z) * T::from_subset(&2.0)
+    }
+
+    /// Index of the axis the box is longest along, 0 for x, 1 for y and 2 for z.
+    pub fn longest_axis(&self) -> usize {
+        let d = self.diagonal();
+        if d.x > d.y && d.x > d.z {
+            0
+        } else if d.y > d.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Returns true if the ray passes through the box at some time in `[ray.tmin, max_t]`.
+    ///
+    /// `inv_dir` is the component wise reciprocal of the ray direction. It is passed in so that
+    /// it only needs to be calculated once per ray when testing many boxes.
+    pub fn intersects(&self, ray: &Ray<T>, inv_dir: &Vector3<T>, max_t: T) -> bool {
+        let mut t0 = ray.tmin;
+        let mut t1 = max_t;
+        for axis in 0..3 {
+            let near = (self.min[axis] - ray.origin[axis]) * inv_dir[axis];
+            let far = (self.max[axis] - ray.origin[axis]) * inv_dir[axis];
+            let (near, far) = if near > far { (far, near) } else { (near, far) };
+            // widen the interval slightly, so rounding error cannot make the ray miss the box
+            // when it hits a shape lying on the boundary
+            let far = far * (T::one() + T::default_epsilon() * T::from_subset(&4.0));
+            // Comparisons are written so a NaN, from a ray lying on a slab boundary, leaves the
+            // interval unchanged.
+            if near > t0 {
+                t0 = near;
+            }
+            if far < t1 {
+                t1 = far;
+            }
+            if t0 > t1 {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> Aabb<f64> {
+        Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0))
+    }
+
+    fn inv(ray: &Ray<f64>) -> Vector3<f64> {
+        ray.direction.map(|x| 1.0 / x)
+    }
+
+    #[test]
+    fn union_contains_both() {
+        let a = unit_box();
+        let b = Aabb::new(Point3::new(-1.0, 0.5, 0.5), Point3::new(0.5, 2.0, 0.5));
+        let u = a.union(&b);
+        assert_eq!(u.min, Point3::new(-1.0, 0.0, 0.0));
+        assert_eq!(u.max, Point3::new(1.0, 2.0, 1.0));
+    }
+
+    #[test]
+    fn empty_box_has_no_area() {
+        let empty: Aabb<f64> = Aabb::empty();
+        assert!(empty.is_empty());
+        assert_eq!(empty.surface_area(), 0.0);
+        assert_eq!(unit_box().surface_area(), 6.0);
+    }
+
+    #[test]
+    fn unbounded_box() {
+        let all: Aabb<f64> = Aabb::unbounded();
+        assert!(all.is_unbounded());
+        assert!(!unit_box().is_unbounded());
+        assert!(unit_box().union(&all).is_unbounded());
+    }
+
+    #[test]
+    fn ray_hits_box() {
+        let ray = Ray::new_normalize(Point3::new(-1.0, 0.5, 0.5), Vector3::new(1.0, 0.0, 0.0));
+        assert!(unit_box().intersects(&ray, &inv(&ray), 10.0));
+        // the box is further than max_t
+        assert!(!unit_box().intersects(&ray, &inv(&ray), 0.5));
+    }
+
+    #[test]
+    fn ray_misses_box() {
+        let ray = Ray::new_normalize(Point3::new(-1.0, 2.0, 0.5), Vector3::new(1.0, 0.0, 0.0));
+        assert!(!unit_box().intersects(&ray, &inv(&ray), 10.0));
+        let away = Ray::new_normalize(Point3::new(-1.0, 0.5, 0.5), Vector3::new(-1.0, 0.0, 0.0));
+        assert!(!unit_box().intersects(&away, &inv(&away), 10.0));
+    }
+}
@@ -0,0 +1,290 @@
+use super::material::Material;
+use super::shape::DiffGeom;
+use super::spectrum::Spec;
+use super::texture::Texture;
+use crate::number::Float;
+use na::{Point2, Scalar, Vector3};
+use std::sync::Arc;
+
+/// Detail too fine to model, added to a surface by bending its shading normal.
+pub trait ShadingDetail: Send + Sync {
+    type NumTy: Scalar;
+
+    /// Shading geometry of the surface with the detail added.
+    fn apply(&self, g: DiffGeom<Self::NumTy>) -> DiffGeom<Self::NumTy>;
+}
+
+impl<D: ShadingDetail + ?Sized> ShadingDetail for Arc<D> {
+    type NumTy = D::NumTy;
+
+    fn apply(&self, g: DiffGeom<Self::NumTy>) -> DiffGeom<Self::NumTy> {
+        (**self).apply(g)
+    }
+}
+
+/// No detail at all for None.
+impl<D: ShadingDetail> ShadingDetail for Option<D> {
+    type NumTy = D::NumTy;
+
+    fn apply(&self, g: DiffGeom<Self::NumTy>) -> DiffGeom<Self::NumTy> {
+        match self {
+            Some(detail) => detail.apply(g),
+            None => g,
+        }
+    }
+}
+
+/// Detail of any type, which can be shared between materials and threads.
+pub type SharedDetail<N> = Arc<dyn ShadingDetail<NumTy = N>>;
+
+/// Bumps described by a height map, raising each point of the surface along its normal.
+///
+/// Only the shading normal changes, by the slope of the height map, which is found by finite
+/// differences across the area a sample covers.
+#[derive(Clone)]
+pub struct BumpMap<T, N> {
+    height: T,
+    /// Distance the surface is raised by a height of 1.
+    scale: N,
+}
+
+impl<T, N> BumpMap<T, N> {
+    pub fn new(height: T, scale: N) -> BumpMap<T, N> {
+        BumpMap { height, scale }
+    }
+}
+
+impl<N: Float, T: Texture<N, NumTy = N>> ShadingDetail for BumpMap<T, N> {
+    type NumTy = N;
+
+    fn apply(&self, g: DiffGeom<N>) -> DiffGeom<N> {
+        // Half the area a sample covers, so the slope doesn't pick up noise from detail too fine
+        // to see. Points need some step to measure a slope over.
+        let step = if g.uv_width > N::zero() {
+            g.uv_width / N::from(2.0)
+        } else {
+            N::from(5e-4)
+        };
+        let height_at = |du: N, dv: N| {
+            let shifted = DiffGeom {
+                position: g.position + g.dpdu * du + g.dpdv * dv,
+                uv: Point2::new(g.uv.x + du, g.uv.y + dv),
+                ..g.clone()
+            };
+            self.height.evaluate(&shifted) * self.scale
+        };
+        let height = height_at(N::zero(), N::zero());
+        let dhdu = (height_at(step, N::zero()) - height) / step;
+        let dhdv = (height_at(N::zero(), step) - height) / step;
+
+        // Tangents in the plane of the shading normal, so that smooth shading is kept.
+        let n = g.normal;
+        let dpdu = g.dpdu - n * n.dot(&g.dpdu) + n * dhdu;
+        let dpdv = g.dpdv - n * n.dot(&g.dpdv) + n * dhdv;
+        let bumped = match dpdu.cross(&dpdv).try_normalize(N::zero()) {
+            Some(bumped) if bumped.dot(&n) < N::zero() => -bumped,
+            Some(bumped) => bumped,
+            None => return g,
+        };
+        DiffGeom {
+            normal: bumped,
+            dpdu,
+            dpdv,
+            ..g
+        }
+    }
+}
+
+/// Shading normals read from an image, as in most normal maps made for games.
+///
+/// Each color is a unit vector in tangent space, with each channel scaled from `[-1, 1]` to
+/// `[0, 1]`. Red is along increasing u, green along increasing v and blue along the normal. The
+/// image should be read without sRGB conversion, see `ImageTexture::load_linear`.
+#[derive(Clone)]
+pub struct NormalMap<T, N> {
+    map: T,
+    /// Scale of the tilt away from the surface normal, like the `-bm` option of an MTL `norm`.
+    strength: N,
+}
+
+impl<T, N: Float> NormalMap<T, N> {
+    pub fn new(map: T) -> NormalMap<T, N> {
+        NormalMap {
+            map,
+            strength: N::one(),
+        }
+    }
+
+    /// Tilts the normals by `strength` times as much, so 0 leaves the surface smooth.
+    pub fn with_strength(self, strength: N) -> NormalMap<T, N> {
+        NormalMap { strength, ..self }
+    }
+}
+
+impl<N: Float, T: Texture<Spec<N>, NumTy = N>> ShadingDetail for NormalMap<T, N> {
+    type NumTy = N;
+
+    fn apply(&self, g: DiffGeom<N>) -> DiffGeom<N> {
+        let color = self.map.evaluate(&g);
+        let unpack = |c: N| c * N::from(2.0) - N::one();
+        let local = Vector3::new(
+            unpack(color.red) * self.strength,
+            unpack(color.green) * self.strength,
+            unpack(color.blue),
+        );
+        let n = g.normal;
+        let tangent = match (g.dpdu - n * n.dot(&g.dpdu)).try_normalize(N::zero()) {
+            Some(tangent) => tangent,
+            None => return g,
+        };
+        let mut bitangent = n.cross(&tangent);
+        // mirrored texture coordinates turn v the other way
+        if bitangent.dot(&g.dpdv) < N::zero() {
+            bitangent = -bitangent;
+        }
+        let mapped = tangent * local.x + bitangent * local.y + n * local.z;
+        match mapped.try_normalize(N::zero()) {
+            Some(normal) => DiffGeom { normal, ..g },
+            None => g,
+        }
+    }
+}
+
+/// Material with detail added to its surface.
+#[derive(Clone)]
+pub struct Detailed<M, D> {
+    material: M,
+    detail: D,
+}
+
+impl<M, D> Detailed<M, D> {
+    pub fn new(material: M, detail: D) -> Detailed<M, D> {
+        Detailed { material, detail }
+    }
+}
+
+impl<M: Material, D: ShadingDetail<NumTy = M::NumTy>> Material for Detailed<M, D> {
+    type NumTy = M::NumTy;
+    type BSDF_fn = M::BSDF_fn;
+
+    fn get_bsdf(&self, g: &DiffGeom<Self::NumTy>) -> Self::BSDF_fn {
+        self.material.get_bsdf(g)
+    }
+
+    fn shading_geometry(&self, g: DiffGeom<Self::NumTy>) -> DiffGeom<Self::NumTy> {
+        self.detail.apply(self.material.shading_geometry(g))
+    }
+
+    fn emission(&self) -> Option<Spec<Self::NumTy>> {
+        self.material.emission()
+    }
+
+    fn opacity(&self) -> Option<Self::NumTy> {
+        self.material.opacity()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::material::{Lambert, UniformMaterial};
+    use crate::render::texture::{ConstantTexture, FbmTexture, ImageTexture};
+    use na::Point3;
+
+    /// Flat surface facing +z, with u along +x and v along +y.
+    fn flat(uv: Point2<f64>) -> DiffGeom<f64> {
+        DiffGeom {
+            position: Point3::new(uv.x, uv.y, 0.0),
+            normal: Vector3::z(),
+            geometric_normal: Vector3::z(),
+            uv,
+            dpdu: Vector3::x(),
+            dpdv: Vector3::y(),
+            uv_width: 0.0,
+        }
+    }
+
+    /// Height of `slope * u`.
+    struct Ramp(f64);
+
+    impl Texture<f64> for Ramp {
+        type NumTy = f64;
+
+        fn evaluate(&self, g: &DiffGeom<f64>) -> f64 {
+            self.0 * g.uv.x
+        }
+    }
+
+    #[test]
+    fn bumps_tilt_normal_by_slope() {
+        let bumped = BumpMap::new(Ramp(1.0), 0.5).apply(flat(Point2::new(0.3, 0.3)));
+        // rising half a unit per unit of u tilts the normal back towards -x
+        let expected = Vector3::new(-0.5, 0.0, 1.0).normalize();
+        assert!((bumped.normal - expected).magnitude() < 1e-6);
+
+        let level = BumpMap::new(ConstantTexture::new(3.0), 1.0).apply(flat(Point2::origin()));
+        assert!((level.normal - Vector3::z()).magnitude() < 1e-10);
+    }
+
+    #[test]
+    fn bumps_keep_smooth_shading_normal() {
+        // an interpolated normal, tilted from the face the derivatives lie in
+        let tilted = Vector3::new(0.3, 0.0, 1.0).normalize();
+        let g = DiffGeom {
+            normal: tilted,
+            ..flat(Point2::origin())
+        };
+        let noise = FbmTexture::new(1.0, 4, 0.5);
+        let bumped = BumpMap::new(noise, 0.0).apply(g);
+        assert!((bumped.normal - tilted).magnitude() < 1e-10);
+    }
+
+    #[test]
+    fn normal_map_uses_tangent_space() {
+        // pointing halfway between +u and the normal
+        let half = 0.5 + 0.5 * (0.5f64).sqrt();
+        let map = ImageTexture::new(1, 1, vec![Spec::new(half, 0.5, half)]);
+        let mapped = NormalMap::new(map).apply(flat(Point2::new(0.5, 0.5)));
+        let expected = Vector3::new(1.0, 0.0, 1.0).normalize();
+        assert!((mapped.normal - expected).magnitude() < 1e-6);
+
+        // with v turned around, green points the other way
+        let map = ImageTexture::new(1, 1, vec![Spec::new(0.5, 1.0, 0.5)]);
+        let mirrored = DiffGeom {
+            dpdv: -Vector3::y(),
+            ..flat(Point2::new(0.5, 0.5))
+        };
+        let mapped = NormalMap::new(map).apply(mirrored);
+        assert!((mapped.normal + Vector3::y()).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn normal_map_strength_scales_tilt() {
+        let half = 0.5 + 0.5 * (0.5f64).sqrt();
+        let map = ImageTexture::new(1, 1, vec![Spec::new(half, 0.5, half)]);
+        let doubled = NormalMap::new(map.clone())
+            .with_strength(2.0)
+            .apply(flat(Point2::new(0.5, 0.5)));
+        let expected = Vector3::new(2.0, 0.0, 1.0).normalize();
+        assert!((doubled.normal - expected).magnitude() < 1e-6);
+
+        let flattened = NormalMap::new(map)
+            .with_strength(0.0)
+            .apply(flat(Point2::new(0.5, 0.5)));
+        assert!((flattened.normal - Vector3::z()).magnitude() < 1e-10);
+    }
+
+    #[test]
+    fn detailed_material_bends_normal() {
+        let material = Detailed::new(
+            UniformMaterial::new(Lambert::new(1.0, 1.0, 1.0)),
+            Some(BumpMap::new(Ramp(2.0), 1.0)),
+        );
+        let g = material.shading_geometry(flat(Point2::new(0.5, 0.5)));
+        assert!(g.normal.x < -0.5);
+        let plain: Detailed<_, Option<BumpMap<Ramp, f64>>> =
+            Detailed::new(UniformMaterial::new(Lambert::new(1.0, 1.0, 1.0)), None);
+        let g = plain.shading_geometry(flat(Point2::new(0.5, 0.5)));
+        assert_eq!(g.normal, Vector3::z());
+    }
+}
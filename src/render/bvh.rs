@@ -0,0 +1,394 @@
+use super::aabb::Aabb;
+use super::ray::Ray;
+use super::shape::{Shape, SurfaceInteraction};
+use na::{Point3, RealField, Scalar, Vector3};
+
+/// Number of buckets the surface area heuristic splits each node's centroids into.
+const SAH_BUCKETS: usize = 12;
+/// Nodes with more shapes than this are always split. Smaller nodes are only split when the
+/// surface area heuristic expects it to be cheaper than testing every shape.
+const MAX_LEAF_SIZE: usize = 8;
+/// Cost of traversing a node, relative to the cost of intersecting one shape.
+const TRAVERSAL_COST: f64 = 0.125;
+
+/// Bounding volume hierarchy over a slice of shapes.
+///
+/// The hierarchy only stores indices into the slice it was built from, so the same slice must be
+/// passed to the intersection queries. Shapes with unbounded extent, like planes, are kept out of
+/// the tree and tested against every ray.
+pub struct Bvh<T: Scalar> {
+    nodes: Vec<BvhNode<T>>,
+    // shape indices, ordered so that each leaf refers to a contiguous range
+    indices: Vec<usize>,
+    unbounded: Vec<usize>,
+}
+
+enum BvhNode<T: Scalar> {
+    Leaf {
+        bounds: Aabb<T>,
+        first: usize,
+        count: usize,
+    },
+    // The first child directly follows its parent in `nodes`.
+    Interior {
+        bounds: Aabb<T>,
+        second_child: usize,
+        axis: usize,
+    },
+}
+
+impl<T: Scalar> BvhNode<T> {
+    fn bounds(&self) -> &Aabb<T> {
+        match self {
+            BvhNode::Leaf { bounds, .. } => bounds,
+            BvhNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+struct BuildInfo<T: Scalar> {
+    index: usize,
+    bounds: Aabb<T>,
+    centroid: Point3<T>,
+}
+
+impl<T: Scalar> Bvh<T> {
+    /// A hierarchy containing no shapes.
+    pub fn empty() -> Bvh<T> {
+        Bvh {
+            nodes: vec![],
+            indices: vec![],
+            unbounded: vec![],
+        }
+    }
+
+    /// The number of shapes in the hierarchy.
+    pub fn len(&self) -> usize {
+        self.indices.len() + self.unbounded.len()
+    }
+}
+
+impl<T: RealField> Bvh<T> {
+    /// Builds a hierarchy over `shapes`, choosing splits with the surface area heuristic.
+    pub fn build<S: Shape<NumTy = T>>(shapes: &[S]) -> Bvh<T> {
+        let mut unbounded = vec![];
+        let mut info: Vec<BuildInfo<T>> = Vec::with_capacity(shapes.len());
+        for (index, s) in shapes.iter().enumerate() {
+            let bounds = s.bounds();
+            if bounds.is_unbounded() {
+                unbounded.push(index);
+                continue;
+            }
+            let centroid = bounds.centroid();
+            info.push(BuildInfo {
+                index,
+                bounds,
+                centroid,
+            });
+        }
+
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * info.len()),
+            indices: Vec::with_capacity(info.len()),
+            unbounded,
+        };
+        if !info.is_empty() {
+            bvh.build_node(&mut info);
+        }
+        bvh
+    }
+
+    // Appends the subtree for `info` to `nodes`, returning the index of its root.
+    fn build_node(&mut self, info: &mut [BuildInfo<T>]) -> usize {
+        let node_index = self.nodes.len();
+        let bounds = info.iter().fold(Aabb::empty(), |b, i| b.union(&i.bounds));
+
+        let split = if info.len() <= 2 {
+            None
+        } else {
+            sah_split(info, &bounds)
+        };
+
+        match split {
+            None => {
+                let first = self.indices.len();
+                self.indices.extend(info.iter().map(|i| i.index));
+                self.nodes.push(BvhNode::Leaf {
+                    bounds,
+                    first,
+                    count: info.len(),
+                });
+            }
+            Some((axis, mid)) => {
+                // placeholder, replaced once the second child's index is known
+                self.nodes.push(BvhNode::Leaf {
+                    bounds: Aabb::empty(),
+                    first: 0,
+                    count: 0,
+                });
+                let (left, right) = info.split_at_mut(mid);
+                self.build_node(left);
+                let second_child = self.build_node(right);
+                self.nodes[node_index] = BvhNode::Interior {
+                    bounds,
+                    second_child,
+                    axis,
+                };
+            }
+        }
+        node_index
+    }
+
+    /// Finds the closest shape the ray intersects, and where it was hit.
+    /// The interaction's `primitive_id` is the index of the shape in `shapes`.
+    pub fn closest_hit<'a, S: Shape<NumTy = T>>(
+        &self,
+        shapes: &'a [S],
+        ray: &Ray<T>,
+    ) -> Option<(&'a S, SurfaceInteraction<T>)> {
+        let mut closest: Option<(&'a S, SurfaceInteraction<T>)> = None;
+        self.traverse(ray, ray.tmax, |index, max_t| {
+            let shape = &shapes[index];
+            if let Some(mut interaction) = shape.intersection(ray) {
+                if interaction.t < *max_t {
+                    *max_t = interaction.t;
+                    interaction.primitive_id = index;
+                    closest = Some((shape, interaction));
+                }
+            }
+            false
+        });
+        closest
+    }
+
+    /// Returns true if the ray intersects any shape within its interval.
+    /// This stops at the first intersection found.
+    pub fn any_hit<S: Shape<NumTy = T>>(&self, shapes: &[S], ray: &Ray<T>) -> bool {
+        let mut hit = false;
+        self.traverse(ray, ray.tmax, |index, _| {
+            hit = shapes[index].intersection(ray).is_some();
+            hit
+        });
+        hit
+    }
+
+    /// Calls `visit` with every shape the ray intersects within its interval, in no particular
+    /// order. `visit` can stop the search by returning true.
+    pub fn each_hit<S: Shape<NumTy = T>, F: FnMut(&S) -> bool>(
+        &self,
+        shapes: &[S],
+        ray: &Ray<T>,
+        mut visit: F,
+    ) {
+        self.traverse(ray, ray.tmax, |index, _| {
+            let shape = &shapes[index];
+            shape.intersection(ray).is_some() && visit(shape)
+        });
+    }
+
+    // Calls `visit` with the index of every shape in a leaf the ray passes through before
+    // `max_t`. `visit` may shorten `max_t`, and can stop the traversal by returning true.
+    fn traverse<F: FnMut(usize, &mut T) -> bool>(&self, ray: &Ray<T>, max_t: T, mut visit: F) {
+        let mut max_t = max_t;
+        for &index in &self.unbounded {
+            if visit(index, &mut max_t) {
+                return;
+            }
+        }
+        if self.nodes.is_empty() {
+            return;
+        }
+        let inv_dir: Vector3<T> = ray.direction.map(|x| T::one() / x);
+        let mut stack: Vec<usize> = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if !node.bounds().intersects(ray, &inv_dir, max_t) {
+                continue;
+            }
+            match node {
+                BvhNode::Leaf { first, count, .. } => {
+                    for &index in &self.indices[*first..*first + *count] {
+                        if visit(index, &mut max_t) {
+                            return;
+                        }
+                    }
+                }
+                BvhNode::Interior {
+                    second_child, axis, ..
+                } => {
+                    // visit the child nearest the ray origin first, so max_t shrinks sooner
+                    if ray.direction[*axis] < T::zero() {
+                        stack.push(node_index + 1);
+                        stack.push(*second_child);
+                    } else {
+                        stack.push(*second_child);
+                        stack.push(node_index + 1);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Chooses where to split a node with the surface area heuristic.
+///
+/// Centroids are binned along the longest axis of their bounds, and the cheapest split between
+/// bins is chosen. On success `info` is partitioned so that the shapes in the first child come
+/// first, and the split axis and size of the first child are returned. Returns `None` when making
+/// a leaf is cheaper.
+fn sah_split<T: RealField>(info: &mut [BuildInfo<T>], bounds: &Aabb<T>) -> Option<(usize, usize)> {
+    let centroid_bounds = Aabb::from_points(info.iter().map(|i| &i.centroid));
+    let axis = centroid_bounds.longest_axis();
+    let min = centroid_bounds.min[axis];
+    let extent = centroid_bounds.max[axis] - min;
+    if extent <= T::zero() {
+        // all centroids are in the same place, so they cannot be separated
+        return None;
+    }
+
+    let bucket_of = |c: &Point3<T>| -> usize {
+        let relative: f64 = ((c[axis] - min) / extent).to_subset().unwrap_or(0.0);
+        ((relative * SAH_BUCKETS as f64) as usize).min(SAH_BUCKETS - 1)
+    };
+
+    let mut counts = [0usize; SAH_BUCKETS];
+    let mut bucket_bounds: Vec<Aabb<T>> = vec![Aabb::empty(); SAH_BUCKETS];
+    for i in info.iter() {
+        let b = bucket_of(&i.centroid);
+        counts[b] += 1;
+        bucket_bounds[b] = bucket_bounds[b].union(&i.bounds);
+    }
+
+    let total_area: f64 = bounds.surface_area().to_subset().unwrap_or(0.0);
+    let mut best: Option<(usize, f64)> = None;
+    for split in 1..SAH_BUCKETS {
+        let (below, above) = bucket_bounds.split_at(split);
+        let (count_below, count_above) = counts.split_at(split);
+        let area = |bs: &[Aabb<T>]| -> f64 {
+            let b = bs.iter().fold(Aabb::empty(), |acc, b| acc.union(b));
+            b.surface_area().to_subset().unwrap_or(0.0)
+        };
+        let n_below: usize = count_below.iter().sum();
+        let n_above: usize = count_above.iter().sum();
+        if n_below == 0 || n_above == 0 {
+            continue;
+        }
+        let cost = TRAVERSAL_COST
+            + (area(below) * n_below as f64 + area(above) * n_above as f64) / total_area;
+        let better = match best {
+            None => true,
+            Some((_, best_cost)) => cost < best_cost,
+        };
+        if better {
+            best = Some((split, cost));
+        }
+    }
+
+    let (split, cost) = best?;
+    if cost >= info.len() as f64 && info.len() <= MAX_LEAF_SIZE {
+        // intersecting everything in one leaf is expected to be cheaper
+        return None;
+    }
+
+    let mut mid = 0;
+    for i in 0..info.len() {
+        if bucket_of(&info[i].centroid) < split {
+            info.swap(i, mid);
+            mid += 1;
+        }
+    }
+    Some((axis, mid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::plane::Plane;
+    use super::super::ray::tests::arb_ray;
+    use super::super::triangle::tests::arb_tri;
+    use super::super::triangle::Triangle;
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    /// Closest intersection found by testing every shape.
+    fn brute_force<S: Shape<NumTy = f64>>(shapes: &[S], ray: &Ray<f64>) -> Option<f64> {
+        shapes
+            .iter()
+            .filter_map(|s| s.intersection(ray).map(|hit| hit.t))
+            .fold(None, |closest: Option<f64>, t| {
+                Some(closest.map_or(t, |c| c.min(t)))
+            })
+    }
+
+    #[test]
+    fn unbounded_shapes_are_hit() {
+        let planes = vec![
+            Plane::new(Vector3::x(), -5.0),
+            Plane::new(Vector3::x(), -2.0),
+        ];
+        let bvh = Bvh::build(&planes);
+        assert_eq!(bvh.len(), 2);
+        let ray = Ray::new_normalize(Point3::origin(), Vector3::x());
+        let (_, hit) = bvh
+            .closest_hit(&planes, &ray)
+            .expect("ray hits both planes");
+        assert_eq!(hit.t, 2.0);
+        assert!(bvh.any_hit(&planes, &ray.clone().with_interval(0.0, 3.0)));
+        assert!(!bvh.any_hit(&planes, &ray.clone().with_interval(0.0, 1.0)));
+        // the interval can also start past the nearer plane
+        let (_, hit) = bvh
+            .closest_hit(&planes, &ray.with_interval(3.0, 10.0))
+            .expect("ray hits the further plane");
+        assert_eq!(hit.t, 5.0);
+    }
+
+    #[test]
+    fn empty_bvh_has_no_hits() {
+        let shapes: Vec<Triangle<f64>> = vec![];
+        let bvh = Bvh::build(&shapes);
+        let ray = Ray::new_normalize(Point3::origin(), Vector3::x());
+        assert!(bvh.closest_hit(&shapes, &ray).is_none());
+        assert!(!bvh.any_hit(&shapes, &ray));
+    }
+
+    proptest! {
+        #[test]
+        fn closest_hit_matches_brute_force(tris in vec(arb_tri(-10f64..10.0), 0..64),
+                                           rays in vec(arb_ray(-10f64..10.0, -1f64..1.0), 16)) {
+            let bvh = Bvh::build(&tris);
+            prop_assert_eq!(bvh.len(), tris.len());
+            for ray in rays.iter().filter(|r| r.direction.magnitude() > 0.5) {
+                let expected = brute_force(&tris, ray);
+                let actual = bvh.closest_hit(&tris, ray).map(|(_, hit)| hit.t);
+                prop_assert_eq!(expected, actual);
+            }
+        }
+
+        #[test]
+        fn any_hit_matches_brute_force(tris in vec(arb_tri(-10f64..10.0), 0..64),
+                                       rays in vec(arb_ray(-10f64..10.0, -1f64..1.0), 16),
+                                       max_t in 0f64..20.0) {
+            let bvh = Bvh::build(&tris);
+            for ray in rays.into_iter().filter(|r| r.direction.magnitude() > 0.5) {
+                let ray = ray.with_interval(0.0, max_t);
+                let expected = tris.iter().any(|s| s.intersection(&ray).is_some());
+                prop_assert_eq!(expected, bvh.any_hit(&tris, &ray));
+            }
+        }
+
+        #[test]
+        fn each_hit_visits_every_hit(tris in vec(arb_tri(-10f64..10.0), 0..64),
+                                     rays in vec(arb_ray(-10f64..10.0, -1f64..1.0), 16)) {
+            let bvh = Bvh::build(&tris);
+            for ray in rays.into_iter().filter(|r| r.direction.magnitude() > 0.5) {
+                let expected = tris.iter().filter(|s| s.intersection(&ray).is_some()).count();
+                let mut visited = 0;
+                bvh.each_hit(&tris, &ray, |_| {
+                    visited += 1;
+                    false
+                });
+                prop_assert_eq!(expected, visited);
+            }
+        }
+    }
+}
@@ -1,8 +1,51 @@
 use super::ray::Ray;
+use super::sampling::{concentric_sample_disk, sample_polygon};
 use na::{Point3, RealField, Rotation3, Scalar, Vector3};
 
-#[derive(Debug)]
-pub struct Camera<T: Scalar> {
+/// Maps points on the image to the rays that are traced for them.
+///
+/// Positions on the image are given as `x` and `y` in `[0, 1]`, the fraction of the way across
+/// the image. Cameras look along +z relative to their orientation, with `x` increasing along +x
+/// and `y` along +y.
+pub trait Camera: Send + Sync {
+    type NumTy: Scalar;
+
+    /// Ray through the centre of the lens at the given coordinates on the camera. None where
+    /// the camera sees nothing, such as outside the image circle of a fisheye lens.
+    fn ray_at(&self, x: Self::NumTy, y: Self::NumTy) -> Option<Ray<Self::NumTy>>;
+
+    /// Ray at the given coordinates on the camera, like `ray_at`, through a point on the lens
+    /// picked by the uniform sample `(u1, u2)`. Cameras without a lens ignore the sample.
+    fn sample_ray(
+        &self,
+        x: Self::NumTy,
+        y: Self::NumTy,
+        _u1: Self::NumTy,
+        _u2: Self::NumTy,
+    ) -> Option<Ray<Self::NumTy>> {
+        self.ray_at(x, y)
+    }
+
+    /// Fits the camera to an image with `width / height` of `aspect_ratio`, so that pixels are
+    /// square.
+    fn with_aspect_ratio(self, aspect_ratio: Self::NumTy) -> Self
+    where
+        Self: Sized;
+}
+
+/// Orientation of a camera at `position` looking at `at_point`, with `up` pointing up.
+fn look_at_orientation<T: RealField>(
+    position: &Point3<T>,
+    at_point: &Point3<T>,
+    up: &Vector3<T>,
+) -> Rotation3<T> {
+    let view_direction = at_point - position;
+    Rotation3::look_at_lh(&view_direction, up).inverse()
+}
+
+/// Camera with a perspective projection, like a photograph.
+#[derive(Debug, Clone)]
+pub struct PerspectiveCamera<T: Scalar> {
     // position of the camera
     position: Point3<T>,
     // (where it is looking at)
@@ -10,27 +53,35 @@ pub struct Camera<T: Scalar> {
     width: T,
     height: T,
     fov: T,
+    // radius of the lens, 0 for a pinhole camera
+    aperture: T,
+    // distance along the view direction of the plane in focus
+    focus_distance: T,
+    // number of straight edges of the lens opening, 0 for a round one
+    blades: u32,
 }
 
-impl<T: Scalar> Camera<T> {
+impl<T: RealField> PerspectiveCamera<T> {
+    /// Pinhole camera, which has everything in focus. See `with_aperture` for depth of field.
     pub fn new(
         position: Point3<T>,
         orientation: Rotation3<T>,
         width: T,
         height: T,
         fov: T,
-    ) -> Camera<T> {
-        Camera {
+    ) -> PerspectiveCamera<T> {
+        PerspectiveCamera {
             position,
             orientation,
             width,
             height,
             fov,
+            aperture: T::zero(),
+            focus_distance: T::one(),
+            blades: 0,
         }
     }
-}
 
-impl<T: RealField> Camera<T> {
     pub fn look_at(
         position: Point3<T>,
         at_point: Point3<T>,
@@ -38,38 +89,291 @@ impl<T: RealField> Camera<T> {
         width: T,
         height: T,
         fov: T,
-    ) -> Camera<T> {
-        let view_direction = at_point - position;
-        let orientation = Rotation3::look_at_lh(&view_direction, &up).inverse();
-        Camera::new(position, orientation, width, height, fov)
+    ) -> PerspectiveCamera<T> {
+        let orientation = look_at_orientation(&position, &at_point, &up);
+        PerspectiveCamera::new(position, orientation, width, height, fov)
+    }
+
+    /// Like `look_at`, with a lens of radius `aperture` focused on `at_point`.
+    pub fn look_at_focused(
+        position: Point3<T>,
+        at_point: Point3<T>,
+        up: Vector3<T>,
+        width: T,
+        height: T,
+        fov: T,
+        aperture: T,
+    ) -> PerspectiveCamera<T> {
+        let focus_distance = (at_point - position).norm();
+        PerspectiveCamera::look_at(position, at_point, up, width, height, fov)
+            .with_aperture(aperture, focus_distance)
+    }
+
+    /// Gives the camera a thin lens of radius `aperture`, focused on the plane `focus_distance`
+    /// in front of it. Anything nearer or further is blurred, more so with a wider aperture.
+    pub fn with_aperture(self, aperture: T, focus_distance: T) -> PerspectiveCamera<T> {
+        PerspectiveCamera {
+            aperture,
+            focus_distance,
+            ..self
+        }
+    }
+
+    /// Makes the opening of the lens a regular polygon with `blades` edges, like the blades of
+    /// a real aperture, rather than a circle. Out of focus highlights take the same shape.
+    /// Fewer than 3 blades gives a circle.
+    pub fn with_blades(self, blades: u32) -> PerspectiveCamera<T> {
+        PerspectiveCamera { blades, ..self }
     }
 }
 
-// generalize this to other floats
-impl Camera<f64> {
-    // returns a ray at the given coordinates on the camera
-    // x and y: [0, 1] are percents of the way across the camera
-    pub fn ray_at(&self, x: f64, y: f64) -> Ray<f64> {
+impl<T: RealField> PerspectiveCamera<T> {
+    /// Origin and direction of the pinhole ray at the given coordinates, relative to the
+    /// camera, which looks along +z.
+    fn pinhole_ray(&self, x: T, y: T) -> (Point3<T>, Vector3<T>) {
+        let two = T::one() + T::one();
+        let half = T::one() / two;
         // point on the unit screen at
         // calculate the focal point behind the sceen
         // draw a ray at the screen with the angle
-        // orient and translate the ray
-        let off_set: Vector3<f64> = self.orientation.transform_vector(&Vector3::new(
-            (x - 0.5) * self.width,
-            (y - 0.5) * self.height,
-            0.0,
-        ));
-        let point = self.position + off_set;
+        let point = Point3::new((x - half) * self.width, (y - half) * self.height, T::zero());
 
         // focal_point lies behind the camera plane, used to determine the ray direction.
-        let half_fov = self.fov / 2.0;
-        let focal_distance = self.width / (2.0 * half_fov.tan());
-        let focal_point = self.position
-            + self
-                .orientation
-                .transform_vector(&Vector3::new(0.0, 0.0, -1.0))
-                * focal_distance;
-        Ray::new_normalize(point, point - focal_point)
+        let half_fov = self.fov / two;
+        let focal_distance = self.width / (two * half_fov.tan());
+        let focal_point = Point3::new(T::zero(), T::zero(), -focal_distance);
+        (point, point - focal_point)
+    }
+}
+
+impl<T: RealField> Camera for PerspectiveCamera<T> {
+    type NumTy = T;
+
+    // returns a ray at the given coordinates on the camera
+    // x and y: [0, 1] are percents of the way across the camera
+    fn ray_at(&self, x: T, y: T) -> Option<Ray<T>> {
+        let (origin, direction) = self.pinhole_ray(x, y);
+        Some(Ray::new_normalize(
+            self.position + self.orientation.transform_vector(&origin.coords),
+            self.orientation.transform_vector(&direction),
+        ))
+    }
+
+    /// Rays through every point of the lens meet on the plane in focus. For a pinhole camera
+    /// this is the same as `ray_at`.
+    fn sample_ray(&self, x: T, y: T, u1: T, u2: T) -> Option<Ray<T>> {
+        if self.aperture <= T::zero() {
+            return self.ray_at(x, y);
+        }
+        let (origin, direction) = self.pinhole_ray(x, y);
+        let focus = origin + direction * (self.focus_distance / direction.z);
+        let lens = if self.blades >= 3 {
+            sample_polygon(self.blades, u1, u2)
+        } else {
+            concentric_sample_disk(u1, u2)
+        };
+        let origin = origin + Vector3::new(lens.x, lens.y, T::zero()) * self.aperture;
+        Some(Ray::new_normalize(
+            self.position + self.orientation.transform_vector(&origin.coords),
+            self.orientation.transform_vector(&(focus - origin)),
+        ))
+    }
+
+    /// Changes the height of the camera so that `width / height` is `aspect_ratio`.
+    /// The width, and so the field of view, is unchanged.
+    fn with_aspect_ratio(self, aspect_ratio: T) -> PerspectiveCamera<T> {
+        PerspectiveCamera {
+            height: self.width / aspect_ratio,
+            ..self
+        }
+    }
+}
+
+/// Camera with parallel rays, which shows objects the same size at any distance. Used for
+/// technical drawings and isometric views.
+#[derive(Debug, Clone)]
+pub struct OrthographicCamera<T: Scalar> {
+    position: Point3<T>,
+    orientation: Rotation3<T>,
+    // size of the area seen, on the plane of the camera
+    width: T,
+    height: T,
+}
+
+impl<T: RealField> OrthographicCamera<T> {
+    pub fn new(
+        position: Point3<T>,
+        orientation: Rotation3<T>,
+        width: T,
+        height: T,
+    ) -> OrthographicCamera<T> {
+        OrthographicCamera {
+            position,
+            orientation,
+            width,
+            height,
+        }
+    }
+
+    pub fn look_at(
+        position: Point3<T>,
+        at_point: Point3<T>,
+        up: Vector3<T>,
+        width: T,
+        height: T,
+    ) -> OrthographicCamera<T> {
+        let orientation = look_at_orientation(&position, &at_point, &up);
+        OrthographicCamera::new(position, orientation, width, height)
+    }
+}
+
+impl<T: RealField> Camera for OrthographicCamera<T> {
+    type NumTy = T;
+
+    fn ray_at(&self, x: T, y: T) -> Option<Ray<T>> {
+        let half = T::one() / (T::one() + T::one());
+        let offset = Vector3::new((x - half) * self.width, (y - half) * self.height, T::zero());
+        Some(Ray::new_normalize(
+            self.position + self.orientation.transform_vector(&offset),
+            self.orientation.transform_vector(&Vector3::z()),
+        ))
+    }
+
+    /// Changes the height of the camera so that `width / height` is `aspect_ratio`.
+    fn with_aspect_ratio(self, aspect_ratio: T) -> OrthographicCamera<T> {
+        OrthographicCamera {
+            height: self.width / aspect_ratio,
+            ..self
+        }
+    }
+}
+
+/// Camera with an equidistant fisheye lens, where the angle from the view direction grows
+/// evenly with the distance from the centre of the image.
+///
+/// The image circle spans the width of the image. Points outside it see nothing, so the corners
+/// of the image, and the top and bottom of a wide image, are left black.
+#[derive(Debug, Clone)]
+pub struct FisheyeCamera<T: Scalar> {
+    position: Point3<T>,
+    orientation: Rotation3<T>,
+    // angle across the image circle, up to a full turn
+    fov: T,
+    aspect_ratio: T,
+}
+
+impl<T: RealField> FisheyeCamera<T> {
+    pub fn new(position: Point3<T>, orientation: Rotation3<T>, fov: T) -> FisheyeCamera<T> {
+        FisheyeCamera {
+            position,
+            orientation,
+            fov,
+            aspect_ratio: T::one(),
+        }
+    }
+
+    pub fn look_at(
+        position: Point3<T>,
+        at_point: Point3<T>,
+        up: Vector3<T>,
+        fov: T,
+    ) -> FisheyeCamera<T> {
+        let orientation = look_at_orientation(&position, &at_point, &up);
+        FisheyeCamera::new(position, orientation, fov)
+    }
+}
+
+impl<T: RealField> Camera for FisheyeCamera<T> {
+    type NumTy = T;
+
+    fn ray_at(&self, x: T, y: T) -> Option<Ray<T>> {
+        let two = T::one() + T::one();
+        // position in the image circle, which has a radius of 1
+        let px = x * two - T::one();
+        let py = (y * two - T::one()) / self.aspect_ratio;
+        let r = (px * px + py * py).sqrt();
+        if r > T::one() {
+            return None;
+        }
+        let theta = r * self.fov / two;
+        let phi = py.atan2(px);
+        let direction = Vector3::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        );
+        Some(Ray::new_normalize(
+            self.position,
+            self.orientation.transform_vector(&direction),
+        ))
+    }
+
+    /// The image circle keeps the width of the image.
+    fn with_aspect_ratio(self, aspect_ratio: T) -> FisheyeCamera<T> {
+        FisheyeCamera {
+            aspect_ratio,
+            ..self
+        }
+    }
+}
+
+/// Camera that sees in every direction, making an equirectangular panorama, as used for VR and
+/// environment maps.
+///
+/// The image is laid out like the ones `EnvironmentLight` reads, so a panorama rendered with the
+/// camera's orientation as the rotation of an environment gives back that environment. The top
+/// row is the camera's +y axis and the bottom row its -y axis. Columns go around the y axis,
+/// starting from +x and turning towards +z. The image should be twice as wide as it is high, so
+/// that pixels cover equal angles across and down.
+#[derive(Debug, Clone)]
+pub struct EquirectangularCamera<T: Scalar> {
+    position: Point3<T>,
+    orientation: Rotation3<T>,
+}
+
+impl<T: RealField> EquirectangularCamera<T> {
+    pub fn new(position: Point3<T>, orientation: Rotation3<T>) -> EquirectangularCamera<T> {
+        EquirectangularCamera {
+            position,
+            orientation,
+        }
+    }
+
+    /// Camera with `at_point` in the middle of the image and `up` at the top.
+    pub fn look_at(
+        position: Point3<T>,
+        at_point: Point3<T>,
+        up: Vector3<T>,
+    ) -> EquirectangularCamera<T> {
+        // the middle of the image is the camera's -x axis, so turn that to where +z looked
+        let quarter_turn = T::pi() / (T::one() + T::one());
+        let middle_to_z = Rotation3::from_axis_angle(&Vector3::y_axis(), quarter_turn);
+        let orientation = look_at_orientation(&position, &at_point, &up) * middle_to_z;
+        EquirectangularCamera::new(position, orientation)
+    }
+}
+
+impl<T: RealField> Camera for EquirectangularCamera<T> {
+    type NumTy = T;
+
+    fn ray_at(&self, x: T, y: T) -> Option<Ray<T>> {
+        // angles from straight up and around from +x, as in `EnvironmentLight`
+        let theta = y * T::pi();
+        let phi = x * T::two_pi();
+        let direction = Vector3::new(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        );
+        Some(Ray::new_normalize(
+            self.position,
+            self.orientation.transform_vector(&direction),
+        ))
+    }
+
+    /// The panorama always covers every direction, so the aspect ratio is ignored.
+    fn with_aspect_ratio(self, _aspect_ratio: T) -> EquirectangularCamera<T> {
+        self
     }
 }
 
@@ -85,7 +389,7 @@ mod tests {
     #[test]
     fn center_point_at_position() {
         let position = Point3::new(0.0, 0.0, 0.0);
-        let cam: Camera<f64> = Camera::look_at(
+        let cam: PerspectiveCamera<f64> = PerspectiveCamera::look_at(
             position,
             Point3::new(0.0, 0.0, 1.0),
             Vector3::new(0.0, 1.0, 0.0),
@@ -93,7 +397,7 @@ mod tests {
             1.0,
             std::f64::consts::PI / 2.0,
         );
-        let mid_ray = cam.ray_at(0.5, 0.5);
+        let mid_ray = cam.ray_at(0.5, 0.5).unwrap();
 
         assert!(abs_diff_eq!(mid_ray.origin, position));
     }
@@ -118,19 +422,201 @@ mod tests {
             (pos in arb_point(-100.0f64..100.0f64),
              orientation in arb_basis3(),
              width in 1.0f64..100.0,
-             height in 1.0f64..100.0) -> Camera<f64>
+             height in 1.0f64..100.0) -> PerspectiveCamera<f64>
         {
-            Camera::new(pos, orientation, width, height, PI / 2.0)
+            PerspectiveCamera::new(pos, orientation, width, height, PI / 2.0)
         }
     }
 
+    #[test]
+    fn lens_rays_meet_on_focus_plane() {
+        let cam = PerspectiveCamera::look_at_focused(
+            Point3::new(1.0, 2.0, 3.0),
+            Point3::new(1.0, 2.0, 8.0),
+            Vector3::y(),
+            1.0,
+            1.0,
+            PI / 2.0,
+            0.5,
+        );
+        // where a ray crosses the plane z = 8
+        let on_focus_plane = |ray: &Ray<f64>| ray.at_time((8.0 - ray.origin.z) / ray.direction.z);
+        for &(x, y) in &[(0.5, 0.5), (0.1, 0.8), (0.9, 0.3)] {
+            let pinhole = cam.ray_at(x, y).unwrap();
+            let sharp = on_focus_plane(&pinhole);
+            for &(u1, u2) in &[(0.1, 0.2), (0.9, 0.5), (0.4, 0.99)] {
+                let ray = cam.sample_ray(x, y, u1, u2).unwrap();
+                let hit = on_focus_plane(&ray);
+                assert!((hit - sharp).magnitude() < 1e-8);
+            }
+        }
+    }
+
+    #[test]
+    fn lens_rays_start_inside_aperture() {
+        let aperture = 0.25;
+        let cam =
+            PerspectiveCamera::new(Point3::origin(), Rotation3::identity(), 1.0, 1.0, PI / 2.0)
+                .with_aperture(aperture, 4.0)
+                .with_blades(6);
+        let centre = cam.ray_at(0.5, 0.5).unwrap().origin;
+        let mut furthest: f64 = 0.0;
+        for i in 0..100 {
+            let u = f64::from(i) / 100.0;
+            let ray = cam.sample_ray(0.5, 0.5, u, (u * 7.0).fract()).unwrap();
+            furthest = furthest.max((ray.origin - centre).magnitude());
+        }
+        assert!(furthest <= aperture + 1e-10);
+        assert!(furthest > aperture / 2.0);
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let cam: OrthographicCamera<f64> = OrthographicCamera::look_at(
+            Point3::new(0.0, 0.0, -5.0),
+            Point3::origin(),
+            Vector3::y(),
+            4.0,
+            4.0,
+        )
+        .with_aspect_ratio(2.0);
+        let corner = cam.ray_at(0.0, 0.0).unwrap();
+        let opposite = cam.ray_at(1.0, 1.0).unwrap();
+        assert!(abs_diff_eq!(corner.direction, Vector3::z_axis()));
+        assert!(abs_diff_eq!(opposite.direction, Vector3::z_axis()));
+        let span = opposite.origin - corner.origin;
+        assert!(abs_diff_eq!(span.x.abs(), 4.0, epsilon = 1e-10));
+        assert!(abs_diff_eq!(span.y.abs(), 2.0, epsilon = 1e-10));
+        assert!(abs_diff_eq!(span.z, 0.0, epsilon = 1e-10));
+    }
+
+    #[test]
+    fn fisheye_angle_grows_with_radius() {
+        let fov = 1.5 * PI;
+        let cam = FisheyeCamera::new(Point3::origin(), Rotation3::identity(), fov);
+        let forward = Vector3::z();
+        let centre = cam.ray_at(0.5, 0.5).unwrap();
+        assert!(abs_diff_eq!(centre.direction.into_inner(), forward));
+        // half way out is a quarter of the field of view from the centre
+        let halfway = cam.ray_at(0.75, 0.5).unwrap();
+        assert!(abs_diff_eq!(
+            halfway.direction.angle(&forward),
+            fov / 4.0,
+            epsilon = 1e-10
+        ));
+        let edge = cam.ray_at(0.5, 0.0).unwrap();
+        assert!(abs_diff_eq!(
+            edge.direction.angle(&forward),
+            fov / 2.0,
+            epsilon = 1e-10
+        ));
+        assert!(cam.ray_at(0.0, 0.0).is_none());
+
+        // a wide image crops the top and bottom of the circle
+        let wide = cam.with_aspect_ratio(2.0);
+        let top = wide.ray_at(0.5, 0.0).unwrap();
+        assert!(abs_diff_eq!(
+            top.direction.angle(&forward),
+            fov / 4.0,
+            epsilon = 1e-10
+        ));
+    }
+
+    #[test]
+    fn equirectangular_covers_sphere() {
+        let cam: EquirectangularCamera<f64> = EquirectangularCamera::look_at(
+            Point3::new(1.0, 2.0, 3.0),
+            Point3::new(1.0, 2.0, 4.0),
+            Vector3::y(),
+        );
+        let direction = |x, y| cam.ray_at(x, y).unwrap().direction.into_inner();
+        assert!(abs_diff_eq!(
+            direction(0.5, 0.5),
+            Vector3::z(),
+            epsilon = 1e-10
+        ));
+        assert!(abs_diff_eq!(
+            direction(0.0, 0.5),
+            -Vector3::z(),
+            epsilon = 1e-10
+        ));
+        assert!(abs_diff_eq!(
+            direction(1.0, 0.5),
+            -Vector3::z(),
+            epsilon = 1e-10
+        ));
+        // the poles are along the camera's vertical axis, with up at the top
+        assert!(abs_diff_eq!(
+            direction(0.3, 0.0),
+            Vector3::y(),
+            epsilon = 1e-10
+        ));
+        assert!(abs_diff_eq!(
+            direction(0.3, 1.0),
+            -Vector3::y(),
+            epsilon = 1e-10
+        ));
+        assert!(abs_diff_eq!(
+            direction(0.75, 0.5).angle(&Vector3::z()),
+            PI / 2.0,
+            epsilon = 1e-10
+        ));
+    }
+
+    #[test]
+    fn equirectangular_matches_environment_layout() {
+        let cam = EquirectangularCamera::new(Point3::origin(), Rotation3::identity());
+        let direction = |x, y| cam.ray_at(x, y).unwrap().direction.into_inner();
+        // columns start at +x and turn towards +z
+        assert!(abs_diff_eq!(
+            direction(0.0, 0.5),
+            Vector3::x(),
+            epsilon = 1e-10
+        ));
+        assert!(abs_diff_eq!(
+            direction(0.25, 0.5),
+            Vector3::z(),
+            epsilon = 1e-10
+        ));
+        assert!(abs_diff_eq!(
+            direction(0.5, 0.5),
+            -Vector3::x(),
+            epsilon = 1e-10
+        ));
+        assert!(abs_diff_eq!(
+            direction(0.1, 0.0),
+            Vector3::y(),
+            epsilon = 1e-10
+        ));
+    }
+
     proptest! {
+        #[test]
+        fn equirectangular_rays_start_at_camera(x in 0.0f64..1.0, y in 0.0f64..1.0) {
+            let position = Point3::new(1.0, 2.0, 3.0);
+            let cam = EquirectangularCamera::new(position, Rotation3::identity());
+            let ray = cam.ray_at(x, y).unwrap();
+            prop_assert!(abs_diff_eq!(ray.origin, position));
+            // the angle from straight up is linear in y
+            let d = ray.direction;
+            let theta = (d.x * d.x + d.z * d.z).sqrt().atan2(d.y);
+            prop_assert!(abs_diff_eq!(theta, y * PI, epsilon = 1e-8));
+        }
+
+        #[test]
+        fn with_aspect_ratio_keeps_width(cam in arb_camera(), aspect in 0.1f64..10.0) {
+            let width = cam.width;
+            let cam = cam.with_aspect_ratio(aspect);
+            prop_assert!(abs_diff_eq!(cam.width, width));
+            prop_assert!(abs_diff_eq!(cam.width / cam.height, aspect, epsilon = 0.00001));
+        }
+
         #[test]
         fn looks_at_point(position in arb_point(-100.0f64..100.0),
                           target in arb_point(-100.0f64..100.0)) {
             prop_assume!((position - target).magnitude_squared() > 0.0001);
-            let cam = Camera::look_at(position, target, Vector3::y(), 1.0, 1.0, PI/2.0);
-            let center_ray = cam.ray_at(0.5, 0.5);
+            let cam = PerspectiveCamera::look_at(position, target, Vector3::y(), 1.0, 1.0, PI/2.0);
+            let center_ray = cam.ray_at(0.5, 0.5).unwrap();
             let closest = center_ray.closest_point(target);
             println!("cam = {:?}", cam);
             println!("ray = {:?}", center_ray);
@@ -141,7 +627,7 @@ mod tests {
         fn ray_origin_on_view_plane(cam in arb_camera(),
                                     x in 0.0f64..1.0,
                                     y in 0.0f64..1.0) {
-            let ray = cam.ray_at(x, y);
+            let ray = cam.ray_at(x, y).unwrap();
             let view_plane = Plane::new_at_point(cam.position, cam.orientation.transform_vector(&(Vector3::z() * -1.0)));
 
             prop_assert!(view_plane.distance_to(ray.origin) < 0.00001);
@@ -149,7 +635,7 @@ mod tests {
 
         #[test]
         fn center_ray_views_direction(cam in arb_camera()) {
-            let ray = cam.ray_at(0.5, 0.5);
+            let ray = cam.ray_at(0.5, 0.5).unwrap();
             let view_vector = cam.orientation.transform_vector(&Vector3::z());
             let theta = ray.direction.angle(&view_vector);
 
@@ -159,8 +645,8 @@ mod tests {
         #[test]
         fn camera_width(cam in arb_camera(),
                         y in 0.0f64..1.0f64) {
-            let left_most = cam.ray_at(0.0, y);
-            let right_most = cam.ray_at(1.0, y);
+            let left_most = cam.ray_at(0.0, y).unwrap();
+            let right_most = cam.ray_at(1.0, y).unwrap();
             let calc_width_sq = (left_most.origin - right_most.origin).magnitude_squared();
             let actual_width_sq = cam.width.powi(2);
             prop_assert!(abs_diff_eq!(calc_width_sq, actual_width_sq, epsilon=0.00001),
@@ -170,8 +656,8 @@ mod tests {
         #[test]
         fn camera_height(cam in arb_camera(),
                          x in 0.0f64..1.0f64) {
-            let top_most = cam.ray_at(x, 1.0);
-            let bottom_most = cam.ray_at(x, 0.0);
+            let top_most = cam.ray_at(x, 1.0).unwrap();
+            let bottom_most = cam.ray_at(x, 0.0).unwrap();
             prop_assert!(abs_diff_eq!(
                     (top_most.origin - bottom_most.origin).magnitude_squared(),
                     cam.height.powi(2),
@@ -180,8 +666,8 @@ mod tests {
 
         #[test]
         fn horizontal_view_angle_is_fov(cam in arb_camera()) {
-            let left_most = cam.ray_at(0.0, 0.5);
-            let right_most = cam.ray_at(1.0, 0.5);
+            let left_most = cam.ray_at(0.0, 0.5).unwrap();
+            let right_most = cam.ray_at(1.0, 0.5).unwrap();
 
             let theta = left_most.direction.angle(&right_most.direction);
             prop_assert!(abs_diff_eq!(theta, cam.fov, epsilon=0.00001), "theta = {:?}", theta);
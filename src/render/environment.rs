@@ -0,0 +1,339 @@
+use super::light::{Light, LightSample};
+use super::ray::Ray;
+use super::sampling::{step_index, Distribution2D};
+use super::shape::SurfaceInteraction;
+use super::spectrum::Spec;
+use crate::number::Float;
+use image::hdr::HDRDecoder;
+use na::{Point2, Rotation3, Scalar, Vector3};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Light arriving from every direction, read from an equirectangular image surrounding the scene.
+///
+/// The top row of the image is straight up, +y, and the bottom row straight down. Columns go
+/// around the y axis, starting from +x and turning towards +z. Directions are picked in
+/// proportion to the brightness of the image, so small bright areas like the sun are found
+/// quickly.
+pub struct EnvironmentLight<T: Scalar> {
+    width: usize,
+    height: usize,
+    /// Radiance of each pixel, row by row from the top.
+    pixels: Vec<Spec<T>>,
+    distribution: Distribution2D<T>,
+    /// Turns directions relative to the image into directions in the scene.
+    rotation: Rotation3<T>,
+    /// Scale applied to the radiance of every pixel.
+    intensity: T,
+}
+
+#[derive(Debug)]
+pub enum EnvironmentLoadError {
+    OpenError,
+    DecodeError,
+    /// Only Radiance `.hdr` and OpenEXR `.exr` files can be read.
+    UnsupportedFormat,
+}
+
+impl<N: Float> EnvironmentLight<N> {
+    /// Environment with the given radiance for each pixel, row by row from the top. The image
+    /// must not be empty.
+    pub fn new(width: usize, height: usize, pixels: Vec<Spec<N>>) -> EnvironmentLight<N> {
+        assert!(width > 0 && height > 0, "empty environment map");
+        assert_eq!(pixels.len(), width * height, "wrong number of pixels");
+        // Rows near the poles are squashed into less of the sphere, so they are picked less often.
+        // Pixels of HDR images can be a little negative, and are never picked.
+        let weights: Vec<N> = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, pixel)| {
+                let row = N::from((i / width) as f32);
+                let theta = N::pi() * (row + N::from(0.5)) / N::from(height as f32);
+                pixel.luminance().max(N::zero()) * theta.sin()
+            })
+            .collect();
+        EnvironmentLight {
+            width,
+            height,
+            distribution: Distribution2D::new(&weights, width, height),
+            pixels,
+            rotation: Rotation3::identity(),
+            intensity: N::one(),
+        }
+    }
+
+    /// Reads an equirectangular Radiance `.hdr` or OpenEXR `.exr` image. Only the first layer
+    /// of an OpenEXR image is read, and any alpha channel is ignored.
+    pub fn load(path: &Path) -> Result<EnvironmentLight<N>, EnvironmentLoadError> {
+        let extension = path.extension().and_then(|e| e.to_str());
+        let (width, height, pixels) = match extension.map(str::to_ascii_lowercase).as_deref() {
+            Some("hdr") => read_hdr(path)?,
+            Some("exr") => read_exr(path)?,
+            _ => return Err(EnvironmentLoadError::UnsupportedFormat),
+        };
+        if width == 0 || height == 0 {
+            return Err(EnvironmentLoadError::DecodeError);
+        }
+        let pixels = pixels
+            .into_iter()
+            .map(|[r, g, b]| Spec::new(N::from(r), N::from(g), N::from(b)))
+            .collect();
+        Ok(EnvironmentLight::new(width, height, pixels))
+    }
+
+    /// Rotates the environment around the scene.
+    pub fn with_rotation(self, rotation: Rotation3<N>) -> EnvironmentLight<N> {
+        EnvironmentLight { rotation, ..self }
+    }
+
+    /// Scales the brightness of the environment.
+    pub fn with_intensity(self, intensity: N) -> EnvironmentLight<N> {
+        EnvironmentLight { intensity, ..self }
+    }
+
+    /// Radiance arriving from the image at `uv`, the position in the image scaled to `[0, 1)`.
+    fn lookup(&self, uv: &Point2<N>) -> Spec<N> {
+        let x = step_index(uv.x, self.width);
+        let y = step_index(uv.y, self.height);
+        self.pixels[y * self.width + x].clone() * self.intensity
+    }
+
+    /// Position in the image seen in the unit direction `w` in the scene, and the sine of its
+    /// angle from straight up.
+    fn to_image(&self, w: &Vector3<N>) -> (Point2<N>, N) {
+        let local = self.rotation.inverse() * w;
+        let cos_theta = local.y.max(-N::one()).min(N::one());
+        let mut phi = local.z.atan2(local.x);
+        if phi < N::zero() {
+            phi += N::two_pi();
+        }
+        let uv = Point2::new(phi / N::two_pi(), cos_theta.acos() / N::pi());
+        (uv, (N::one() - cos_theta * cos_theta).max(N::zero()).sqrt())
+    }
+
+    /// Converts a density over the image to a density over directions.
+    fn to_solid_angle_pdf(map_pdf: N, sin_theta: N) -> N {
+        if sin_theta <= N::zero() {
+            return N::zero();
+        }
+        map_pdf / (N::two_pi() * N::pi() * sin_theta)
+    }
+}
+
+impl<N: Float> Light for EnvironmentLight<N> {
+    type NumTy = N;
+
+    fn sample_li(
+        &self,
+        interaction: &SurfaceInteraction<N>,
+        u1: N,
+        u2: N,
+    ) -> Option<LightSample<N>> {
+        let (uv, map_pdf) = self.distribution.sample(u1, u2);
+        let theta = uv.y * N::pi();
+        let phi = uv.x * N::two_pi();
+        let sin_theta = theta.sin();
+        let pdf = Self::to_solid_angle_pdf(map_pdf, sin_theta);
+        if pdf <= N::zero() {
+            return None;
+        }
+        let local = Vector3::new(sin_theta * phi.cos(), theta.cos(), sin_theta * phi.sin());
+        Some(LightSample {
+            radiance: self.lookup(&uv),
+            shadow_ray: interaction.spawn_ray(self.rotation * local),
+            pdf,
+        })
+    }
+
+    fn pdf_li(&self, _interaction: &SurfaceInteraction<N>, wi: &Vector3<N>) -> N {
+        let (uv, sin_theta) = self.to_image(&wi.normalize());
+        Self::to_solid_angle_pdf(self.distribution.pdf(&uv), sin_theta)
+    }
+
+    fn le(&self, ray: &Ray<N>) -> Option<Spec<N>> {
+        let (uv, _) = self.to_image(&ray.direction);
+        Some(self.lookup(&uv))
+    }
+}
+
+/// Width, height and linear RGB pixels, row by row from the top, of a Radiance `.hdr` image.
+fn read_hdr(path: &Path) -> Result<(usize, usize, Vec<[f32; 3]>), EnvironmentLoadError> {
+    let file = File::open(path).map_err(|_| EnvironmentLoadError::OpenError)?;
+    let decoder =
+        HDRDecoder::new(BufReader::new(file)).map_err(|_| EnvironmentLoadError::DecodeError)?;
+    let metadata = decoder.metadata();
+    let pixels = decoder
+        .read_image_hdr()
+        .map_err(|_| EnvironmentLoadError::DecodeError)?
+        .into_iter()
+        .map(|p| [p[0], p[1], p[2]])
+        .collect();
+    Ok((metadata.width as usize, metadata.height as usize, pixels))
+}
+
+/// Like `read_hdr`, for the first layer of an OpenEXR image.
+fn read_exr(path: &Path) -> Result<(usize, usize, Vec<[f32; 3]>), EnvironmentLoadError> {
+    let image = exr::prelude::read_first_rgba_layer_from_file(
+        path,
+        |size, _| (size.width(), vec![[0.0; 3]; size.area()]),
+        |(width, pixels): &mut (usize, Vec<[f32; 3]>), at, (r, g, b, _): (f32, f32, f32, f32)| {
+            pixels[at.y() * *width + at.x()] = [r, g, b];
+        },
+    )
+    .map_err(|e| match e {
+        exr::error::Error::Io(_) => EnvironmentLoadError::OpenError,
+        _ => EnvironmentLoadError::DecodeError,
+    })?;
+    let (width, pixels) = image.layer_data.channel_data.pixels;
+    Ok((width, pixels.len() / width.max(1), pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::temp_dir::TempDir;
+    use super::*;
+    use image::hdr::HDREncoder;
+    use image::Rgb;
+    use na::Point3;
+    use proptest::prelude::*;
+    use std::f64::consts::PI;
+
+    fn interaction() -> SurfaceInteraction<f64> {
+        SurfaceInteraction::new(
+            0.0,
+            Point3::origin(),
+            Vector3::zeros(),
+            Vector3::y(),
+            Point2::origin(),
+            Vector3::x(),
+            Vector3::z(),
+        )
+    }
+
+    /// Dim 8 by 4 environment, with one bright pixel just above the horizon.
+    fn sun() -> EnvironmentLight<f64> {
+        let mut pixels = vec![Spec::new(0.1, 0.1, 0.1); 32];
+        pixels[8 + 3] = Spec::new(100.0, 90.0, 80.0);
+        EnvironmentLight::new(8, 4, pixels)
+    }
+
+    #[test]
+    fn uniform_environment_has_uniform_pdf() {
+        let light = EnvironmentLight::new(16, 8, vec![Spec::new(1.0, 1.0, 1.0); 128]);
+        let expected = 1.0 / (4.0 * PI);
+        for &(u1, u2) in &[(0.1, 0.5), (0.7, 0.2), (0.4, 0.9)] {
+            let sample = light.sample_li(&interaction(), u1, u2).unwrap();
+            // pixels near the poles cover less of the sphere, so the pdf is only roughly uniform
+            assert!((sample.pdf - expected).abs() < 0.25 * expected);
+        }
+    }
+
+    #[test]
+    fn samples_favour_bright_pixels() {
+        let light = sun();
+        let sample = light.sample_li(&interaction(), 0.5, 0.5).unwrap();
+        assert!(sample.radiance.red > 99.0);
+        // the bright pixel is in row 1, a little above the horizon
+        let direction = sample.shadow_ray.direction;
+        assert!(direction.y > 0.0 && direction.y < 0.75);
+    }
+
+    #[test]
+    fn rotation_turns_environment() {
+        let quarter_turn = Rotation3::from_axis_angle(&Vector3::y_axis(), PI / 2.0);
+        let light = sun();
+        let turned = sun().with_rotation(quarter_turn).with_intensity(2.0);
+        let sample = light.sample_li(&interaction(), 0.5, 0.5).unwrap();
+        let direction = sample.shadow_ray.direction.into_inner();
+        let turned_ray = Ray::new_normalize(Point3::origin(), quarter_turn * direction);
+        let seen = turned.le(&turned_ray).unwrap();
+        assert!((seen.red - 2.0 * sample.radiance.red).abs() < 1e-6);
+    }
+
+    #[test]
+    fn loads_hdr_files() {
+        let dir = TempDir::new("load_environment");
+        let path = dir.join("sky.hdr");
+        let data: Vec<Rgb<f32>> = (0..8).map(|i| Rgb([i as f32, 0.5, 2.0])).collect();
+        HDREncoder::new(File::create(&path).unwrap())
+            .encode(&data, 4, 2)
+            .unwrap();
+        let light: EnvironmentLight<f64> = EnvironmentLight::load(&path).unwrap();
+        assert_eq!((light.width, light.height), (4, 2));
+        assert!((light.pixels[5].red - 5.0).abs() < 0.1);
+
+        assert!(matches!(
+            EnvironmentLight::<f64>::load(&dir.join("sky.png")),
+            Err(EnvironmentLoadError::UnsupportedFormat)
+        ));
+        assert!(matches!(
+            EnvironmentLight::<f64>::load(&dir.join("missing.hdr")),
+            Err(EnvironmentLoadError::OpenError)
+        ));
+    }
+
+    #[test]
+    fn loads_exr_files() {
+        let dir = TempDir::new("load_exr");
+        let path = dir.join("sky.EXR");
+        exr::prelude::write_rgb_file(&path, 4, 2, |x, y| (x as f32, y as f32, 0.25)).unwrap();
+        let light: EnvironmentLight<f64> = EnvironmentLight::load(&path).unwrap();
+        assert_eq!((light.width, light.height), (4, 2));
+        // pixels are read row by row from the top
+        let pixel = &light.pixels[6];
+        assert_eq!((pixel.red, pixel.green, pixel.blue), (2.0, 1.0, 0.25));
+
+        assert!(matches!(
+            EnvironmentLight::<f64>::load(&dir.join("missing.exr")),
+            Err(EnvironmentLoadError::OpenError)
+        ));
+        std::fs::write(dir.join("broken.exr"), "not an image").unwrap();
+        assert!(matches!(
+            EnvironmentLight::<f64>::load(&dir.join("broken.exr")),
+            Err(EnvironmentLoadError::DecodeError)
+        ));
+    }
+
+    #[test]
+    fn empty_images_are_rejected() {
+        let dir = TempDir::new("empty_environment");
+        let path = dir.join("empty.hdr");
+        HDREncoder::new(File::create(&path).unwrap())
+            .encode(&[], 0, 0)
+            .unwrap();
+        assert!(matches!(
+            EnvironmentLight::<f64>::load(&path),
+            Err(EnvironmentLoadError::DecodeError)
+        ));
+    }
+
+    #[test]
+    fn negative_pixels_are_never_picked() {
+        // the left half of the image, around +z, is slightly negative
+        let dark = Spec::new(-0.1, -0.2, -0.1);
+        let light = EnvironmentLight::new(2, 1, vec![dark, Spec::new(1.0, 1.0, 1.0)]);
+        for i in 0..20 {
+            let u1 = (f64::from(i) + 0.5) / 20.0;
+            let sample = light.sample_li(&interaction(), u1, 0.5).unwrap();
+            assert!(sample.pdf > 0.0);
+            assert!(sample.radiance.red > 0.0);
+            assert!(sample.shadow_ray.direction.z <= 0.0);
+        }
+        assert_eq!(light.pdf_li(&interaction(), &Vector3::z()), 0.0);
+    }
+
+    proptest! {
+        #[test]
+        fn pdf_matches_sample(u1 in 0f64..1.0, u2 in 0f64..1.0) {
+            let light = sun();
+            if let Some(sample) = light.sample_li(&interaction(), u1, u2) {
+                let direction = sample.shadow_ray.direction.into_inner();
+                let pdf = light.pdf_li(&interaction(), &direction);
+                prop_assert!((pdf - sample.pdf).abs() < 1e-6 * sample.pdf.max(1.0));
+                let seen = light.le(&Ray::new_normalize(Point3::origin(), direction)).unwrap();
+                prop_assert!((seen.red - sample.radiance.red).abs() < 1e-10);
+            }
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use std::f64::consts::PI;
+use std::fmt::Debug;
+
+/// Pixel reconstruction filter.
+///
+/// Each sample is added to the pixels around it, weighted by the filter evaluated at the
+/// sample's offset from each pixel centre. Offsets are measured in pixels.
+pub trait Filter: Debug + Send + Sync {
+    /// Distance from the pixel centre, along each axis, beyond which the filter is zero.
+    fn radius(&self) -> f64;
+
+    /// Weight of a sample offset by `(x, y)` from the pixel centre.
+    fn evaluate(&self, x: f64, y: f64) -> f64;
+}
+
+/// Weights every sample within the radius equally.
+#[derive(Debug, Clone)]
+pub struct BoxFilter {
+    radius: f64,
+}
+
+impl BoxFilter {
+    pub fn new(radius: f64) -> BoxFilter {
+        BoxFilter { radius }
+    }
+}
+
+impl Default for BoxFilter {
+    /// A filter covering exactly one pixel.
+    fn default() -> BoxFilter {
+        BoxFilter::new(0.5)
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        if x.abs() <= self.radius && y.abs() <= self.radius {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Weights fall off linearly from the centre, reaching zero at the radius.
+#[derive(Debug, Clone)]
+pub struct TentFilter {
+    radius: f64,
+}
+
+impl TentFilter {
+    pub fn new(radius: f64) -> TentFilter {
+        TentFilter { radius }
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        (self.radius - x.abs()).max(0.0) * (self.radius - y.abs()).max(0.0)
+    }
+}
+
+/// Gaussian falloff, shifted down so that it reaches zero at the radius.
+/// Larger `alpha` gives a narrower, sharper filter.
+#[derive(Debug, Clone)]
+pub struct GaussianFilter {
+    radius: f64,
+    alpha: f64,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: f64, alpha: f64) -> GaussianFilter {
+        GaussianFilter { radius, alpha }
+    }
+
+    fn gaussian(&self, d: f64) -> f64 {
+        let edge = (-self.alpha * self.radius * self.radius).exp();
+        ((-self.alpha * d * d).exp() - edge).max(0.0)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.gaussian(x) * self.gaussian(y)
+    }
+}
+
+/// Mitchell-Netravali cubic filter.
+///
+/// `b` and `c` trade blurring against ringing. The authors recommend values with `b + 2c = 1`,
+/// such as `b = c = 1/3`.
+#[derive(Debug, Clone)]
+pub struct MitchellFilter {
+    radius: f64,
+    b: f64,
+    c: f64,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: f64, b: f64, c: f64) -> MitchellFilter {
+        MitchellFilter { radius, b, c }
+    }
+
+    // The cubic is defined on [-2, 2], so `x` is first scaled to that range.
+    fn mitchell_1d(&self, x: f64) -> f64 {
+        let x = (2.0 * x / self.radius).abs();
+        let (b, c) = (self.b, self.c);
+        let value = if x > 2.0 {
+            0.0
+        } else if x > 1.0 {
+            (-b - 6.0 * c) * x * x * x
+                + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c)
+        } else {
+            (12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b)
+        };
+        value / 6.0
+    }
+}
+
+impl Default for MitchellFilter {
+    fn default() -> MitchellFilter {
+        MitchellFilter::new(2.0, 1.0 / 3.0, 1.0 / 3.0)
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.mitchell_1d(x) * self.mitchell_1d(y)
+    }
+}
+
+/// Windowed sinc filter. `tau` is the number of sinc lobes inside the window.
+#[derive(Debug, Clone)]
+pub struct LanczosFilter {
+    radius: f64,
+    tau: f64,
+}
+
+impl LanczosFilter {
+    pub fn new(radius: f64, tau: f64) -> LanczosFilter {
+        LanczosFilter { radius, tau }
+    }
+
+    fn windowed_sinc(&self, x: f64) -> f64 {
+        let x = x.abs();
+        if x > self.radius {
+            return 0.0;
+        }
+        let window = sinc(x / self.radius);
+        sinc(x * self.tau / self.radius) * window
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.windowed_sinc(x) * self.windowed_sinc(y)
+    }
+}
+
+/// Normalized sinc, `sin(PI x) / (PI x)`.
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn filters() -> Vec<Box<dyn Filter>> {
+        vec![
+            Box::new(BoxFilter::default()),
+            Box::new(TentFilter::new(1.0)),
+            Box::new(GaussianFilter::new(1.5, 2.0)),
+            Box::new(MitchellFilter::default()),
+            Box::new(LanczosFilter::new(2.0, 3.0)),
+        ]
+    }
+
+    #[test]
+    fn centre_has_positive_weight() {
+        for f in filters() {
+            assert!(f.evaluate(0.0, 0.0) > 0.0, "{:?}", f);
+        }
+    }
+
+    #[test]
+    fn sinc_at_integers() {
+        assert_eq!(sinc(0.0), 1.0);
+        assert!(sinc(1.0).abs() < 1e-10);
+        assert!(sinc(2.0).abs() < 1e-10);
+    }
+
+    proptest! {
+        #[test]
+        fn zero_outside_radius(x in -10f64..10.0, y in -10f64..10.0) {
+            for f in filters() {
+                if x.abs() > f.radius() || y.abs() > f.radius() {
+                    prop_assert_eq!(f.evaluate(x, y), 0.0, "{:?}", f);
+                }
+            }
+        }
+
+        #[test]
+        fn symmetric(x in -3f64..3.0, y in -3f64..3.0) {
+            for f in filters() {
+                let w = f.evaluate(x, y);
+                prop_assert!((w - f.evaluate(-x, y)).abs() < 1e-10, "{:?}", f);
+                prop_assert!((w - f.evaluate(x, -y)).abs() < 1e-10, "{:?}", f);
+            }
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use super::sampling::coordinate_system;
+use na::{RealField, Scalar, Vector3};
+
+/// Orthonormal basis, used to move directions into the local shading space of a surface.
+///
+/// In local space the surface normal is +Z, so the cosine of the angle between a unit direction
+/// and the normal is just its z component.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame<T: Scalar> {
+    /// Local +X.
+    pub s: Vector3<T>,
+    /// Local +Y.
+    pub t: Vector3<T>,
+    /// Local +Z.
+    pub n: Vector3<T>,
+}
+
+impl<T: RealField> Frame<T> {
+    /// Frame with the unit vector `n` as +Z, and any perpendicular axes.
+    pub fn from_normal(n: Vector3<T>) -> Frame<T> {
+        let (s, t) = coordinate_system(&n);
+        Frame { s, t, n }
+    }
+
+    /// Frame with the unit vector `n` as +Z, and +X as close as possible to `tangent`.
+    /// This lines the frame up with the surface coordinates when `tangent` is a derivative of
+    /// the surface. Falls back to `from_normal` when `tangent` is parallel to `n`.
+    pub fn from_normal_tangent(n: Vector3<T>, tangent: &Vector3<T>) -> Frame<T> {
+        match (tangent - n * n.dot(tangent)).try_normalize(T::default_epsilon()) {
+            Some(s) => Frame {
+                t: n.cross(&s),
+                s,
+                n,
+            },
+            None => Frame::from_normal(n),
+        }
+    }
+
+    /// Converts a direction in world space to local space.
+    pub fn to_local(&self, v: &Vector3<T>) -> Vector3<T> {
+        Vector3::new(v.dot(&self.s), v.dot(&self.t), v.dot(&self.n))
+    }
+
+    /// Converts a direction in local space to world space.
+    pub fn to_world(&self, v: &Vector3<T>) -> Vector3<T> {
+        self.s * v.x + self.t * v.y + self.n * v.z
+    }
+}
+
+/// Cosine of the angle between the local direction `w` and the normal.
+pub fn cos_theta<T: RealField>(w: &Vector3<T>) -> T {
+    w.z
+}
+
+/// True if the local directions `a` and `b` are on the same side of the surface.
+pub fn same_hemisphere<T: RealField>(a: &Vector3<T>, b: &Vector3<T>) -> bool {
+    a.z * b.z > T::zero()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::ray::tests::st_vec3;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn round_trip(n in st_vec3(-1f64..1.0),
+                      tangent in st_vec3(-1f64..1.0),
+                      v in st_vec3(-1f64..1.0)) {
+            prop_assume!(n.magnitude() > 0.001);
+            let frame = Frame::from_normal_tangent(n.normalize(), &tangent);
+            prop_assert!((frame.to_world(&frame.to_local(&v)) - v).magnitude() < 1e-10);
+            prop_assert!((frame.to_local(&frame.n) - Vector3::z()).magnitude() < 1e-10);
+            // the axes are orthonormal and right handed
+            prop_assert!((frame.s.cross(&frame.t) - frame.n).magnitude() < 1e-10);
+            prop_assert!((frame.s.magnitude() - 1.0).abs() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn tangent_becomes_x() {
+        let frame = Frame::from_normal_tangent(Vector3::z(), &Vector3::new(2.0, 0.0, 1.0));
+        assert!((frame.s - Vector3::x()).magnitude() < 1e-10);
+        assert!((frame.t - Vector3::y()).magnitude() < 1e-10);
+        // a tangent along the normal gives some other frame
+        let fallback: Frame<f64> = Frame::from_normal_tangent(Vector3::z(), &Vector3::z());
+        assert!(fallback.s.dot(&Vector3::z()).abs() < 1e-10);
+    }
+}
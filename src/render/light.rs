@@ -1,8 +1,648 @@
-use na::{Point3, Scalar};
+use na::{Point2, Point3, Scalar, Vector3};
 
+use super::ray::Ray;
+use super::sampling::Distribution2D;
+use super::shape::SurfaceInteraction;
 use super::spectrum::*;
+use crate::number::Float;
 
+/// Source of light that isn't a surface in the scene. Emissive surfaces are handled by the
+/// scene itself.
+///
+/// Lights are shared between render threads, so they must be `Send` and `Sync`.
+pub trait Light: Send + Sync {
+    type NumTy: Scalar;
+
+    /// Picks a direction light arrives at the point of `interaction` from, using the uniform
+    /// random numbers `u1` and `u2` in `[0, 1)`. Returns None if no light from here reaches the
+    /// point.
+    fn sample_li(
+        &self,
+        interaction: &SurfaceInteraction<Self::NumTy>,
+        u1: Self::NumTy,
+        u2: Self::NumTy,
+    ) -> Option<LightSample<Self::NumTy>>;
+
+    /// Probability density, with respect to solid angle, of `sample_li` picking the direction
+    /// `wi` from the point of `interaction`. Lights that shine from a single point or direction
+    /// can't be found by picking directions at random, so they always return 0.
+    fn pdf_li(
+        &self,
+        interaction: &SurfaceInteraction<Self::NumTy>,
+        wi: &Vector3<Self::NumTy>,
+    ) -> Self::NumTy;
+
+    /// Radiance arriving along a ray that leaves the scene without hitting anything. Only
+    /// lights surrounding the whole scene, like environment maps, give any.
+    fn le(&self, _ray: &Ray<Self::NumTy>) -> Option<Spec<Self::NumTy>> {
+        None
+    }
+}
+
+/// Light arriving at a point from one direction, picked by `Light::sample_li`.
+pub struct LightSample<T: Scalar> {
+    /// Radiance arriving along the shadow ray.
+    pub radiance: Spec<T>,
+    /// Ray from the point towards the light, ending at the light. The light is only seen if
+    /// nothing hits this ray.
+    pub shadow_ray: Ray<T>,
+    /// Probability density of picking this direction, with respect to solid angle. Lights that
+    /// shine from a single point or direction use 1.
+    pub pdf: T,
+}
+
+/// Light shining equally in every direction from a single point. It falls off with the square
+/// of the distance.
 pub struct PointLight<T: Scalar> {
     pub position: Point3<T>,
-    pub color: Spec<T>,
+    /// Power per unit solid angle given off in every direction.
+    pub intensity: Spec<T>,
+}
+
+impl<T: Scalar> PointLight<T> {
+    /// Light at `position` with radiant `intensity`, so a surface facing it `d` units away
+    /// receives an irradiance of `intensity / d^2`.
+    pub fn new(position: Point3<T>, intensity: Spec<T>) -> PointLight<T> {
+        PointLight {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl<N: Float> Light for PointLight<N> {
+    type NumTy = N;
+
+    fn sample_li(
+        &self,
+        interaction: &SurfaceInteraction<N>,
+        _u1: N,
+        _u2: N,
+    ) -> Option<LightSample<N>> {
+        let distance_sq = (self.position - interaction.point).norm_squared();
+        if distance_sq <= N::zero() {
+            return None;
+        }
+        Some(LightSample {
+            radiance: self.intensity.clone() * (N::one() / distance_sq),
+            shadow_ray: interaction.spawn_ray_to(&self.position),
+            pdf: N::one(),
+        })
+    }
+
+    fn pdf_li(&self, _interaction: &SurfaceInteraction<N>, _wi: &Vector3<N>) -> N {
+        N::zero()
+    }
+}
+
+/// Light arriving from a single direction everywhere in the scene, like sunlight. It does not
+/// fall off with distance.
+pub struct DirectionalLight<T: Scalar> {
+    /// Unit direction the light travels in.
+    pub direction: Vector3<T>,
+    /// Power per unit area arriving on a surface facing the light.
+    pub irradiance: Spec<T>,
+}
+
+impl<N: Float> DirectionalLight<N> {
+    /// Light travelling in `direction`, which doesn't have to be a unit vector.
+    pub fn new(direction: Vector3<N>, irradiance: Spec<N>) -> DirectionalLight<N> {
+        DirectionalLight {
+            direction: direction.normalize(),
+            irradiance,
+        }
+    }
+}
+
+impl<N: Float> Light for DirectionalLight<N> {
+    type NumTy = N;
+
+    fn sample_li(
+        &self,
+        interaction: &SurfaceInteraction<N>,
+        _u1: N,
+        _u2: N,
+    ) -> Option<LightSample<N>> {
+        Some(LightSample {
+            radiance: self.irradiance.clone(),
+            shadow_ray: interaction.spawn_ray(-self.direction),
+            pdf: N::one(),
+        })
+    }
+
+    fn pdf_li(&self, _interaction: &SurfaceInteraction<N>, _wi: &Vector3<N>) -> N {
+        N::zero()
+    }
+}
+
+/// Point light that only shines in a cone, like a stage spotlight.
+///
+/// The light is at full intensity within `falloff_start` radians of the centre of the cone,
+/// then fades smoothly to nothing at the edge of the cone, `cone_angle` radians from the centre.
+pub struct SpotLight<T: Scalar> {
+    pub position: Point3<T>,
+    /// Unit direction of the centre of the cone.
+    pub direction: Vector3<T>,
+    /// Power per unit solid angle given off at the centre of the cone.
+    pub intensity: Spec<T>,
+    cos_cone_angle: T,
+    cos_falloff_start: T,
+}
+
+impl<N: Float> SpotLight<N> {
+    /// Spotlight at `position`, pointing at `target`.
+    pub fn new(
+        position: Point3<N>,
+        target: Point3<N>,
+        intensity: Spec<N>,
+        cone_angle: N,
+        falloff_start: N,
+    ) -> SpotLight<N> {
+        SpotLight {
+            position,
+            direction: (target - position).normalize(),
+            intensity,
+            cos_cone_angle: cone_angle.cos(),
+            cos_falloff_start: falloff_start.min(cone_angle).cos(),
+        }
+    }
+
+    /// Fraction of the full intensity given off in the unit direction `w`.
+    fn falloff(&self, w: &Vector3<N>) -> N {
+        let cos_theta = w.dot(&self.direction);
+        if cos_theta < self.cos_cone_angle {
+            return N::zero();
+        }
+        if cos_theta >= self.cos_falloff_start {
+            return N::one();
+        }
+        let delta =
+            (cos_theta - self.cos_cone_angle) / (self.cos_falloff_start - self.cos_cone_angle);
+        (delta * delta) * (delta * delta)
+    }
+}
+
+impl<N: Float> Light for SpotLight<N> {
+    type NumTy = N;
+
+    fn sample_li(
+        &self,
+        interaction: &SurfaceInteraction<N>,
+        _u1: N,
+        _u2: N,
+    ) -> Option<LightSample<N>> {
+        let to_point = interaction.point - self.position;
+        let distance_sq = to_point.norm_squared();
+        if distance_sq <= N::zero() {
+            return None;
+        }
+        let falloff = self.falloff(&(to_point / distance_sq.sqrt()));
+        if falloff <= N::zero() {
+            return None;
+        }
+        Some(LightSample {
+            radiance: self.intensity.clone() * (falloff / distance_sq),
+            shadow_ray: interaction.spawn_ray_to(&self.position),
+            pdf: N::one(),
+        })
+    }
+
+    fn pdf_li(&self, _interaction: &SurfaceInteraction<N>, _wi: &Vector3<N>) -> N {
+        N::zero()
+    }
+}
+
+/// Clear daytime sky, using the analytic model of Preetham, Shirley and Smits.
+///
+/// Up is +y, and azimuths are measured around it from +x towards +z. Light only comes from
+/// above the horizon. The sun itself is not part of the sky, and is added with the light from
+/// `sun`. Directions are picked in proportion to the brightness of the sky, read from a table
+/// made up front, so the bright sky around the sun is found quickly.
+pub struct PreethamSky<T: Scalar> {
+    /// Unit direction towards the sun.
+    sun_direction: Vector3<T>,
+    turbidity: T,
+    /// Luminance and chromaticity straight up, as `[Y, x, y]`.
+    zenith: [T; 3],
+    /// Coefficients of the Perez distribution for `Y`, `x` and `y`.
+    perez: [[T; 5]; 3],
+    /// Scale applied to the luminance of the model, which is in kilocandelas per square metre.
+    intensity: T,
+    /// Brightness of the sky over a grid of directions, laid out like an `EnvironmentLight`
+    /// image of the sky above the horizon.
+    distribution: Distribution2D<T>,
+}
+
+/// Columns of the table sky directions are picked from, around the horizon.
+const SKY_TABLE_WIDTH: usize = 128;
+/// Rows of the table sky directions are picked from, from straight up to the horizon.
+const SKY_TABLE_HEIGHT: usize = 32;
+
+impl<N: Float> PreethamSky<N> {
+    /// Sky with the sun `elevation` radians above the horizon, at `azimuth` radians around it.
+    /// `turbidity` is the haziness of the air, from about 2 for a very clear day to 10 for a
+    /// hazy one.
+    ///
+    /// The default intensity lights a white surface in the midday sun to about 1.
+    pub fn new(elevation: N, azimuth: N, turbidity: N) -> PreethamSky<N> {
+        let c = |v: f32| N::from(v);
+        let t = turbidity;
+        let theta = N::frac_pi_2() - elevation.max(N::zero());
+        let sun_direction = Vector3::new(
+            elevation.cos() * azimuth.cos(),
+            elevation.sin(),
+            elevation.cos() * azimuth.sin(),
+        );
+
+        let chi = (c(4.0 / 9.0) - t / c(120.0)) * (N::pi() - theta - theta);
+        let zenith_y = (c(4.0453) * t - c(4.9710)) * chi.tan() - c(0.2155) * t + c(2.4192);
+        let chromaticity = |m: [[f32; 4]; 3]| {
+            let powers = [theta * theta * theta, theta * theta, theta, N::one()];
+            let row = |r: [f32; 4]| (0..4).fold(N::zero(), |sum, i| sum + c(r[i]) * powers[i]);
+            t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_chroma_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let coefficients = |m: [[f32; 2]; 5]| {
+            let mut out = [N::zero(); 5];
+            for (o, [slope, offset]) in out.iter_mut().zip(m.iter()) {
+                *o = c(*slope) * t + c(*offset);
+            }
+            out
+        };
+        let perez = [
+            coefficients([
+                [0.1787, -1.4630],
+                [-0.3554, 0.4275],
+                [-0.0227, 5.3251],
+                [0.1206, -2.5771],
+                [-0.0670, 0.3703],
+            ]),
+            coefficients([
+                [-0.0193, -0.2592],
+                [-0.0665, 0.0008],
+                [-0.0004, 0.2125],
+                [-0.0641, -0.8989],
+                [-0.0033, 0.0452],
+            ]),
+            coefficients([
+                [-0.0167, -0.2608],
+                [-0.0950, 0.0092],
+                [-0.0079, 0.2102],
+                [-0.0441, -1.6537],
+                [-0.0109, 0.0529],
+            ]),
+        ];
+
+        let mut sky = PreethamSky {
+            sun_direction,
+            turbidity,
+            zenith: [zenith_y, zenith_x, zenith_chroma_y],
+            perez,
+            intensity: c(0.02),
+            // filled in below, as the table needs the rest of the model
+            distribution: Distribution2D::new(&[N::one()], 1, 1),
+        };
+        sky.distribution = sky.tabulate();
+        sky
+    }
+
+    /// Scales the brightness of both the sky and the sun.
+    pub fn with_intensity(self, intensity: N) -> PreethamSky<N> {
+        PreethamSky { intensity, ..self }
+    }
+
+    /// The sun matching the sky, shining from the sun's direction with the colour and brightness
+    /// left after passing through the atmosphere.
+    pub fn sun(&self) -> DirectionalLight<N> {
+        let c = |v: f32| N::from(v);
+        let cos_theta = self.sun_direction.y.max(N::zero());
+        let theta_degrees = cos_theta.acos() * c(180.0) / N::pi();
+        // relative length of the sun's path through the air, compared to straight up
+        let air_mass = N::one()
+            / (cos_theta + c(0.15) * (c(93.885) - theta_degrees).max(c(0.01)).powf(c(-1.253)));
+        let beta = c(0.04608) * self.turbidity - c(0.04586);
+        // Rayleigh and aerosol scattering at the wavelengths of red, green and blue, in
+        // micrometres. The light outside the atmosphere is 128 kilolux.
+        let transmitted = |wavelength: f32| {
+            let lambda = c(wavelength);
+            let rayleigh = (-c(0.008735) * lambda.powf(c(-4.08)) * air_mass).exp();
+            let aerosol = (-beta * lambda.powf(c(-1.3)) * air_mass).exp();
+            c(128.0) * rayleigh * aerosol * self.intensity
+        };
+        let irradiance = if self.sun_direction.y > N::zero() {
+            Spec::new(transmitted(0.68), transmitted(0.55), transmitted(0.44))
+        } else {
+            Spec::new(N::zero(), N::zero(), N::zero())
+        };
+        DirectionalLight::new(-self.sun_direction, irradiance)
+    }
+
+    /// Distribution of the brightness of the sky, for picking directions to sample. Cells near
+    /// the zenith cover less of the sky, so they are picked less often.
+    fn tabulate(&self) -> Distribution2D<N> {
+        let (width, height) = (SKY_TABLE_WIDTH, SKY_TABLE_HEIGHT);
+        let mut weights = Vec::with_capacity(width * height);
+        for row in 0..height {
+            for column in 0..width {
+                let v = (N::from(row as f32) + N::from(0.5)) / N::from(height as f32);
+                let u = (N::from(column as f32) + N::from(0.5)) / N::from(width as f32);
+                let (w, sin_theta) = sky_direction(&Point2::new(u, v));
+                weights.push(self.sky_radiance(&w).luminance().max(N::zero()) * sin_theta);
+            }
+        }
+        Distribution2D::new(&weights, width, height)
+    }
+
+    /// Radiance of the sky in the unit direction `w`, which must be above the horizon.
+    fn sky_radiance(&self, w: &Vector3<N>) -> Spec<N> {
+        let c = |v: f32| N::from(v);
+        // keep away from the horizon, where the model divides by zero
+        let cos_theta = w.y.max(c(0.001));
+        let cos_gamma = w.dot(&self.sun_direction).max(-N::one()).min(N::one());
+        let sun_cos_theta = self.sun_direction.y.max(-N::one()).min(N::one());
+        let perez = |k: &[N; 5], cos_theta: N, cos_gamma: N| {
+            let gamma = cos_gamma.acos();
+            (N::one() + k[0] * (k[1] / cos_theta).exp())
+                * (N::one() + k[2] * (k[3] * gamma).exp() + k[4] * cos_gamma * cos_gamma)
+        };
+        // each value is its zenith value scaled by the distribution, relative to the zenith
+        let value = |i: usize| {
+            let k = &self.perez[i];
+            self.zenith[i] * perez(k, cos_theta, cos_gamma) / perez(k, N::one(), sun_cos_theta)
+        };
+        let (luminance, x, y) = (value(0) * self.intensity, value(1), value(2));
+
+        // xyY to XYZ to linear sRGB
+        let big_x = x * luminance / y;
+        let big_z = (N::one() - x - y) * luminance / y;
+        let rgb =
+            |m: [f32; 3]| (c(m[0]) * big_x + c(m[1]) * luminance + c(m[2]) * big_z).max(N::zero());
+        Spec::new(
+            rgb([3.2406, -1.5372, -0.4986]),
+            rgb([-0.9689, 1.8758, 0.0415]),
+            rgb([0.0557, -0.2040, 1.0570]),
+        )
+    }
+}
+
+impl<N: Float> Light for PreethamSky<N> {
+    type NumTy = N;
+
+    fn sample_li(
+        &self,
+        interaction: &SurfaceInteraction<N>,
+        u1: N,
+        u2: N,
+    ) -> Option<LightSample<N>> {
+        let (uv, map_pdf) = self.distribution.sample(u1, u2);
+        let (wi, sin_theta) = sky_direction(&uv);
+        let pdf = sky_solid_angle_pdf(map_pdf, sin_theta);
+        if pdf <= N::zero() || wi.y <= N::zero() {
+            return None;
+        }
+        Some(LightSample {
+            radiance: self.sky_radiance(&wi),
+            shadow_ray: interaction.spawn_ray(wi),
+            pdf,
+        })
+    }
+
+    fn pdf_li(&self, _interaction: &SurfaceInteraction<N>, wi: &Vector3<N>) -> N {
+        let w = wi.normalize();
+        if w.y <= N::zero() {
+            return N::zero();
+        }
+        let cos_theta = w.y.min(N::one());
+        let sin_theta = (N::one() - cos_theta * cos_theta).max(N::zero()).sqrt();
+        let mut phi = w.z.atan2(w.x);
+        if phi < N::zero() {
+            phi += N::two_pi();
+        }
+        let uv = Point2::new(phi / N::two_pi(), cos_theta.acos() / N::frac_pi_2());
+        sky_solid_angle_pdf(self.distribution.pdf(&uv), sin_theta)
+    }
+
+    fn le(&self, ray: &Ray<N>) -> Option<Spec<N>> {
+        if ray.direction.y <= N::zero() {
+            return None;
+        }
+        Some(self.sky_radiance(&ray.direction))
+    }
+}
+
+/// Unit direction above the horizon at the point `uv` of the sky's table, and the sine of its
+/// angle from straight up. `u` goes around from +x towards +z, and `v` down from +y.
+fn sky_direction<N: Float>(uv: &Point2<N>) -> (Vector3<N>, N) {
+    let theta = uv.y * N::frac_pi_2();
+    let phi = uv.x * N::two_pi();
+    let sin_theta = theta.sin();
+    let w = Vector3::new(sin_theta * phi.cos(), theta.cos(), sin_theta * phi.sin());
+    (w, sin_theta)
+}
+
+/// Converts a density over the sky's table to a density over directions.
+fn sky_solid_angle_pdf<N: Float>(map_pdf: N, sin_theta: N) -> N {
+    if sin_theta <= N::zero() {
+        return N::zero();
+    }
+    // the table spans half a turn down and a whole turn around
+    map_pdf / (N::frac_pi_2() * N::two_pi() * sin_theta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::abs_diff_eq;
+    use std::f64::consts::PI;
+
+    /// Interaction on the plane z = 0 at `point`, facing +z.
+    fn interaction_at(point: Point3<f64>) -> SurfaceInteraction<f64> {
+        SurfaceInteraction::new(
+            0.0,
+            point,
+            Vector3::zeros(),
+            Vector3::z(),
+            Point2::origin(),
+            Vector3::x(),
+            Vector3::y(),
+        )
+    }
+
+    fn white(value: f64) -> Spec<f64> {
+        Spec::new(value, value, value)
+    }
+
+    #[test]
+    fn lights_are_lights() {
+        fn is_light<L: Light>() {}
+        is_light::<PointLight<f64>>();
+        is_light::<DirectionalLight<f32>>();
+        is_light::<SpotLight<f64>>();
+        is_light::<PreethamSky<f32>>();
+    }
+
+    fn sky_towards(sky: &PreethamSky<f64>, direction: Vector3<f64>) -> Option<Spec<f64>> {
+        sky.le(&Ray::new_normalize(Point3::origin(), direction))
+    }
+
+    #[test]
+    fn sky_is_brightest_around_sun() {
+        let sky = PreethamSky::new(0.5, 0.0, 3.0);
+        let near_sun = sky_towards(&sky, Vector3::new(0.5f64.cos(), 0.55f64.sin(), 0.0)).unwrap();
+        let away = sky_towards(&sky, Vector3::new(-1.0, 0.5, 0.0)).unwrap();
+        assert!(near_sun.green > away.green);
+        // a clear sky is blue away from the sun
+        assert!(away.blue > away.red);
+        assert!(sky_towards(&sky, -Vector3::y()).is_none());
+    }
+
+    #[test]
+    fn sky_zenith_matches_model() {
+        let sky = PreethamSky::new(1.0, 2.0, 2.5);
+        let zenith = sky_towards(&sky, Vector3::y()).unwrap();
+        // Y of linear sRGB
+        let luminance = 0.2126 * zenith.red + 0.7152 * zenith.green + 0.0722 * zenith.blue;
+        assert!((luminance - sky.zenith[0] * sky.intensity).abs() < 1e-3 * luminance);
+    }
+
+    #[test]
+    fn low_sun_is_redder() {
+        let high = PreethamSky::new(1.2, 0.0, 3.0).sun();
+        let low = PreethamSky::new(0.05, 0.0, 3.0).sun();
+        assert!(high.irradiance.green > low.irradiance.green);
+        let redness = |sun: &DirectionalLight<f64>| sun.irradiance.red / sun.irradiance.blue;
+        assert!(redness(&low) > redness(&high));
+        // the sun's light travels away from the sun
+        assert!(high.direction.y < 0.0);
+
+        let set = PreethamSky::new(-0.1, 0.0, 3.0).sun();
+        assert_eq!(set.irradiance.red, 0.0);
+    }
+
+    #[test]
+    fn sky_pdf_matches_sample() {
+        let sky = PreethamSky::new(0.7, 1.0, 4.0);
+        let interaction = interaction_at(Point3::origin());
+        for &(u1, u2) in &[(0.3, 0.8), (0.01, 0.5), (0.5, 0.99), (0.9, 0.02)] {
+            let sample = sky.sample_li(&interaction, u1, u2).unwrap();
+            let wi = sample.shadow_ray.direction.into_inner();
+            assert!(wi.y > 0.0);
+            assert!(abs_diff_eq!(
+                sky.pdf_li(&interaction, &wi),
+                sample.pdf,
+                epsilon = 1e-6 * sample.pdf
+            ));
+        }
+        assert_eq!(sky.pdf_li(&interaction, &-Vector3::y()), 0.0);
+    }
+
+    /// Irradiance from the sky on a surface facing `normal`, by the midpoint rule over a fine
+    /// grid of directions above the horizon.
+    fn sky_irradiance(sky: &PreethamSky<f64>, normal: &Vector3<f64>) -> f64 {
+        let steps = 400;
+        let step = 1.0 / f64::from(steps);
+        let mut total = 0.0;
+        for i in 0..steps {
+            for j in 0..steps {
+                let uv = Point2::new((f64::from(i) + 0.5) * step, (f64::from(j) + 0.5) * step);
+                let (w, sin_theta) = sky_direction(&uv);
+                let solid_angle = sin_theta * (PI / 2.0 * step) * (2.0 * PI * step);
+                let cos = w.dot(normal).max(0.0);
+                total += sky.sky_radiance(&w).green * cos * solid_angle;
+            }
+        }
+        total
+    }
+
+    #[test]
+    fn sky_samples_favour_sun() {
+        let sky = PreethamSky::new(0.4, 2.0, 3.0);
+        // a wall facing away from the sun, which sees half the sky
+        let normal = Vector3::new(-(2.0f64.cos()), 0.0, -(2.0f64.sin()));
+        let interaction = interaction_at(Point3::origin());
+        let n = 64;
+        let mut estimate = 0.0;
+        let mut towards_sun = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let u1 = (f64::from(i) + 0.5) / f64::from(n);
+                let u2 = (f64::from(j) + 0.5) / f64::from(n);
+                let sample = sky.sample_li(&interaction, u1, u2).unwrap();
+                let wi = sample.shadow_ray.direction.into_inner();
+                let cos = wi.dot(&normal).max(0.0);
+                estimate += sample.radiance.green * cos / sample.pdf;
+                towards_sun += wi.dot(&sky.sun_direction);
+            }
+        }
+        let count = f64::from(n * n);
+        let expected = sky_irradiance(&sky, &normal);
+        assert!(
+            (estimate / count - expected).abs() < 0.01 * expected,
+            "{} != {}",
+            estimate / count,
+            expected
+        );
+        // directions picked by height alone would average 2/3 of the sun's height
+        assert!(towards_sun / count > sky.sun_direction.y * 2.0 / 3.0 + 0.1);
+    }
+
+    #[test]
+    fn point_light_falls_off_with_distance() {
+        let light = PointLight::new(Point3::new(0.0, 0.0, 2.0), white(8.0));
+        let sample = light
+            .sample_li(&interaction_at(Point3::origin()), 0.5, 0.5)
+            .unwrap();
+        assert!(abs_diff_eq!(sample.radiance.red, 2.0, epsilon = 1e-10));
+        assert!((sample.shadow_ray.direction.into_inner() - Vector3::z()).magnitude() < 1e-10);
+        // the shadow ray stops at the light
+        assert!(abs_diff_eq!(sample.shadow_ray.tmax, 2.0, epsilon = 1e-6));
+        assert_eq!(
+            light.pdf_li(&interaction_at(Point3::origin()), &Vector3::z()),
+            0.0
+        );
+    }
+
+    #[test]
+    fn directional_light_is_same_everywhere() {
+        let light = DirectionalLight::new(Vector3::new(0.0, -1.0, -1.0), white(3.0));
+        for point in &[Point3::origin(), Point3::new(100.0, -50.0, 0.0)] {
+            let sample = light.sample_li(&interaction_at(*point), 0.5, 0.5).unwrap();
+            assert!(abs_diff_eq!(sample.radiance.red, 3.0, epsilon = 1e-10));
+            let towards_light = Vector3::new(0.0, 1.0, 1.0).normalize();
+            assert!((sample.shadow_ray.direction.into_inner() - towards_light).magnitude() < 1e-10);
+            assert_eq!(sample.shadow_ray.tmax, f64::MAX);
+        }
+    }
+
+    #[test]
+    fn spot_light_fades_at_cone_edge() {
+        let light = SpotLight::new(
+            Point3::new(0.0, 0.0, 1.0),
+            Point3::origin(),
+            white(1.0),
+            0.5,
+            0.25,
+        );
+        let at = |x: f64| {
+            light
+                .sample_li(&interaction_at(Point3::new(x, 0.0, 0.0)), 0.5, 0.5)
+                .map_or(0.0, |sample| sample.radiance.red * (1.0 + x * x))
+        };
+        // full intensity inside the falloff start, none past the cone
+        assert!(abs_diff_eq!(at(0.0), 1.0, epsilon = 1e-10));
+        assert!(abs_diff_eq!(at(0.2f64.tan()), 1.0, epsilon = 1e-10));
+        assert_eq!(at(0.6f64.tan()), 0.0);
+        let edge = at(0.4f64.tan());
+        assert!(edge > 0.0 && edge < 1.0);
+    }
 }
@@ -1,37 +1,211 @@
+use super::frame::{cos_theta, same_hemisphere, Frame};
+use super::microfacet::{g, pdf_wh, MicrofacetDistribution};
+use super::sampling::cosine_sample_hemisphere;
 use super::spectrum::Spec;
 use super::shape::DiffGeom;
+use super::texture::Texture;
+use crate::number::Float;
 use na::{RealField, Scalar, Vector3};
+use std::marker::PhantomData;
+use std::ops::BitOr;
+use std::sync::Arc;
 
-// Defines the type of scattering functions used for lighting materials.
-// Defines how light is reflected by the surface for queried light and view vectors
+/// Scattering function of a surface, describing how light arriving from one direction is
+/// reflected or transmitted in another.
+///
+/// Directions are in the local shading space of the surface, where the normal is +Z, and point
+/// away from the surface. `wo` is the direction light leaves in, towards the viewer, and `wi`
+/// the direction it arrives from.
+///
+/// The defaults for `sample_f` and `pdf` pick directions with a cosine weighted density over the
+/// hemisphere of `wo`, which suits diffuse surfaces.
+#[allow(clippy::upper_case_acronyms)]
 pub trait BSDF {
-    type NumTy: Scalar;
-    /// Defines how light is reflected of the surface at an assumed point and orientation.
-    /// Vectors will be oriented to the reflection space. That means this can assume the normal is
-    /// unit vector in the positive Z direction <0, 0, 1>
-    ///
-    /// # Arguments
-    /// * `view`: the vector of the view, a narrow slice of the light emited from a point in that
-    /// direction.
-    /// * `light`: the vector of the incoming light.
-    ///
-    /// # Returns
-    /// Returns the ratio at which light will be reflected.
-    fn bsdf(
+    type NumTy: RealField;
+
+    /// Fraction of the light arriving from `wi` that leaves along `wo`, per unit solid angle.
+    /// This does not include the cosine of the angle of `wi` to the normal.
+    fn f(&self, wo: &Vector3<Self::NumTy>, wi: &Vector3<Self::NumTy>) -> Spec<Self::NumTy>;
+
+    /// Picks a direction `wi` for light to arrive from, from the uniform random numbers `u1` and
+    /// `u2` in `[0, 1)`. Returns None if no direction could be picked.
+    fn sample_f(
+        &self,
+        wo: &Vector3<Self::NumTy>,
+        u1: Self::NumTy,
+        u2: Self::NumTy,
+    ) -> Option<BsdfSample<Self::NumTy>> {
+        cosine_sample_f(self, wo, u1, u2)
+    }
+
+    /// Probability density, with respect to solid angle, of `sample_f` picking `wi`.
+    fn pdf(&self, wo: &Vector3<Self::NumTy>, wi: &Vector3<Self::NumTy>) -> Self::NumTy {
+        cosine_pdf(wo, wi)
+    }
+
+    /// Fraction of the light the surface stops. The rest passes straight through it unchanged,
+    /// as through a `Dissolved` surface, and so reaches past it along shadow rays too.
+    fn opacity(&self) -> Self::NumTy {
+        na::one()
+    }
+}
+
+/// Picks `wi` with a cosine weighted density over the hemisphere of `wo`. This is the default
+/// for `BSDF::sample_f`.
+pub fn cosine_sample_f<N: RealField, B: BSDF<NumTy = N> + ?Sized>(
+    bsdf: &B,
+    wo: &Vector3<N>,
+    u1: N,
+    u2: N,
+) -> Option<BsdfSample<N>> {
+    let mut wi = cosine_sample_hemisphere(u1, u2);
+    if cos_theta(wo) < N::zero() {
+        wi.z = -wi.z;
+    }
+    let pdf = cosine_pdf(wo, &wi);
+    if pdf <= N::zero() {
+        return None;
+    }
+    Some(BsdfSample {
+        f: bsdf.f(wo, &wi),
+        wi,
+        pdf,
+        flags: BsdfFlags::REFLECTION | BsdfFlags::DIFFUSE,
+    })
+}
+
+/// Density of `cosine_sample_f` picking `wi`.
+pub fn cosine_pdf<N: RealField>(wo: &Vector3<N>, wi: &Vector3<N>) -> N {
+    if same_hemisphere(wo, wi) {
+        cos_theta(wi).abs() / N::pi()
+    } else {
+        N::zero()
+    }
+}
+
+impl<B: BSDF + ?Sized> BSDF for Box<B> {
+    type NumTy = B::NumTy;
+
+    fn f(&self, wo: &Vector3<Self::NumTy>, wi: &Vector3<Self::NumTy>) -> Spec<Self::NumTy> {
+        (**self).f(wo, wi)
+    }
+
+    fn sample_f(
+        &self,
+        wo: &Vector3<Self::NumTy>,
+        u1: Self::NumTy,
+        u2: Self::NumTy,
+    ) -> Option<BsdfSample<Self::NumTy>> {
+        (**self).sample_f(wo, u1, u2)
+    }
+
+    fn pdf(&self, wo: &Vector3<Self::NumTy>, wi: &Vector3<Self::NumTy>) -> Self::NumTy {
+        (**self).pdf(wo, wi)
+    }
+
+    fn opacity(&self) -> Self::NumTy {
+        (**self).opacity()
+    }
+}
+
+impl<B: BSDF + ?Sized> BSDF for Arc<B> {
+    type NumTy = B::NumTy;
+
+    fn f(&self, wo: &Vector3<Self::NumTy>, wi: &Vector3<Self::NumTy>) -> Spec<Self::NumTy> {
+        (**self).f(wo, wi)
+    }
+
+    fn sample_f(
         &self,
-        view: &Vector3<Self::NumTy>,
-        normal: &Vector3<Self::NumTy>,
-        light: &Vector3<Self::NumTy>,
-    ) -> Spec<Self::NumTy>;
+        wo: &Vector3<Self::NumTy>,
+        u1: Self::NumTy,
+        u2: Self::NumTy,
+    ) -> Option<BsdfSample<Self::NumTy>> {
+        (**self).sample_f(wo, u1, u2)
+    }
+
+    fn pdf(&self, wo: &Vector3<Self::NumTy>, wi: &Vector3<Self::NumTy>) -> Self::NumTy {
+        (**self).pdf(wo, wi)
+    }
+
+    fn opacity(&self) -> Self::NumTy {
+        (**self).opacity()
+    }
+}
+
+/// BSDF of any type, which can be shared between materials and threads.
+pub type SharedBsdf<T> = Arc<dyn BSDF<NumTy = T> + Send + Sync>;
+
+/// Direction picked by `BSDF::sample_f`.
+#[derive(Clone)]
+pub struct BsdfSample<T: Scalar> {
+    /// Direction light arrives from, in local shading space.
+    pub wi: Vector3<T>,
+    /// Value of `BSDF::f` for the direction.
+    pub f: Spec<T>,
+    /// Probability density of picking the direction, with respect to solid angle.
+    pub pdf: T,
+    /// Kind of scattering the direction was picked from.
+    pub flags: BsdfFlags,
+}
+
+/// Set of kinds of scattering. Combine flags with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BsdfFlags(u8);
+
+impl BsdfFlags {
+    /// Light stays on the side of the surface it arrived on.
+    pub const REFLECTION: BsdfFlags = BsdfFlags(1);
+    /// Light passes through the surface.
+    pub const TRANSMISSION: BsdfFlags = BsdfFlags(1 << 1);
+    /// Light is scattered over the whole hemisphere.
+    pub const DIFFUSE: BsdfFlags = BsdfFlags(1 << 2);
+    /// Light is scattered around a preferred direction.
+    pub const GLOSSY: BsdfFlags = BsdfFlags(1 << 3);
+    /// Light is scattered in a single direction.
+    pub const SPECULAR: BsdfFlags = BsdfFlags(1 << 4);
+
+    /// True if every flag in `other` is set.
+    pub fn contains(self, other: BsdfFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for BsdfFlags {
+    type Output = BsdfFlags;
+
+    fn bitor(self, rhs: BsdfFlags) -> BsdfFlags {
+        BsdfFlags(self.0 | rhs.0)
+    }
 }
 
 pub trait Material {
     // the number type to use
     type NumTy: Scalar;
     // the BSDF to return
+    #[allow(non_camel_case_types)]
     type BSDF_fn;
 
     fn get_bsdf(&self, g: &DiffGeom<Self::NumTy>) -> Self::BSDF_fn;
+
+    /// Geometry to shade the surface with, which may add detail like bumps to the shading
+    /// normal. The result is passed to `get_bsdf`, and the BSDF is used in the shading frame of
+    /// its normal.
+    fn shading_geometry(&self, g: DiffGeom<Self::NumTy>) -> DiffGeom<Self::NumTy> {
+        g
+    }
+
+    /// Light given off by the surface, the same in every direction and from both sides.
+    /// Materials that don't glow return None.
+    fn emission(&self) -> Option<Spec<Self::NumTy>> {
+        None
+    }
+
+    /// Fraction of the light the surface stops, for materials that let the rest pass straight
+    /// through, see `BSDF::opacity`. Opaque materials return None.
+    fn opacity(&self) -> Option<Self::NumTy> {
+        None
+    }
 }
 
 #[derive(Clone)]
@@ -49,24 +223,645 @@ impl<T> Lambert<T> {
 
 impl<T: RealField> BSDF for Lambert<T> {
     type NumTy = T;
-    fn bsdf(
-        &self,
-        _view: &Vector3<Self::NumTy>,
-        normal: &Vector3<Self::NumTy>,
-        light: &Vector3<Self::NumTy>,
-    ) -> Spec<Self::NumTy> {
-        let cos = normal.dot(light) / (normal.magnitude() * light.magnitude());
-        self.color.clone() * cos.max(T::zero())
+
+    fn f(&self, wo: &Vector3<T>, wi: &Vector3<T>) -> Spec<T> {
+        if !same_hemisphere(wo, wi) {
+            return Spec::new(T::zero(), T::zero(), T::zero());
+        }
+        self.color.clone() * T::frac_1_pi()
+    }
+}
+
+/// Diffuse surface with a glossy highlight, using the energy conserving form of the Phong model.
+///
+/// The highlight is brightest when the view lies along the mirror reflection of the light, and
+/// narrows as `exponent` grows.
+#[derive(Clone)]
+pub struct Phong<T> {
+    diffuse: Spec<T>,
+    specular: Spec<T>,
+    exponent: T,
+}
+
+impl<T> Phong<T> {
+    pub fn new(diffuse: Spec<T>, specular: Spec<T>, exponent: T) -> Phong<T> {
+        Phong {
+            diffuse,
+            specular,
+            exponent,
+        }
+    }
+}
+
+impl<T: RealField> BSDF for Phong<T> {
+    type NumTy = T;
+
+    fn f(&self, wo: &Vector3<T>, wi: &Vector3<T>) -> Spec<T> {
+        if !same_hemisphere(wo, wi) {
+            return Spec::new(T::zero(), T::zero(), T::zero());
+        }
+        let two = T::one() + T::one();
+        // mirror reflection of wi about the normal
+        let reflected = Vector3::new(-wi.x, -wi.y, wi.z);
+        let cos_alpha = reflected.dot(&wo.normalize()).max(T::zero());
+        // (n + 2) / 2 normalizes the lobe, so it never reflects more light than it receives
+        let highlight = cos_alpha.powf(self.exponent) * (self.exponent + two) / two;
+        (self.diffuse.clone() + self.specular.clone() * highlight) * T::frac_1_pi()
+    }
+
+    /// Picks the diffuse or the glossy lobe in proportion to how much light each reflects, then
+    /// a direction with a density following that lobe.
+    fn sample_f(&self, wo: &Vector3<T>, u1: T, u2: T) -> Option<BsdfSample<T>> {
+        let diffuse_weight = self.diffuse_weight();
+        let (wi, flags) = if u1 < diffuse_weight {
+            let mut wi = cosine_sample_hemisphere(u1 / diffuse_weight, u2);
+            if cos_theta(wo) < T::zero() {
+                wi.z = -wi.z;
+            }
+            (wi, BsdfFlags::REFLECTION | BsdfFlags::DIFFUSE)
+        } else {
+            // cos(alpha)^n around the mirror direction
+            let u1 = (u1 - diffuse_weight) / (T::one() - diffuse_weight);
+            let cos_alpha = u1.powf(T::one() / (self.exponent + T::one()));
+            let sin_alpha = (T::one() - cos_alpha * cos_alpha).max(T::zero()).sqrt();
+            let phi = T::two_pi() * u2;
+            let lobe = Vector3::new(sin_alpha * phi.cos(), sin_alpha * phi.sin(), cos_alpha);
+            let wi = Frame::from_normal(reflect(&wo.normalize())).to_world(&lobe);
+            (wi, BsdfFlags::REFLECTION | BsdfFlags::GLOSSY)
+        };
+        // parts of the glossy lobe below the surface reflect nothing
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= T::zero() {
+            return None;
+        }
+        Some(BsdfSample {
+            f: self.f(wo, &wi),
+            wi,
+            pdf,
+            flags,
+        })
+    }
+
+    fn pdf(&self, wo: &Vector3<T>, wi: &Vector3<T>) -> T {
+        if !same_hemisphere(wo, wi) {
+            return T::zero();
+        }
+        let cos_alpha = reflect(&wo.normalize()).dot(wi).max(T::zero());
+        let glossy = cos_alpha.powf(self.exponent) * (self.exponent + T::one()) / T::two_pi();
+        let diffuse_weight = self.diffuse_weight();
+        cosine_pdf(wo, wi) * diffuse_weight + glossy * (T::one() - diffuse_weight)
+    }
+}
+
+impl<T: RealField> Phong<T> {
+    /// Chance of `sample_f` picking the diffuse lobe, its share of the total reflectance.
+    fn diffuse_weight(&self) -> T {
+        let sum = |c: &Spec<T>| c.red + c.green + c.blue;
+        let diffuse = sum(&self.diffuse);
+        let total = diffuse + sum(&self.specular);
+        if total > T::zero() {
+            diffuse / total
+        } else {
+            T::one()
+        }
+    }
+}
+
+/// Perfectly smooth mirror, reflecting all light in the mirror direction.
+#[derive(Clone)]
+pub struct Mirror<T> {
+    color: Spec<T>,
+}
+
+impl<T> Mirror<T> {
+    pub fn new(color: Spec<T>) -> Mirror<T> {
+        Mirror { color }
+    }
+}
+
+impl<T: RealField> BSDF for Mirror<T> {
+    type NumTy = T;
+
+    /// Only the single mirror direction reflects any light, so any other pair of directions
+    /// gives nothing.
+    fn f(&self, _wo: &Vector3<T>, _wi: &Vector3<T>) -> Spec<T> {
+        Spec::new(T::zero(), T::zero(), T::zero())
+    }
+
+    fn sample_f(&self, wo: &Vector3<T>, _u1: T, _u2: T) -> Option<BsdfSample<T>> {
+        let wi = reflect(wo);
+        let cos = cos_theta(&wi).abs();
+        if cos <= T::zero() {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.color.clone() * (T::one() / cos),
+            pdf: T::one(),
+            flags: BsdfFlags::REFLECTION | BsdfFlags::SPECULAR,
+        })
     }
+
+    fn pdf(&self, _wo: &Vector3<T>, _wi: &Vector3<T>) -> T {
+        T::zero()
+    }
+}
+
+/// Smooth boundary between air and a transparent material such as glass or water.
+///
+/// Light is either reflected in the mirror direction or refracted through the surface, chosen
+/// at random in proportion to the Fresnel reflectance. The surface normal points out into the
+/// air, and light inside that hits the surface at a shallow angle is totally internally
+/// reflected.
+#[derive(Clone)]
+pub struct Dielectric<T> {
+    reflect: Spec<T>,
+    transmit: Spec<T>,
+    /// Index of refraction of the material, relative to the air outside it.
+    ior: T,
+}
+
+impl<T> Dielectric<T> {
+    /// Clear material with index of refraction `ior`, for example 1.5 for glass.
+    pub fn new(reflect: Spec<T>, transmit: Spec<T>, ior: T) -> Dielectric<T> {
+        Dielectric {
+            reflect,
+            transmit,
+            ior,
+        }
+    }
+}
+
+impl<T: RealField> BSDF for Dielectric<T> {
+    type NumTy = T;
+
+    /// Only the reflected and refracted directions carry any light, so any other pair of
+    /// directions gives nothing.
+    fn f(&self, _wo: &Vector3<T>, _wi: &Vector3<T>) -> Spec<T> {
+        Spec::new(T::zero(), T::zero(), T::zero())
+    }
+
+    fn sample_f(&self, wo: &Vector3<T>, u1: T, _u2: T) -> Option<BsdfSample<T>> {
+        let reflectance = fresnel_dielectric(cos_theta(wo), self.ior);
+        if u1 < reflectance {
+            let wi = reflect(wo);
+            let cos = cos_theta(&wi).abs();
+            if cos <= T::zero() {
+                return None;
+            }
+            return Some(BsdfSample {
+                wi,
+                f: self.reflect.clone() * (reflectance / cos),
+                pdf: reflectance,
+                flags: BsdfFlags::REFLECTION | BsdfFlags::SPECULAR,
+            });
+        }
+
+        let entering = cos_theta(wo) > T::zero();
+        let (eta_i, eta_t) = if entering {
+            (T::one(), self.ior)
+        } else {
+            (self.ior, T::one())
+        };
+        let wi = refract(wo, eta_i / eta_t)?;
+        let cos = cos_theta(&wi).abs();
+        if cos <= T::zero() {
+            return None;
+        }
+        let transmittance = T::one() - reflectance;
+        // Radiance is concentrated into a smaller solid angle as it enters a denser material.
+        let squeeze = (eta_i * eta_i) / (eta_t * eta_t);
+        Some(BsdfSample {
+            wi,
+            f: self.transmit.clone() * (transmittance * squeeze / cos),
+            pdf: transmittance,
+            flags: BsdfFlags::TRANSMISSION | BsdfFlags::SPECULAR,
+        })
+    }
+
+    fn pdf(&self, _wo: &Vector3<T>, _wi: &Vector3<T>) -> T {
+        T::zero()
+    }
+}
+
+/// Surface that is only partly there, like a fine mesh or the dissolve of an MTL material.
+///
+/// A fraction `opacity` of the light is scattered by the wrapped BSDF, and the rest passes
+/// straight through the surface as if it wasn't there.
+#[derive(Clone)]
+pub struct Dissolved<B, N> {
+    bsdf: B,
+    opacity: N,
+}
+
+impl<N: RealField, B: BSDF<NumTy = N>> Dissolved<B, N> {
+    /// `opacity` is clamped to `[0, 1]`.
+    pub fn new(bsdf: B, opacity: N) -> Dissolved<B, N> {
+        Dissolved {
+            bsdf,
+            opacity: opacity.max(N::zero()).min(N::one()),
+        }
+    }
+}
+
+impl<N: RealField, B: BSDF<NumTy = N>> BSDF for Dissolved<B, N> {
+    type NumTy = N;
+
+    /// Light passing through only goes in a single direction, so only the scattered part is
+    /// left.
+    fn f(&self, wo: &Vector3<N>, wi: &Vector3<N>) -> Spec<N> {
+        self.bsdf.f(wo, wi) * self.opacity
+    }
+
+    /// Picks between scattering and passing through in proportion to the opacity, so the
+    /// wrapped BSDF's samples keep their weight.
+    fn sample_f(&self, wo: &Vector3<N>, u1: N, u2: N) -> Option<BsdfSample<N>> {
+        if u1 < self.opacity {
+            let sample = self.bsdf.sample_f(wo, u1 / self.opacity, u2)?;
+            return Some(BsdfSample {
+                f: sample.f * self.opacity,
+                pdf: sample.pdf * self.opacity,
+                ..sample
+            });
+        }
+        let wi = -wo;
+        let cos = cos_theta(&wi).abs();
+        if cos <= N::zero() {
+            return None;
+        }
+        let clear = N::one() - self.opacity;
+        Some(BsdfSample {
+            wi,
+            f: Spec::new(clear, clear, clear) * (N::one() / cos),
+            pdf: clear,
+            flags: BsdfFlags::TRANSMISSION | BsdfFlags::SPECULAR,
+        })
+    }
+
+    fn pdf(&self, wo: &Vector3<N>, wi: &Vector3<N>) -> N {
+        self.bsdf.pdf(wo, wi) * self.opacity
+    }
+
+    fn opacity(&self) -> N {
+        self.opacity * self.bsdf.opacity()
+    }
+}
+
+/// Rough metal, reflecting light off tiny mirror facets with orientations given by `D`.
+///
+/// The color comes from the complex index of refraction of the metal, `eta + i k`, given for each
+/// color channel.
+#[derive(Clone)]
+pub struct Conductor<D: MicrofacetDistribution> {
+    distribution: D,
+    eta: Spec<D::NumTy>,
+    k: Spec<D::NumTy>,
+}
+
+impl<N: Float, D: MicrofacetDistribution<NumTy = N>> Conductor<D> {
+    pub fn new(distribution: D, eta: Spec<N>, k: Spec<N>) -> Conductor<D> {
+        Conductor {
+            distribution,
+            eta,
+            k,
+        }
+    }
+
+    pub fn gold(distribution: D) -> Conductor<D> {
+        Conductor::new(
+            distribution,
+            spec(0.143, 0.374, 1.442),
+            spec(3.983, 2.385, 1.603),
+        )
+    }
+
+    pub fn silver(distribution: D) -> Conductor<D> {
+        Conductor::new(
+            distribution,
+            spec(0.155, 0.117, 0.138),
+            spec(4.828, 3.122, 2.147),
+        )
+    }
+
+    pub fn copper(distribution: D) -> Conductor<D> {
+        Conductor::new(
+            distribution,
+            spec(0.200, 0.924, 1.102),
+            spec(3.912, 2.452, 2.142),
+        )
+    }
+
+    pub fn aluminium(distribution: D) -> Conductor<D> {
+        Conductor::new(
+            distribution,
+            spec(1.657, 0.880, 0.521),
+            spec(9.224, 6.270, 4.837),
+        )
+    }
+
+    /// Fraction of light reflected by a facet, for each color channel.
+    fn fresnel(&self, cos_i: N) -> Spec<N> {
+        Spec::new(
+            fresnel_conductor(cos_i, self.eta.red, self.k.red),
+            fresnel_conductor(cos_i, self.eta.green, self.k.green),
+            fresnel_conductor(cos_i, self.eta.blue, self.k.blue),
+        )
+    }
+}
+
+fn spec<N: Float>(red: f32, green: f32, blue: f32) -> Spec<N> {
+    Spec::new(N::from(red), N::from(green), N::from(blue))
 }
 
-pub struct UniformMaterial<T> {
+/// Directions on the outside of the surface, for BSDFs that reflect the same from either side.
+fn to_upper<N: Float>(wo: &Vector3<N>, wi: &Vector3<N>) -> (Vector3<N>, Vector3<N>) {
+    if wo.z < N::zero() {
+        (-wo, -wi)
+    } else {
+        (*wo, *wi)
+    }
+}
+
+impl<N: Float, D: MicrofacetDistribution<NumTy = N>> BSDF for Conductor<D> {
+    type NumTy = N;
+
+    fn f(&self, wo: &Vector3<N>, wi: &Vector3<N>) -> Spec<N> {
+        let black = Spec::new(N::zero(), N::zero(), N::zero());
+        if !same_hemisphere(wo, wi) {
+            return black;
+        }
+        let (wo, wi) = to_upper(wo, wi);
+        let wh = match (wo + wi).try_normalize(N::zero()) {
+            Some(wh) => wh,
+            None => return black,
+        };
+        let scale = self.distribution.d(&wh) * g(&self.distribution, &wo, &wi)
+            / (N::from(4.0) * cos_theta(&wo) * cos_theta(&wi));
+        self.fresnel(wi.dot(&wh)) * scale
+    }
+
+    fn sample_f(&self, wo: &Vector3<N>, u1: N, u2: N) -> Option<BsdfSample<N>> {
+        if cos_theta(wo) == N::zero() {
+            return None;
+        }
+        let wh = self.distribution.sample_wh(wo, u1, u2);
+        let wi = reflect_about(wo, &wh);
+        if !same_hemisphere(wo, &wi) {
+            return None;
+        }
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= N::zero() {
+            return None;
+        }
+        Some(BsdfSample {
+            f: self.f(wo, &wi),
+            wi,
+            pdf,
+            flags: BsdfFlags::REFLECTION | BsdfFlags::GLOSSY,
+        })
+    }
+
+    fn pdf(&self, wo: &Vector3<N>, wi: &Vector3<N>) -> N {
+        if !same_hemisphere(wo, wi) {
+            return N::zero();
+        }
+        let (wo, wi) = to_upper(wo, wi);
+        match (wo + wi).try_normalize(N::zero()) {
+            Some(wh) => pdf_wh(&self.distribution, &wo, &wh) / (N::from(4.0) * wo.dot(&wh)),
+            None => N::zero(),
+        }
+    }
+}
+
+/// Rough boundary of a transparent material, like frosted glass, made of tiny smooth facets
+/// with orientations given by `D`.
+///
+/// As with `Dielectric`, the surface normal points out into the air. Rather than the Fresnel
+/// reflectance of each facet, which isn't known until one is picked, reflection is chosen with the
+/// reflectance of the surface as a whole, kept away from 0 and 1 so that both are always found.
+#[derive(Clone)]
+pub struct RoughDielectric<D: MicrofacetDistribution> {
+    distribution: D,
+    reflect: Spec<D::NumTy>,
+    transmit: Spec<D::NumTy>,
+    /// Index of refraction of the material, relative to the air outside it.
+    ior: D::NumTy,
+}
+
+impl<N: Float, D: MicrofacetDistribution<NumTy = N>> RoughDielectric<D> {
+    pub fn new(distribution: D, reflect: Spec<N>, transmit: Spec<N>, ior: N) -> RoughDielectric<D> {
+        RoughDielectric {
+            distribution,
+            reflect,
+            transmit,
+            ior,
+        }
+    }
+
+    /// Chance of `sample_f` picking reflection rather than refraction.
+    fn reflect_probability(&self, wo: &Vector3<N>) -> N {
+        let reflectance = fresnel_dielectric(cos_theta(wo), self.ior);
+        reflectance.max(N::from(0.1)).min(N::from(0.9))
+    }
+
+    /// Facet normal that turns `wo` into `wi`, facing the outside of the surface, and the index of
+    /// refraction on the side of `wi` relative to the side of `wo`. None if no facet visible from
+    /// both directions can do it.
+    fn facet_normal(&self, wo: &Vector3<N>, wi: &Vector3<N>) -> Option<(Vector3<N>, N)> {
+        let cos_o = cos_theta(wo);
+        let cos_i = cos_theta(wi);
+        if cos_o == N::zero() || cos_i == N::zero() {
+            return None;
+        }
+        let eta = if cos_o * cos_i > N::zero() {
+            N::one()
+        } else if cos_o > N::zero() {
+            self.ior
+        } else {
+            N::one() / self.ior
+        };
+        let wm = (wi * eta + wo).try_normalize(N::zero())?;
+        let wm = if wm.z < N::zero() { -wm } else { wm };
+        if wm.dot(wi) * cos_i < N::zero() || wm.dot(wo) * cos_o < N::zero() {
+            return None;
+        }
+        Some((wm, eta))
+    }
+}
+
+impl<N: Float, D: MicrofacetDistribution<NumTy = N>> BSDF for RoughDielectric<D> {
+    type NumTy = N;
+
+    fn f(&self, wo: &Vector3<N>, wi: &Vector3<N>) -> Spec<N> {
+        let (wm, eta) = match self.facet_normal(wo, wi) {
+            Some(found) => found,
+            None => return Spec::new(N::zero(), N::zero(), N::zero()),
+        };
+        let cos_o = cos_theta(wo);
+        let cos_i = cos_theta(wi);
+        let fresnel = fresnel_dielectric(wo.dot(&wm), self.ior);
+        let d = self.distribution.d(&wm);
+        let g = g(&self.distribution, wo, wi);
+        if same_hemisphere(wo, wi) {
+            return self.reflect.clone() * (d * g * fresnel / (N::from(4.0) * cos_o * cos_i).abs());
+        }
+        let denominator = wi.dot(&wm) + wo.dot(&wm) / eta;
+        let transmitted = d * (N::one() - fresnel) * g * wi.dot(&wm) * wo.dot(&wm)
+            / (denominator * denominator * cos_i * cos_o);
+        // Radiance is concentrated into a smaller solid angle as it enters a denser material.
+        self.transmit.clone() * (transmitted.abs() / (eta * eta))
+    }
+
+    fn sample_f(&self, wo: &Vector3<N>, u1: N, u2: N) -> Option<BsdfSample<N>> {
+        let reflect = self.reflect_probability(wo);
+        let below_one = N::one() - N::default_epsilon();
+        let (wi, flags) = if u1 < reflect {
+            let wm = self
+                .distribution
+                .sample_wh(wo, (u1 / reflect).min(below_one), u2);
+            let wi = reflect_about(wo, &wm);
+            (wi, BsdfFlags::REFLECTION | BsdfFlags::GLOSSY)
+        } else {
+            let u1 = ((u1 - reflect) / (N::one() - reflect)).min(below_one);
+            let wm = self.distribution.sample_wh(wo, u1, u2);
+            let eta = if cos_theta(wo) > N::zero() {
+                N::one() / self.ior
+            } else {
+                self.ior
+            };
+            let wi = refract_about(wo, &wm, eta)?;
+            (wi, BsdfFlags::TRANSMISSION | BsdfFlags::GLOSSY)
+        };
+        if same_hemisphere(wo, &wi) != flags.contains(BsdfFlags::REFLECTION) {
+            return None;
+        }
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= N::zero() {
+            return None;
+        }
+        Some(BsdfSample {
+            f: self.f(wo, &wi),
+            wi,
+            pdf,
+            flags,
+        })
+    }
+
+    fn pdf(&self, wo: &Vector3<N>, wi: &Vector3<N>) -> N {
+        let (wm, eta) = match self.facet_normal(wo, wi) {
+            Some(found) => found,
+            None => return N::zero(),
+        };
+        // the sampled facet faces the same side as wo
+        let wm_o = if cos_theta(wo) < N::zero() { -wm } else { wm };
+        let facet_pdf = pdf_wh(&self.distribution, wo, &wm_o);
+        let reflect = self.reflect_probability(wo);
+        if same_hemisphere(wo, wi) {
+            return facet_pdf / (N::from(4.0) * wo.dot(&wm_o).abs()) * reflect;
+        }
+        let denominator = wi.dot(&wm) + wo.dot(&wm) / eta;
+        let dwm_dwi = wi.dot(&wm).abs() / (denominator * denominator);
+        facet_pdf * dwm_dwi * (N::one() - reflect)
+    }
+}
+
+/// Mirror reflection of the local direction `w` about the normal.
+pub fn reflect<T: RealField>(w: &Vector3<T>) -> Vector3<T> {
+    Vector3::new(-w.x, -w.y, w.z)
+}
+
+/// Mirror reflection of `w` about the unit vector `n`.
+pub fn reflect_about<T: RealField>(w: &Vector3<T>, n: &Vector3<T>) -> Vector3<T> {
+    n * (n.dot(w) * (T::one() + T::one())) - w
+}
+
+/// Direction light arriving from local direction `w` is refracted to when crossing the surface.
+/// `eta` is the index of refraction on the side of `w` divided by the one on the other side.
+/// Returns None when all of the light is reflected instead.
+pub fn refract<T: RealField>(w: &Vector3<T>, eta: T) -> Option<Vector3<T>> {
+    refract_about(w, &Vector3::z(), eta)
+}
+
+/// Like `refract`, but through a surface with the unit normal `n` on either side.
+pub fn refract_about<T: RealField>(w: &Vector3<T>, n: &Vector3<T>, eta: T) -> Option<Vector3<T>> {
+    // normal on the same side as w
+    let n = if n.dot(w) < T::zero() { -n } else { *n };
+    let cos_i = n.dot(w);
+    let sin2_i = (T::one() - cos_i * cos_i).max(T::zero());
+    let sin2_t = eta * eta * sin2_i;
+    if sin2_t >= T::one() {
+        return None;
+    }
+    let cos_t = (T::one() - sin2_t).sqrt();
+    Some(-w * eta + n * (eta * cos_i - cos_t))
+}
+
+/// Fraction of unpolarized light reflected at a smooth boundary, for light arriving at an angle
+/// with cosine `cos_i` to the normal. `ior` is the index of refraction on the inside of the
+/// surface, relative to the outside, and negative `cos_i` arrives from the inside.
+pub fn fresnel_dielectric<T: RealField>(cos_i: T, ior: T) -> T {
+    let cos_i = cos_i.max(-T::one()).min(T::one());
+    let (eta_i, eta_t, cos_i) = if cos_i > T::zero() {
+        (T::one(), ior, cos_i)
+    } else {
+        (ior, T::one(), -cos_i)
+    };
+    let sin_i = (T::one() - cos_i * cos_i).max(T::zero()).sqrt();
+    let sin_t = eta_i / eta_t * sin_i;
+    if sin_t >= T::one() {
+        // total internal reflection
+        return T::one();
+    }
+    let cos_t = (T::one() - sin_t * sin_t).max(T::zero()).sqrt();
+    let parallel = (eta_t * cos_i - eta_i * cos_t) / (eta_t * cos_i + eta_i * cos_t);
+    let perpendicular = (eta_i * cos_i - eta_t * cos_t) / (eta_i * cos_i + eta_t * cos_t);
+    (parallel * parallel + perpendicular * perpendicular) / (T::one() + T::one())
+}
+
+/// Fraction of unpolarized light reflected by a smooth metal with the complex index of refraction
+/// `eta + i k`, for light arriving at an angle with cosine `cos_i` to the normal.
+pub fn fresnel_conductor<T: RealField>(cos_i: T, eta: T, k: T) -> T {
+    let cos_i = cos_i.abs().min(T::one());
+    let two = T::one() + T::one();
+    let cos2 = cos_i * cos_i;
+    let sin2 = T::one() - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + two * two * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = ((a2_plus_b2 + t0) / two).max(T::zero()).sqrt();
+    let t2 = two * cos_i * a;
+    let perpendicular = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+    (parallel + perpendicular) / two
+}
+
+#[derive(Clone)]
+pub struct UniformMaterial<T: BSDF> {
     bsdf: T,
+    emission: Option<Spec<T::NumTy>>,
 }
 
-impl<T> UniformMaterial<T> {
+impl<T: BSDF> UniformMaterial<T> {
     pub fn new(bsdf: T) -> UniformMaterial<T> {
-        UniformMaterial { bsdf }
+        UniformMaterial {
+            bsdf,
+            emission: None,
+        }
+    }
+
+    /// Makes the material glow with the given radiance, turning the surfaces that use it into
+    /// lights.
+    pub fn with_emission(self, emission: Spec<T::NumTy>) -> UniformMaterial<T> {
+        UniformMaterial {
+            emission: Some(emission),
+            ..self
+        }
     }
 }
 
@@ -77,20 +872,479 @@ impl<B: BSDF + Clone> Material for UniformMaterial<B> {
     fn get_bsdf(&self, _g: &DiffGeom<Self::NumTy>) -> Self::BSDF_fn {
         self.bsdf.clone()
     }
+
+    fn emission(&self) -> Option<Spec<Self::NumTy>> {
+        self.emission.clone()
+    }
+
+    fn opacity(&self) -> Option<Self::NumTy> {
+        Some(self.bsdf.opacity())
+    }
+}
+
+/// Lambertian surface with a color that varies across it.
+#[derive(Clone)]
+pub struct TexturedLambert<T, N> {
+    color: T,
+    num_type: PhantomData<N>,
+}
+
+impl<T, N> TexturedLambert<T, N> {
+    pub fn new(color: T) -> TexturedLambert<T, N> {
+        TexturedLambert {
+            color,
+            num_type: PhantomData,
+        }
+    }
+}
+
+impl<N: RealField, T: Texture<Spec<N>, NumTy = N>> Material for TexturedLambert<T, N> {
+    type NumTy = N;
+    type BSDF_fn = Lambert<N>;
+
+    fn get_bsdf(&self, g: &DiffGeom<N>) -> Lambert<N> {
+        Lambert {
+            color: self.color.evaluate(g),
+        }
+    }
+}
+
+/// `Phong` surface with a diffuse color that varies across it, such as an MTL material with a
+/// `map_Kd` image.
+#[derive(Clone)]
+pub struct TexturedPhong<T, N> {
+    diffuse: T,
+    specular: Spec<N>,
+    exponent: N,
+    emission: Option<Spec<N>>,
+    opacity: N,
+}
+
+impl<T, N: RealField> TexturedPhong<T, N> {
+    pub fn new(diffuse: T, specular: Spec<N>, exponent: N) -> TexturedPhong<T, N> {
+        TexturedPhong {
+            diffuse,
+            specular,
+            exponent,
+            emission: None,
+            opacity: N::one(),
+        }
+    }
+
+    /// Makes the material glow with `emission`, the radiance leaving each point of the surface.
+    pub fn with_emission(self, emission: Spec<N>) -> TexturedPhong<T, N> {
+        TexturedPhong {
+            emission: Some(emission),
+            ..self
+        }
+    }
+
+    /// Lets the fraction `1 - opacity` of the light pass straight through, see `Dissolved`.
+    pub fn with_opacity(self, opacity: N) -> TexturedPhong<T, N> {
+        TexturedPhong { opacity, ..self }
+    }
+}
+
+impl<N: RealField, T: Texture<Spec<N>, NumTy = N>> Material for TexturedPhong<T, N> {
+    type NumTy = N;
+    type BSDF_fn = Dissolved<Phong<N>, N>;
+
+    fn get_bsdf(&self, g: &DiffGeom<N>) -> Dissolved<Phong<N>, N> {
+        let phong = Phong::new(
+            self.diffuse.evaluate(g),
+            self.specular.clone(),
+            self.exponent,
+        );
+        Dissolved::new(phong, self.opacity)
+    }
+
+    fn emission(&self) -> Option<Spec<N>> {
+        self.emission.clone()
+    }
+
+    fn opacity(&self) -> Option<N> {
+        Some(self.opacity)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::render::microfacet::{Beckmann, GGX};
+    use crate::render::ray::tests::st_vec3;
+    use crate::render::texture::ConstantTexture;
+    use approx::abs_diff_eq;
+    use proptest::prelude::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+    use std::f64::consts::PI;
+
+    #[test]
+    fn lambert_is_bsdf() {
+        fn is_bsdf<B: BSDF>() {}
+        is_bsdf::<Lambert<f64>>();
+        is_bsdf::<Lambert<f32>>();
+        is_bsdf::<Phong<f64>>();
+    }
+
+    #[test]
+    fn phong_without_specular_matches_lambert() {
+        let phong = Phong::new(Spec::new(0.5, 0.25, 1.0), Spec::new(0.0, 0.0, 0.0), 20.0);
+        let lambert = Lambert::new(0.5, 0.25, 1.0);
+        let wo = Vector3::new(0.3, -0.2, 1.0).normalize();
+        let wi = Vector3::new(-0.5, 0.1, 0.8).normalize();
+        let p = phong.f(&wo, &wi);
+        let l = lambert.f(&wo, &wi);
+        assert!(abs_diff_eq!(p.red, l.red, epsilon = 1e-10));
+        assert!(abs_diff_eq!(p.green, l.green, epsilon = 1e-10));
+        assert!(abs_diff_eq!(p.blue, l.blue, epsilon = 1e-10));
+    }
+
+    #[test]
+    fn nothing_passes_through_opaque_surfaces() {
+        let lambert = Lambert::new(1.0, 1.0, 1.0);
+        let below = lambert.f(&Vector3::z(), &-Vector3::z());
+        assert_eq!(below.max_component(), 0.0);
+        assert_eq!(lambert.pdf(&Vector3::z(), &-Vector3::z()), 0.0);
+    }
+
+    #[test]
+    fn dissolved_passes_rest_of_light_through() {
+        let wo = Vector3::new(0.6, 0.0, 0.8);
+        let dissolved = Dissolved::new(Lambert::new(1.0, 1.0, 1.0), 0.25);
+        // only a quarter of the light is scattered
+        let f = dissolved.f(&wo, &Vector3::z());
+        assert!(abs_diff_eq!(f.red, 0.25 / PI, epsilon = 1e-10));
+
+        let through = dissolved.sample_f(&wo, 0.5, 0.5).unwrap();
+        assert_eq!(through.wi, -wo);
+        assert!(through
+            .flags
+            .contains(BsdfFlags::TRANSMISSION | BsdfFlags::SPECULAR));
+        let scattered = dissolved.sample_f(&wo, 0.1, 0.5).unwrap();
+        assert!(same_hemisphere(&wo, &scattered.wi));
+        assert!(abs_diff_eq!(
+            scattered.pdf,
+            dissolved.pdf(&wo, &scattered.wi),
+            epsilon = 1e-10
+        ));
+        // both kinds of sample carry all of the light they stand for
+        for sample in &[through, scattered] {
+            let weight = sample.f.red * cos_theta(&sample.wi).abs() / sample.pdf;
+            assert!(abs_diff_eq!(weight, 1.0, epsilon = 1e-10));
+        }
+    }
+
+    #[test]
+    fn flags_combine() {
+        let flags = BsdfFlags::REFLECTION | BsdfFlags::DIFFUSE;
+        assert!(flags.contains(BsdfFlags::DIFFUSE));
+        assert!(flags.contains(BsdfFlags::REFLECTION | BsdfFlags::DIFFUSE));
+        assert!(!flags.contains(BsdfFlags::SPECULAR));
+    }
+
+    proptest! {
+        #[test]
+        fn lambert_sample_weight_is_color(u1 in 0f64..1.0, u2 in 0f64..1.0, wo in st_vec3(-1f64..1.0)) {
+            prop_assume!(wo.z.abs() > 0.01);
+            let wo = wo.normalize();
+            let lambert = Lambert::new(0.5, 0.25, 1.0);
+            if let Some(sample) = lambert.sample_f(&wo, u1, u2) {
+                prop_assert!(same_hemisphere(&wo, &sample.wi));
+                prop_assert!(abs_diff_eq!(lambert.pdf(&wo, &sample.wi), sample.pdf, epsilon = 1e-10));
+                let weight = sample.f * (cos_theta(&sample.wi).abs() / sample.pdf);
+                prop_assert!(abs_diff_eq!(weight.red, 0.5, epsilon = 1e-10));
+                prop_assert!(abs_diff_eq!(weight.blue, 1.0, epsilon = 1e-10));
+            }
+        }
+
+        #[test]
+        fn phong_pdf_matches_sample(u1 in 0f64..1.0, u2 in 0f64..1.0, wo in st_vec3(-1f64..1.0),
+                                    exponent in 0f64..100.0) {
+            prop_assume!(wo.z.abs() > 0.01);
+            let wo = wo.normalize();
+            let phong = Phong::new(Spec::new(0.5, 0.25, 0.5), Spec::new(0.5, 0.5, 0.25), exponent);
+            if let Some(sample) = phong.sample_f(&wo, u1, u2) {
+                prop_assert!(same_hemisphere(&wo, &sample.wi));
+                prop_assert!(abs_diff_eq!(sample.wi.magnitude(), 1.0, epsilon = 1e-10));
+                let pdf = phong.pdf(&wo, &sample.wi);
+                prop_assert!(abs_diff_eq!(sample.pdf, pdf, epsilon = 1e-9 * pdf.max(1.0)));
+                prop_assert_eq!(sample.f.red, phong.f(&wo, &sample.wi).red);
+            }
+        }
+    }
+
+    /// Fraction of the light arriving at `phong` from all directions that leaves along `wo`,
+    /// by the midpoint rule over the angles of the hemisphere.
+    fn phong_albedo(phong: &Phong<f64>, wo: &Vector3<f64>) -> f64 {
+        let steps = 300;
+        let d_theta = PI / 2.0 / f64::from(steps);
+        let d_phi = 2.0 * PI / f64::from(steps);
+        let mut total = 0.0;
+        for i in 0..steps {
+            let theta = (f64::from(i) + 0.5) * d_theta;
+            for j in 0..steps {
+                let phi = (f64::from(j) + 0.5) * d_phi;
+                let wi = Vector3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                total += phong.f(wo, &wi).red * theta.cos() * theta.sin();
+            }
+        }
+        total * d_theta * d_phi
+    }
+
+    #[test]
+    fn phong_reflects_at_most_what_it_receives() {
+        for &exponent in &[0.0, 1.0, 10.0, 100.0] {
+            let phong = Phong::new(Spec::new(0.5, 0.5, 0.5), Spec::new(0.5, 0.5, 0.5), exponent);
+            for &theta in &[0.0f64, 0.5, 1.0, 1.5] {
+                let wo = Vector3::new(theta.sin(), 0.0, theta.cos());
+                let albedo = phong_albedo(&phong, &wo);
+                assert!(
+                    albedo <= 1.0 + 1e-3,
+                    "{} at {}: {}",
+                    exponent,
+                    theta,
+                    albedo
+                );
+            }
+        }
+        // looking straight down the highlight, all of the light is reflected
+        let phong = Phong::new(Spec::new(0.5, 0.5, 0.5), Spec::new(0.5, 0.5, 0.5), 10.0);
+        let albedo = phong_albedo(&phong, &Vector3::z());
+        assert!(abs_diff_eq!(albedo, 1.0, epsilon = 1e-3));
+    }
+
+    #[test]
+    fn phong_samples_follow_highlight() {
+        // With the samples following the narrow lobe, a few hundred are enough to find the
+        // albedo closely. Directions picked evenly would mostly miss the highlight.
+        let phong = Phong::new(Spec::new(0.1, 0.1, 0.1), Spec::new(0.9, 0.9, 0.9), 100.0);
+        let wo = Vector3::new(0.6, 0.0, 0.8);
+        let mut rng = SmallRng::seed_from_u64(3);
+        let samples = 500;
+        let mut total = 0.0;
+        for _ in 0..samples {
+            if let Some(sample) = phong.sample_f(&wo, rng.gen(), rng.gen()) {
+                total += sample.f.red * cos_theta(&sample.wi) / sample.pdf;
+            }
+        }
+        let expected = phong_albedo(&phong, &wo);
+        assert!(abs_diff_eq!(
+            total / f64::from(samples),
+            expected,
+            epsilon = 0.02
+        ));
+    }
+
+    #[test]
+    fn mirror_reflects_about_normal() {
+        let mirror = Mirror::new(Spec::new(0.9, 0.9, 0.9));
+        let wo = Vector3::new(0.6, 0.0, 0.8);
+        let sample = mirror.sample_f(&wo, 0.5, 0.5).unwrap();
+        assert!((sample.wi - Vector3::new(-0.6, 0.0, 0.8)).magnitude() < 1e-10);
+        assert!(sample.flags.contains(BsdfFlags::SPECULAR));
+        let weight = sample.f * (cos_theta(&sample.wi) / sample.pdf);
+        assert!(abs_diff_eq!(weight.red, 0.9, epsilon = 1e-10));
+        assert_eq!(mirror.f(&wo, &sample.wi).red, 0.0);
+    }
+
+    #[test]
+    fn fresnel_of_glass() {
+        // about 4% of light is reflected straight on
+        assert!(abs_diff_eq!(
+            fresnel_dielectric(1.0, 1.5),
+            0.04,
+            epsilon = 1e-10
+        ));
+        // and all of it at a grazing angle
+        assert!(abs_diff_eq!(
+            fresnel_dielectric(0.0, 1.5),
+            1.0,
+            epsilon = 1e-10
+        ));
+        // past the critical angle inside the glass, light can't get out
+        assert_eq!(fresnel_dielectric(-0.5, 1.5), 1.0);
+        assert!(fresnel_dielectric(-0.9, 1.5) < 1.0);
+    }
+
+    #[test]
+    fn glass_refracts_by_snells_law() {
+        let glass = Dielectric::new(Spec::new(1.0, 1.0, 1.0), Spec::new(1.0, 1.0, 1.0), 1.5);
+        let wo = Vector3::new(0.6, 0.0, 0.8);
+        // a sample past the reflectance always refracts
+        let sample = glass.sample_f(&wo, 0.99, 0.5).unwrap();
+        assert!(sample.flags.contains(BsdfFlags::TRANSMISSION));
+        assert!(sample.wi.z < 0.0);
+        assert!(abs_diff_eq!(sample.wi.magnitude(), 1.0, epsilon = 1e-10));
+        // sin of the angle to the normal shrinks by the index of refraction, on the other side
+        assert!(abs_diff_eq!(sample.wi.x, -0.4, epsilon = 1e-10));
+
+        // going back out reverses the refraction
+        let out = refract(&sample.wi, 1.5).unwrap();
+        assert!((out - wo).magnitude() < 1e-10);
+
+        let reflected = glass.sample_f(&wo, 0.0, 0.5).unwrap();
+        assert!(reflected.flags.contains(BsdfFlags::REFLECTION));
+        assert!((reflected.wi - reflect(&wo)).magnitude() < 1e-10);
+    }
+
+    proptest! {
+        #[test]
+        fn glass_keeps_energy(wo in st_vec3(-1f64..1.0), u1 in 0f64..1.0) {
+            prop_assume!(wo.magnitude() > 0.01 && wo.z.abs() > 0.01);
+            let wo = wo.normalize();
+            let ior = 1.5;
+            let glass = Dielectric::new(Spec::new(1.0, 1.0, 1.0), Spec::new(1.0, 1.0, 1.0), ior);
+            let sample = glass.sample_f(&wo, u1, 0.5).unwrap();
+            let weight = sample.f.red * cos_theta(&sample.wi).abs() / sample.pdf;
+            // each path carries all of the light, apart from the change in radiance as it
+            // crosses into a different material
+            let expected = if sample.flags.contains(BsdfFlags::REFLECTION) {
+                1.0
+            } else if wo.z > 0.0 {
+                1.0 / (ior * ior)
+            } else {
+                ior * ior
+            };
+            prop_assert!(abs_diff_eq!(weight, expected, epsilon = 1e-8));
+        }
+    }
+
+    #[test]
+    fn fresnel_conductor_without_absorption_is_dielectric() {
+        for &cos in &[1.0, 0.7, 0.2, 0.0] {
+            let conductor = fresnel_conductor(cos, 1.5, 0.0);
+            assert!(abs_diff_eq!(
+                conductor,
+                fresnel_dielectric(cos, 1.5),
+                epsilon = 1e-10
+            ));
+        }
+        // metals reflect most light even straight on
+        assert!(fresnel_conductor(1.0, 0.2, 3.9) > 0.9);
+    }
+
+    /// Integrates the pdf of `bsdf` over every direction, on a grid in spherical coordinates.
+    fn total_pdf<B: BSDF<NumTy = f64>>(bsdf: &B, wo: &Vector3<f64>) -> f64 {
+        let steps = 500;
+        let d_theta = PI / f64::from(steps);
+        let d_phi = 2.0 * PI / f64::from(steps);
+        let mut total = 0.0;
+        for i in 0..steps {
+            let theta = (f64::from(i) + 0.5) * d_theta;
+            for j in 0..steps {
+                let phi = (f64::from(j) + 0.5) * d_phi;
+                let wi = Vector3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                total += bsdf.pdf(wo, &wi) * theta.sin() * d_theta * d_phi;
+            }
+        }
+        total
+    }
+
+    /// Average of the sample weights `f |cos| / pdf`, the fraction of light arriving along `wo`
+    /// that is scattered.
+    fn albedo<B: BSDF<NumTy = f64>>(bsdf: &B, wo: &Vector3<f64>) -> f64 {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let samples = 20000;
+        let mut total = 0.0;
+        for _ in 0..samples {
+            if let Some(sample) = bsdf.sample_f(wo, rng.gen(), rng.gen()) {
+                total += sample.f.red * cos_theta(&sample.wi).abs() / sample.pdf;
+            }
+        }
+        total / f64::from(samples)
+    }
+
+    #[test]
+    fn microfacet_pdfs_integrate_to_one() {
+        // Samples that would scatter to the wrong side of the surface are dropped, as are
+        // refractions through facets that totally internally reflect, so a little is lost.
+        let wo = Vector3::new(0.4, 0.1, 0.8).normalize();
+        let gold = Conductor::gold(GGX::anisotropic(0.3, 0.1));
+        let total = total_pdf(&gold, &wo);
+        assert!(total > 0.9 && total < 1.01, "total {}", total);
+
+        let frosted = RoughDielectric::new(
+            Beckmann::new(0.3),
+            Spec::new(1.0, 1.0, 1.0),
+            Spec::new(1.0, 1.0, 1.0),
+            1.5,
+        );
+        for wo in &[wo, -wo] {
+            let total = total_pdf(&frosted, wo);
+            assert!(total > 0.8 && total < 1.01, "total {}", total);
+        }
+    }
+
+    #[test]
+    fn smooth_microfacets_match_specular() {
+        let wo = Vector3::new(0.3, 0.0, 0.9).normalize();
+        let gold = Conductor::gold(GGX::new(0.01));
+        let expected = fresnel_conductor(wo.z, 0.143, 3.983);
+        assert!((albedo(&gold, &wo) - expected).abs() < 0.01);
+
+        let ior = 1.5;
+        let white = Spec::new(1.0, 1.0, 1.0);
+        let glass = RoughDielectric::new(GGX::new(0.01), white.clone(), white, ior);
+        let reflected = fresnel_dielectric(wo.z, ior);
+        let expected = reflected + (1.0 - reflected) / (ior * ior);
+        assert!((albedo(&glass, &wo) - expected).abs() < 0.02);
+    }
+
+    proptest! {
+        #[test]
+        fn conductor_reflects_at_most_what_it_receives(
+            wo in st_vec3(-1f64..1.0),
+            u1 in 0f64..1.0,
+            u2 in 0f64..1.0,
+            alpha in 0.01f64..1.0,
+        ) {
+            prop_assume!(wo.magnitude() > 0.01 && wo.z.abs() > 0.01);
+            let wo = wo.normalize();
+            let silver = Conductor::silver(Beckmann::anisotropic(alpha, 0.2));
+            if let Some(sample) = silver.sample_f(&wo, u1, u2) {
+                prop_assert!(same_hemisphere(&wo, &sample.wi));
+                let weight = sample.f.red * cos_theta(&sample.wi).abs() / sample.pdf;
+                prop_assert!(weight <= 1.0 + 1e-6, "weight {}", weight);
+                prop_assert!(abs_diff_eq!(silver.pdf(&wo, &sample.wi), sample.pdf, epsilon = 1e-6));
+            }
+        }
+    }
+
+    #[test]
+    fn textured_lambert_follows_texture() {
+        use crate::render::texture::Checkerboard;
+        use na::{Point2, Point3};
 
-    fn lambert_is_BSDF() {
-        fn is_BSDF<B: BSDF>() {};
-        is_BSDF::<Lambert<f64>>();
-        is_BSDF::<Lambert<f32>>();
+        let black = ConstantTexture::new(Spec::new(0.0, 0.0, 0.0));
+        let white = ConstantTexture::new(Spec::new(1.0, 1.0, 1.0));
+        let material = TexturedLambert::new(Checkerboard::new(white, black, 2.0));
+        let mut g = DiffGeom {
+            position: Point3::origin(),
+            normal: Vector3::z(),
+            geometric_normal: Vector3::z(),
+            uv: Point2::new(0.25, 0.25),
+            dpdu: Vector3::x(),
+            dpdv: Vector3::y(),
+            uv_width: 0.0,
+        };
+        let z = Vector3::z();
+        assert!(abs_diff_eq!(material.get_bsdf(&g).f(&z, &z).red * PI, 1.0));
+        g.uv = Point2::new(0.75, 0.25);
+        assert_eq!(material.get_bsdf(&g).f(&z, &z).red, 0.0);
     }
 
+    #[test]
     fn uniform_is_material() {
-        fn is_material<M: Material>() {};
+        fn is_material<M: Material>() {}
         is_material::<UniformMaterial<Lambert<f64>>>();
     }
 }
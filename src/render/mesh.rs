@@ -0,0 +1,162 @@
+use na::{Point3, RealField, Vector3};
+use std::collections::HashMap;
+
+/// Generates smooth vertex normals for a triangle mesh.
+///
+/// `triangles` index into `positions`. The normal at each corner of a triangle is the area
+/// weighted average of the normals of the triangles sharing that vertex, skipping any whose
+/// normal is more than `crease_angle` radians from this triangle's. Edges sharper than the
+/// crease angle stay sharp, while curved surfaces are shaded smoothly.
+///
+/// Returns the three corner normals of each triangle, in the order of `triangles`.
+pub fn smooth_normals<N: RealField>(
+    positions: &[Point3<N>],
+    triangles: &[[usize; 3]],
+    crease_angle: N,
+) -> Vec<[Vector3<N>; 3]> {
+    // cross product of the edges, so the length is twice the area
+    let face_normals: Vec<Vector3<N>> = triangles
+        .iter()
+        .map(|&[a, b, c]| (positions[b] - positions[a]).cross(&(positions[c] - positions[a])))
+        .collect();
+
+    let mut adjacent = vec![vec![]; positions.len()];
+    for (face, corners) in triangles.iter().enumerate() {
+        for &vertex in corners {
+            adjacent[vertex].push(face);
+        }
+    }
+
+    let min_cos = crease_angle.cos();
+    triangles
+        .iter()
+        .enumerate()
+        .map(|(face, corners)| {
+            let normal = face_normals[face];
+            let unit = match normal.try_normalize(N::zero()) {
+                Some(unit) => unit,
+                // a degenerate triangle has no direction to compare against
+                None => return [normal; 3],
+            };
+            let mut smoothed = [unit; 3];
+            for (corner, &vertex) in smoothed.iter_mut().zip(corners) {
+                let sum = adjacent[vertex]
+                    .iter()
+                    .map(|&other| face_normals[other])
+                    .filter(|other| {
+                        matches!(other.try_normalize(N::zero()),
+                                 Some(other) if other.dot(&unit) >= min_cos)
+                    })
+                    .fold(Vector3::zeros(), |sum, other| sum + other);
+                *corner = sum.try_normalize(N::zero()).unwrap_or(unit);
+            }
+            smoothed
+        })
+        .collect()
+}
+
+/// Averages the tangents of the triangles of a mesh at each vertex, so they change smoothly
+/// across the mesh.
+///
+/// `vertices` gives an id for each corner of each triangle, and `face_tangents` the tangent of
+/// each triangle, or None for triangles without one. Corners with the same id share a tangent.
+/// Ids should tell apart corners that share a position but not texture coordinates, so that
+/// tangents aren't averaged across seams in the texture.
+///
+/// Returns the three corner tangents of each triangle, in the order of `vertices`.
+pub fn smooth_tangents<N: RealField>(
+    vertices: &[[usize; 3]],
+    face_tangents: &[Option<Vector3<N>>],
+) -> Vec<[Vector3<N>; 3]> {
+    // sum and count of the tangents at each vertex
+    let mut sums: HashMap<usize, (Vector3<N>, usize)> = HashMap::new();
+    for (corners, tangent) in vertices.iter().zip(face_tangents) {
+        if let Some(tangent) = tangent {
+            for vertex in corners {
+                let sum = sums.entry(*vertex).or_insert((Vector3::zeros(), 0));
+                sum.0 += tangent;
+                sum.1 += 1;
+            }
+        }
+    }
+    vertices
+        .iter()
+        .zip(face_tangents)
+        .map(|(corners, tangent)| {
+            let own = tangent.unwrap_or_else(Vector3::zeros);
+            corners.map(|vertex| match sums.get(&vertex) {
+                // mirrored texture coordinates can cancel out
+                Some((sum, count)) if sum.norm_squared() > N::zero() => {
+                    sum / N::from_subset(&(*count as f64))
+                }
+                _ => own,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    /// Two triangles sharing the edge from (0, 0, 0) to (0, 1, 0), folded by `angle` radians
+    /// away from flat.
+    fn folded(angle: f64) -> (Vec<Point3<f64>>, Vec<[usize; 3]>) {
+        let positions = vec![
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+            Point3::new(-1.0, 0.0, 0.0),
+            Point3::new(angle.cos(), 0.0, angle.sin()),
+        ];
+        (positions, vec![[0, 1, 2], [0, 3, 1]])
+    }
+
+    #[test]
+    fn flat_mesh_keeps_face_normals() {
+        let (positions, triangles) = folded(0.0);
+        for corners in smooth_normals(&positions, &triangles, PI / 3.0) {
+            for normal in &corners {
+                assert!((normal - Vector3::z()).magnitude() < 1e-10);
+            }
+        }
+    }
+
+    #[test]
+    fn shallow_fold_is_smoothed() {
+        let (positions, triangles) = folded(0.3);
+        let normals = smooth_normals(&positions, &triangles, PI / 3.0);
+        // the shared vertices get the same normal on both sides of the fold
+        assert!((normals[0][0] - normals[1][0]).magnitude() < 1e-10);
+        assert!((normals[0][1] - normals[1][2]).magnitude() < 1e-10);
+        // vertices not on the fold keep the face normal
+        assert!((normals[0][2] - Vector3::z()).magnitude() < 1e-10);
+    }
+
+    #[test]
+    fn sharp_fold_is_kept() {
+        let (positions, triangles) = folded(1.5);
+        let normals = smooth_normals(&positions, &triangles, PI / 3.0);
+        assert!((normals[0][0] - Vector3::z()).magnitude() < 1e-10);
+        assert!((normals[0][0] - normals[1][0]).magnitude() > 0.5);
+    }
+
+    #[test]
+    fn tangents_average_at_shared_vertices() {
+        let vertices = [[0, 1, 2], [0, 2, 3], [4, 5, 6]];
+        let tangents = [
+            Some(Vector3::new(1.0, 0.0, 0.0)),
+            Some(Vector3::new(0.0, 1.0, 0.0)),
+            None,
+        ];
+        let smoothed = smooth_tangents(&vertices, &tangents);
+        // shared corners get the average
+        let average = Vector3::new(0.5, 0.5, 0.0);
+        assert!((smoothed[0][0] - average).magnitude() < 1e-10);
+        assert!((smoothed[1][1] - average).magnitude() < 1e-10);
+        // others keep the face's own
+        assert!((smoothed[0][1] - Vector3::x()).magnitude() < 1e-10);
+        assert!((smoothed[1][2] - Vector3::y()).magnitude() < 1e-10);
+        assert_eq!(smoothed[2][0], Vector3::zeros());
+    }
+}
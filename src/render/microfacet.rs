@@ -0,0 +1,461 @@
+use super::frame::cos_theta;
+use crate::number::Float;
+use na::Vector3;
+
+/// Distribution of the orientations of the tiny mirror facets that make up a rough surface.
+///
+/// Directions are in the local shading space, with the surface normal along +Z. Roughness is
+/// given as `alpha`, roughly the RMS slope of the facets, separately along the local X and Y axes
+/// so that brushed surfaces can be rough in one direction and smooth in the other.
+pub trait MicrofacetDistribution {
+    type NumTy: Float;
+
+    /// Density of facets with the normal `wh`, per unit of projected area of the surface.
+    fn d(&self, wh: &Vector3<Self::NumTy>) -> Self::NumTy;
+
+    /// Area of facets hidden from direction `w` by other facets, relative to the visible area.
+    fn lambda(&self, w: &Vector3<Self::NumTy>) -> Self::NumTy;
+
+    /// Picks a facet normal from those visible from `wo`, in proportion to their visible area.
+    fn sample_wh(
+        &self,
+        wo: &Vector3<Self::NumTy>,
+        u1: Self::NumTy,
+        u2: Self::NumTy,
+    ) -> Vector3<Self::NumTy>;
+}
+
+/// Fraction of facets visible from direction `w`.
+pub fn g1<N: Float, D: MicrofacetDistribution<NumTy = N> + ?Sized>(
+    distribution: &D,
+    w: &Vector3<N>,
+) -> N {
+    N::one() / (N::one() + distribution.lambda(w))
+}
+
+/// Fraction of facets visible from both `wo` and `wi`, using the Smith model.
+pub fn g<N: Float, D: MicrofacetDistribution<NumTy = N> + ?Sized>(
+    distribution: &D,
+    wo: &Vector3<N>,
+    wi: &Vector3<N>,
+) -> N {
+    N::one() / (N::one() + distribution.lambda(wo) + distribution.lambda(wi))
+}
+
+/// Density of `sample_wh` picking the facet normal `wh` when seen from `wo`. Facets facing away
+/// from `wo` are never picked.
+pub fn pdf_wh<N: Float, D: MicrofacetDistribution<NumTy = N> + ?Sized>(
+    distribution: &D,
+    wo: &Vector3<N>,
+    wh: &Vector3<N>,
+) -> N {
+    let cos_o = cos_theta(wo).abs();
+    if cos_o <= N::zero() {
+        return N::zero();
+    }
+    distribution.d(wh) * g1(distribution, wo) * wo.dot(wh).max(N::zero()) / cos_o
+}
+
+/// Trowbridge-Reitz, or GGX, distribution. It has long tails, which give highlights a soft glow
+/// around them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GGX<T> {
+    alpha_x: T,
+    alpha_y: T,
+}
+
+/// Beckmann distribution, where facet slopes are normally distributed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Beckmann<T> {
+    alpha_x: T,
+    alpha_y: T,
+}
+
+impl<T: Float> GGX<T> {
+    /// Distribution that is equally rough in every direction.
+    pub fn new(alpha: T) -> GGX<T> {
+        GGX::anisotropic(alpha, alpha)
+    }
+
+    pub fn anisotropic(alpha_x: T, alpha_y: T) -> GGX<T> {
+        GGX {
+            alpha_x: clamp_alpha(alpha_x),
+            alpha_y: clamp_alpha(alpha_y),
+        }
+    }
+}
+
+impl<T: Float> Beckmann<T> {
+    /// Distribution that is equally rough in every direction.
+    pub fn new(alpha: T) -> Beckmann<T> {
+        Beckmann::anisotropic(alpha, alpha)
+    }
+
+    pub fn anisotropic(alpha_x: T, alpha_y: T) -> Beckmann<T> {
+        Beckmann {
+            alpha_x: clamp_alpha(alpha_x),
+            alpha_y: clamp_alpha(alpha_y),
+        }
+    }
+}
+
+/// Perfectly smooth surfaces would need an infinitely narrow distribution, so roughness is kept
+/// above a small minimum. Use `Mirror` or `Dielectric` for smooth surfaces instead.
+fn clamp_alpha<T: Float>(alpha: T) -> T {
+    alpha.max(T::from(1e-3))
+}
+
+impl<T: Float> MicrofacetDistribution for GGX<T> {
+    type NumTy = T;
+
+    fn d(&self, wh: &Vector3<T>) -> T {
+        let tan2 = tan2_theta(wh);
+        if !tan2.is_finite() {
+            return T::zero();
+        }
+        let cos2 = wh.z * wh.z;
+        let e = tan2 * slope_scale(wh, self.alpha_x, self.alpha_y);
+        T::one()
+            / (T::pi()
+                * self.alpha_x
+                * self.alpha_y
+                * cos2
+                * cos2
+                * (T::one() + e)
+                * (T::one() + e))
+    }
+
+    fn lambda(&self, w: &Vector3<T>) -> T {
+        let tan2 = tan2_theta(w);
+        if !tan2.is_finite() {
+            return T::zero();
+        }
+        let alpha2_tan2 = tan2 / slope_scale_inverse(w, self.alpha_x, self.alpha_y);
+        ((T::one() + alpha2_tan2).sqrt() - T::one()) / T::from(2.0)
+    }
+
+    /// Samples visible normals exactly, as described by Heitz in "Sampling the GGX Distribution
+    /// of Visible Normals".
+    fn sample_wh(&self, wo: &Vector3<T>, u1: T, u2: T) -> Vector3<T> {
+        let flip = wo.z < T::zero();
+        let wo = if flip { -wo } else { *wo };
+        // Stretch the view so the distribution becomes a hemisphere of radius 1.
+        let vh = Vector3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).normalize();
+        let len2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len2 > T::zero() {
+            Vector3::new(-vh.y, vh.x, T::zero()) / len2.sqrt()
+        } else {
+            Vector3::x()
+        };
+        let t2 = vh.cross(&t1);
+        // Uniform point on the projection of the visible half of the hemisphere.
+        let r = u1.sqrt();
+        let phi = T::two_pi() * u2;
+        let p1 = r * phi.cos();
+        let p2 = r * phi.sin();
+        let s = (T::one() + vh.z) / T::from(2.0);
+        let p2 = (T::one() - s) * (T::one() - p1 * p1).max(T::zero()).sqrt() + s * p2;
+        let p3 = (T::one() - p1 * p1 - p2 * p2).max(T::zero()).sqrt();
+        let nh = t1 * p1 + t2 * p2 + vh * p3;
+        let wh = Vector3::new(
+            self.alpha_x * nh.x,
+            self.alpha_y * nh.y,
+            nh.z.max(T::zero()),
+        )
+        .normalize();
+        if flip {
+            -wh
+        } else {
+            wh
+        }
+    }
+}
+
+impl<T: Float> MicrofacetDistribution for Beckmann<T> {
+    type NumTy = T;
+
+    fn d(&self, wh: &Vector3<T>) -> T {
+        let tan2 = tan2_theta(wh);
+        if !tan2.is_finite() {
+            return T::zero();
+        }
+        let cos2 = wh.z * wh.z;
+        (-tan2 * slope_scale(wh, self.alpha_x, self.alpha_y)).exp()
+            / (T::pi() * self.alpha_x * self.alpha_y * cos2 * cos2)
+    }
+
+    fn lambda(&self, w: &Vector3<T>) -> T {
+        let tan2 = tan2_theta(w);
+        if !tan2.is_finite() {
+            return T::zero();
+        }
+        let a = (slope_scale_inverse(w, self.alpha_x, self.alpha_y) / tan2).sqrt();
+        if a >= T::from(1.6) {
+            return T::zero();
+        }
+        // rational fit to the exact expression, which needs erf
+        let fit = (T::one() - T::from(1.259) * a + T::from(0.396) * a * a)
+            / (T::from(3.535) * a + T::from(2.181) * a * a);
+        fit.max(T::zero())
+    }
+
+    /// Samples visible normals by stretching the view to a unit roughness and sampling slopes
+    /// there, following Jakob's implementation of "Importance Sampling Microfacet-Based BSDFs
+    /// using the Distribution of Visible Normals" by Heitz and d'Eon.
+    fn sample_wh(&self, wo: &Vector3<T>, u1: T, u2: T) -> Vector3<T> {
+        let flip = wo.z < T::zero();
+        let wo = if flip { -wo } else { *wo };
+        let stretched = Vector3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).normalize();
+        let (slope_x, slope_y) = beckmann_sample_slopes(stretched.z, u1, u2);
+        // Turn the slopes to face the view, then unstretch them.
+        let (cos_phi, sin_phi) = cos_sin_phi(&stretched);
+        let turned_x = cos_phi * slope_x - sin_phi * slope_y;
+        let turned_y = sin_phi * slope_x + cos_phi * slope_y;
+        let wh =
+            Vector3::new(-turned_x * self.alpha_x, -turned_y * self.alpha_y, T::one()).normalize();
+        if flip {
+            -wh
+        } else {
+            wh
+        }
+    }
+}
+
+/// Slopes of a visible facet of a Beckmann surface with unit roughness, seen from a direction
+/// with cosine `cos_theta_o` to the normal and no Y component.
+fn beckmann_sample_slopes<T: Float>(cos_theta_o: T, u1: T, u2: T) -> (T, T) {
+    if cos_theta_o > T::from(0.9999) {
+        // seen straight on, every facet is visible
+        let r = (-(T::one() - u1).ln()).sqrt();
+        let phi = T::two_pi() * u2;
+        return (r * phi.cos(), r * phi.sin());
+    }
+
+    let sin_theta_o = (T::one() - cos_theta_o * cos_theta_o).max(T::zero()).sqrt();
+    let tan_theta_o = sin_theta_o / cos_theta_o;
+    let cot_theta_o = T::one() / tan_theta_o;
+    let inv_sqrt_pi = T::one() / T::pi().sqrt();
+
+    // Invert the cumulative distribution of the X slope with Newton's method, starting from a
+    // fitted guess. The root stays bracketed by [a, c].
+    let mut a = -T::one();
+    let mut c = erf(cot_theta_o);
+    let u = u1.max(T::from(1e-6));
+    let theta_o = cos_theta_o.acos();
+    let fit = T::one()
+        + theta_o * (T::from(-0.876) + theta_o * (T::from(0.4265) - T::from(0.0594) * theta_o));
+    let mut b = c - (T::one() + c) * (T::one() - u).powf(fit);
+    let normalization =
+        T::one() / (T::one() + c + inv_sqrt_pi * tan_theta_o * (-cot_theta_o * cot_theta_o).exp());
+    for _ in 0..10 {
+        if !(b >= a && b <= c) {
+            b = (a + c) / T::from(2.0);
+        }
+        let inv_erf = erf_inv(b);
+        let value = normalization
+            * (T::one() + b + inv_sqrt_pi * tan_theta_o * (-inv_erf * inv_erf).exp())
+            - u;
+        let derivative = normalization * (T::one() - inv_erf * tan_theta_o);
+        if value.abs() < T::from(1e-5) {
+            break;
+        }
+        if value > T::zero() {
+            c = b;
+        } else {
+            a = b;
+        }
+        b -= value / derivative;
+    }
+    let slope_y = erf_inv(T::from(2.0) * u2.max(T::from(1e-6)) - T::one());
+    (erf_inv(b), slope_y)
+}
+
+/// `tan²θ` of the local direction `w`.
+fn tan2_theta<T: Float>(w: &Vector3<T>) -> T {
+    let cos2 = w.z * w.z;
+    (T::one() - cos2).max(T::zero()) / cos2
+}
+
+/// Cosine and sine of the angle of `w` around the normal, from +X.
+fn cos_sin_phi<T: Float>(w: &Vector3<T>) -> (T, T) {
+    let sin_theta = (w.x * w.x + w.y * w.y).sqrt();
+    if sin_theta <= T::zero() {
+        return (T::one(), T::zero());
+    }
+    let clamp = |x: T| x.max(-T::one()).min(T::one());
+    (clamp(w.x / sin_theta), clamp(w.y / sin_theta))
+}
+
+/// `cos²φ/αx² + sin²φ/αy²`, which scales `tan²θ` to account for the roughness in the direction
+/// of `w`.
+fn slope_scale<T: Float>(w: &Vector3<T>, alpha_x: T, alpha_y: T) -> T {
+    let (cos_phi, sin_phi) = cos_sin_phi(w);
+    cos_phi * cos_phi / (alpha_x * alpha_x) + sin_phi * sin_phi / (alpha_y * alpha_y)
+}
+
+/// `1 / (cos²φ αx² + sin²φ αy²)`, the inverse squared roughness in the direction of `w`.
+fn slope_scale_inverse<T: Float>(w: &Vector3<T>, alpha_x: T, alpha_y: T) -> T {
+    let (cos_phi, sin_phi) = cos_sin_phi(w);
+    T::one() / (cos_phi * cos_phi * alpha_x * alpha_x + sin_phi * sin_phi * alpha_y * alpha_y)
+}
+
+/// Error function, with an absolute error below 1.5e-7 (Abramowitz and Stegun 7.1.26).
+fn erf<T: Float>(x: T) -> T {
+    let sign = if x < T::zero() { -T::one() } else { T::one() };
+    let x = x.abs();
+    let t = T::one() / (T::one() + T::from(0.327_591_1) * x);
+    let poly = ((((T::from(1.061_405_4) * t + T::from(-1.453_152)) * t + T::from(1.421_413_8))
+        * t
+        + T::from(-0.284_496_72))
+        * t
+        + T::from(0.254_829_6))
+        * t;
+    sign * (T::one() - poly * (-x * x).exp())
+}
+
+/// Inverse of `erf`, using the single precision approximation by Giles.
+fn erf_inv<T: Float>(x: T) -> T {
+    let limit = T::from(0.99999);
+    let x = x.max(-limit).min(limit);
+    let w = -((T::one() - x) * (T::one() + x)).ln();
+    let (w, coefficients): (T, &[f32]) = if w < T::from(5.0) {
+        (
+            w - T::from(2.5),
+            &[
+                2.810_226_4e-8,
+                3.432_739_4e-7,
+                -3.523_387_7e-6,
+                -4.391_506_5e-6,
+                2.185_808_7e-4,
+                -1.253_725e-3,
+                -4.177_681_6e-3,
+                2.466_407_3e-1,
+                1.501_409_4,
+            ],
+        )
+    } else {
+        (
+            w.sqrt() - T::from(3.0),
+            &[
+                -2.002_142_6e-4,
+                1.009_505_6e-4,
+                1.349_343_2e-3,
+                -3.673_428_4e-3,
+                5.739_507_7e-3,
+                -7.622_461_3e-3,
+                9.438_870_5e-3,
+                1.001_674,
+                2.832_976_8,
+            ],
+        )
+    };
+    let p = coefficients
+        .iter()
+        .fold(T::zero(), |p, &c| p * w + T::from(c));
+    p * x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::ray::tests::st_vec3;
+    use proptest::prelude::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+    use std::f64::consts::PI;
+
+    /// Integrates `f` over the hemisphere above the surface, on a grid in spherical coordinates.
+    fn integrate_hemisphere<F: Fn(&Vector3<f64>) -> f64>(f: F) -> f64 {
+        let steps = 400;
+        let d_theta = PI / 2.0 / f64::from(steps);
+        let d_phi = 2.0 * PI / f64::from(steps);
+        let mut total = 0.0;
+        for i in 0..steps {
+            let theta = (f64::from(i) + 0.5) * d_theta;
+            for j in 0..steps {
+                let phi = (f64::from(j) + 0.5) * d_phi;
+                let w = Vector3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                total += f(&w) * theta.sin() * d_theta * d_phi;
+            }
+        }
+        total
+    }
+
+    #[test]
+    fn projected_facet_area_is_surface_area() {
+        let distributions: Vec<Box<dyn MicrofacetDistribution<NumTy = f64>>> = vec![
+            Box::new(GGX::new(0.3)),
+            Box::new(GGX::anisotropic(0.2, 0.5)),
+            Box::new(Beckmann::new(0.3)),
+            Box::new(Beckmann::anisotropic(0.2, 0.5)),
+        ];
+        for distribution in &distributions {
+            let area = integrate_hemisphere(|wh| distribution.d(wh) * wh.z);
+            assert!((area - 1.0).abs() < 0.01, "area {}", area);
+            // the same holds for the facets visible from any one direction
+            let wo = Vector3::new(0.6, 0.3, 0.5).normalize();
+            let visible = integrate_hemisphere(|wh| pdf_wh(&**distribution, &wo, wh));
+            assert!((visible - 1.0).abs() < 0.01, "visible {}", visible);
+        }
+    }
+
+    #[test]
+    fn samples_follow_pdf() {
+        // The average sampled normal matches the average under the pdf.
+        let distributions: Vec<Box<dyn MicrofacetDistribution<NumTy = f64>>> = vec![
+            Box::new(GGX::anisotropic(0.6, 0.9)),
+            Box::new(Beckmann::anisotropic(0.6, 0.9)),
+        ];
+        let mut rng = SmallRng::seed_from_u64(1);
+        let wo = Vector3::new(0.3, -0.2, 0.9).normalize();
+        for distribution in &distributions {
+            let samples = 100_000;
+            let mut total = Vector3::zeros();
+            for _ in 0..samples {
+                let wh = distribution.sample_wh(&wo, rng.gen(), rng.gen());
+                assert!(wh.z >= 0.0 && (wh.magnitude() - 1.0).abs() < 1e-6);
+                assert!(wo.dot(&wh) >= -1e-6, "sampled a facet facing away");
+                total += wh;
+            }
+            let average = total / f64::from(samples);
+            let pdf = |wh: &Vector3<f64>| pdf_wh(&**distribution, &wo, wh);
+            let expected = Vector3::new(
+                integrate_hemisphere(|wh| wh.x * pdf(wh)),
+                integrate_hemisphere(|wh| wh.y * pdf(wh)),
+                integrate_hemisphere(|wh| wh.z * pdf(wh)),
+            );
+            assert!(
+                (average - expected).magnitude() < 0.01,
+                "average {} expected {}",
+                average,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn erf_inv_inverts_erf() {
+        for &x in &[-2.0f64, -0.7, 0.0, 0.1, 1.3] {
+            assert!((erf_inv(erf(x)) - x).abs() < 1e-3, "x {}", x);
+        }
+        assert!((erf(1f64) - 0.842_700_79).abs() < 1e-6);
+    }
+
+    proptest! {
+        #[test]
+        fn masking_is_a_fraction(w in st_vec3(-1f64..1.0), alpha in 0.01f64..2.0) {
+            prop_assume!(w.magnitude() > 0.01);
+            let w = w.normalize();
+            let ggx = GGX::anisotropic(alpha, 0.5);
+            let beckmann = Beckmann::anisotropic(alpha, 0.5);
+            for fraction in &[g1(&ggx, &w), g1(&beckmann, &w)] {
+                prop_assert!(*fraction > 0.0 && *fraction <= 1.0);
+            }
+            prop_assert!(g(&ggx, &w, &w) <= g1(&ggx, &w));
+        }
+    }
+}
@@ -1,12 +1,26 @@
+pub mod aabb;
+pub mod bump;
+mod bvh;
 pub mod camera;
-mod light;
+pub mod environment;
+pub mod filter;
+pub mod frame;
+pub mod light;
 pub mod material;
-mod plane;
+mod mesh;
+pub mod microfacet;
+pub mod mtl;
+pub mod plane;
 pub mod ray;
 pub mod renderable;
 pub mod renderer;
+mod sampling;
 pub mod scene;
 pub mod shape;
 pub mod spectrum;
 pub mod sphere;
+#[cfg(test)]
+mod temp_dir;
+pub mod texture;
 pub mod triangle;
+mod triangulate;
@@ -0,0 +1,443 @@
+use super::material::{Dielectric, Dissolved, Mirror, Phong, SharedBsdf, TexturedPhong};
+use super::spectrum::Spec;
+use super::texture::ScaledTexture;
+use crate::number::Float;
+use std::io::{self, BufRead};
+use std::sync::Arc;
+
+/// Material read from a Wavefront MTL file.
+///
+/// Only the statements the renderer understands are kept. Anything else in the file, such as
+/// specular maps, is skipped rather than rejected, since most exporters write far more than we use.
+/// Options of the texture maps that are kept are read too, see `TextureMap`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MtlMaterial {
+    pub name: String,
+    /// Diffuse color, `Kd`.
+    pub kd: Option<[f32; 3]>,
+    /// Specular color, `Ks`.
+    pub ks: Option<[f32; 3]>,
+    /// Emitted color, `Ke`.
+    pub ke: Option<[f32; 3]>,
+    /// Specular exponent, `Ns`.
+    pub ns: Option<f32>,
+    /// Index of refraction, `Ni`.
+    pub ni: Option<f32>,
+    /// Opacity, `d`. `Tr` is read as `1 - d`.
+    pub d: Option<f32>,
+    /// Illumination model, `illum`.
+    pub illum: Option<i32>,
+    /// Image of the diffuse color, `map_Kd`.
+    pub map_kd: Option<TextureMap>,
+    /// Height map, `map_Bump` or `bump`.
+    pub map_bump: Option<TextureMap>,
+    /// Tangent space normal map, `norm`.
+    pub norm: Option<TextureMap>,
+}
+
+/// Image named by a texture map statement, such as `map_Kd`, and the options given with it.
+///
+/// Options that only hint at how to filter the image, `-blendu`, `-blendv`, `-boost` and
+/// `-texres`, are skipped. Any others that change how the map looks, like `-o` and `-s`, are
+/// rejected rather than silently dropped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureMap {
+    /// Path of the image, relative to the MTL file.
+    pub file: String,
+    /// `-clamp on` stretches the edge pixels outwards, rather than tiling the image.
+    pub clamp: bool,
+    /// Scale of a bump or normal map, from `-bm`.
+    pub bump_multiplier: f32,
+}
+
+impl TextureMap {
+    pub fn new(file: String) -> TextureMap {
+        TextureMap {
+            file,
+            clamp: false,
+            bump_multiplier: 1.0,
+        }
+    }
+}
+
+/// Diffuse color of materials that don't give one, and of faces with no material at all.
+const DEFAULT_DIFFUSE: f32 = 0.8;
+
+/// Index of refraction of transparent materials that don't give one, about that of glass.
+const DEFAULT_IOR: f32 = 1.5;
+
+impl MtlMaterial {
+    pub fn new(name: String) -> MtlMaterial {
+        MtlMaterial {
+            name,
+            kd: None,
+            ks: None,
+            ke: None,
+            ns: None,
+            ni: None,
+            d: None,
+            illum: None,
+            map_kd: None,
+            map_bump: None,
+            norm: None,
+        }
+    }
+
+    /// Material used for faces that have no `usemtl` statement, or name a material that
+    /// doesn't exist. It is a plain light grey diffuse surface.
+    pub fn default_material() -> MtlMaterial {
+        MtlMaterial::new("default".to_string())
+    }
+
+    /// Builds the BSDF described by the material.
+    ///
+    /// Illumination models 0 and 1 have no highlights, so `Ks` is ignored for them. Model 5 is a
+    /// perfect mirror tinted by `Ks`, and models 4, 6 and 7 are clear glass with index of
+    /// refraction `Ni`. A dissolve `d` below 1 lets the rest of the light pass straight through
+    /// the surface.
+    pub fn to_bsdf<N: Float>(&self) -> SharedBsdf<N> {
+        let bsdf: SharedBsdf<N> = match self.illum {
+            Some(4) | Some(6) | Some(7) => {
+                let white = to_spec([1.0; 3]);
+                let ior = N::from(self.ni.unwrap_or(DEFAULT_IOR));
+                Arc::new(Dielectric::new(white.clone(), white, ior))
+            }
+            Some(5) => Arc::new(Mirror::new(to_spec(self.ks.unwrap_or([1.0; 3])))),
+            _ => Arc::new(self.to_phong()),
+        };
+        match self.opacity() {
+            opacity if opacity < N::one() => Arc::new(Dissolved::new(bsdf, opacity)),
+            _ => bsdf,
+        }
+    }
+
+    /// Like `to_bsdf`, but with the diffuse color given by `texture`, the `map_Kd` image,
+    /// multiplied by `Kd`. Materials with no `Kd` show the image as it is.
+    pub fn to_textured_phong<N: Float, T>(
+        &self,
+        texture: T,
+    ) -> TexturedPhong<ScaledTexture<T, Spec<N>>, N> {
+        let tint = to_spec(self.kd.unwrap_or([1.0; 3]));
+        TexturedPhong::new(
+            ScaledTexture::new(texture, tint),
+            self.specular(),
+            self.exponent(),
+        )
+        .with_opacity(self.opacity())
+    }
+
+    /// The `map_Kd` image, for the illumination models that have a diffuse color.
+    pub fn diffuse_texture(&self) -> Option<&TextureMap> {
+        match self.illum {
+            Some(4..=7) => None,
+            _ => self.map_kd.as_ref(),
+        }
+    }
+
+    fn to_phong<N: Float>(&self) -> Phong<N> {
+        let diffuse = to_spec(self.kd.unwrap_or([DEFAULT_DIFFUSE; 3]));
+        Phong::new(diffuse, self.specular(), self.exponent())
+    }
+
+    fn specular<N: Float>(&self) -> Spec<N> {
+        match self.illum {
+            Some(0) | Some(1) => to_spec([0.0; 3]),
+            _ => to_spec(self.ks.unwrap_or([0.0; 3])),
+        }
+    }
+
+    /// Fraction of the light the surface stops, from the dissolve `d`.
+    fn opacity<N: Float>(&self) -> N {
+        N::from(self.d.unwrap_or(1.0))
+    }
+
+    fn exponent<N: Float>(&self) -> N {
+        N::from(self.ns.unwrap_or(0.0).max(0.0))
+    }
+
+    /// Light given off by the material, from `Ke`. Materials with no `Ke`, or a black one,
+    /// don't glow.
+    pub fn emission<N: Float>(&self) -> Option<Spec<N>> {
+        match self.ke {
+            Some(ke) if ke.iter().any(|&c| c > 0.0) => Some(to_spec(ke)),
+            _ => None,
+        }
+    }
+}
+
+fn to_spec<N: Float>(color: [f32; 3]) -> Spec<N> {
+    Spec::new(N::from(color[0]), N::from(color[1]), N::from(color[2]))
+}
+
+/// Reads every material from an MTL file.
+///
+/// Unknown statements are ignored. A known statement with a missing or malformed value is an
+/// `InvalidData` error.
+pub fn parse_mtl<B: BufRead>(input: B) -> io::Result<Vec<MtlMaterial>> {
+    let mut materials = vec![];
+    let mut current: Option<MtlMaterial> = None;
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid mtl statement on line {}: {}", index + 1, line),
+            )
+        };
+        if keyword == "newmtl" {
+            materials.extend(current.take());
+            let name = words.next().ok_or_else(invalid)?;
+            current = Some(MtlMaterial::new(name.to_string()));
+            continue;
+        }
+        // statements before the first `newmtl` have no material to apply to
+        let material = match current.as_mut() {
+            Some(material) => material,
+            None => continue,
+        };
+        let values: Vec<&str> = words.collect();
+        match keyword {
+            "Kd" => material.kd = Some(parse_color(&values).ok_or_else(invalid)?),
+            "Ks" => material.ks = Some(parse_color(&values).ok_or_else(invalid)?),
+            "Ke" => material.ke = Some(parse_color(&values).ok_or_else(invalid)?),
+            "Ns" => material.ns = Some(parse_value(&values).ok_or_else(invalid)?),
+            "Ni" => material.ni = Some(parse_value(&values).ok_or_else(invalid)?),
+            "d" => material.d = Some(parse_value(&values).ok_or_else(invalid)?),
+            "Tr" => material.d = Some(1.0 - parse_value::<f32>(&values).ok_or_else(invalid)?),
+            "illum" => material.illum = Some(parse_value(&values).ok_or_else(invalid)?),
+            "map_Kd" => material.map_kd = Some(parse_texture_map(&values).ok_or_else(invalid)?),
+            "map_Bump" | "map_bump" | "bump" => {
+                material.map_bump = Some(parse_texture_map(&values).ok_or_else(invalid)?)
+            }
+            "norm" => material.norm = Some(parse_texture_map(&values).ok_or_else(invalid)?),
+            _ => {}
+        }
+    }
+    materials.extend(current);
+    Ok(materials)
+}
+
+/// Parses the options and file name of a texture map statement. Options come before the file
+/// name, as in `map_Kd -clamp on wood.png`.
+fn parse_texture_map(values: &[&str]) -> Option<TextureMap> {
+    let mut clamp = false;
+    let mut bump_multiplier = 1.0;
+    let mut rest = values;
+    loop {
+        match rest {
+            ["-clamp", on, tail @ ..] => {
+                clamp = match *on {
+                    "on" => true,
+                    "off" => false,
+                    _ => return None,
+                };
+                rest = tail;
+            }
+            ["-bm", multiplier, tail @ ..] => {
+                bump_multiplier = multiplier.parse().ok()?;
+                rest = tail;
+            }
+            // filtering hints, which make no difference here
+            ["-blendu", _, tail @ ..]
+            | ["-blendv", _, tail @ ..]
+            | ["-boost", _, tail @ ..]
+            | ["-texres", _, tail @ ..] => rest = tail,
+            [file] if !file.starts_with('-') => {
+                return Some(TextureMap {
+                    file: file.to_string(),
+                    clamp,
+                    bump_multiplier,
+                })
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Parses the first value of a statement.
+fn parse_value<T: std::str::FromStr>(values: &[&str]) -> Option<T> {
+    values.first()?.parse().ok()
+}
+
+/// Parses an RGB color. A single value is used for all three channels, as the format allows.
+fn parse_color(values: &[&str]) -> Option<[f32; 3]> {
+    let parsed = values
+        .iter()
+        .map(|v| v.parse::<f32>().ok())
+        .collect::<Option<Vec<f32>>>()?;
+    match parsed.as_slice() {
+        [v] => Some([*v; 3]),
+        [r, g, b] => Some([*r, *g, *b]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::abs_diff_eq;
+
+    const EXAMPLE: &str = "
+# exported material
+newmtl red
+Ka 0.1 0.1 0.1
+Kd 0.8 0.1 0.1
+Ks 0.5 0.5 0.5
+Ns 32
+illum 2
+map_Kd red.png
+map_Bump -bm 0.05 red_height.png
+
+newmtl lamp
+Kd 1
+Ke 4 4 3
+Ni 1.5
+Tr 0.25
+illum 1
+norm -bm 2 lamp_normal.png
+";
+
+    #[test]
+    fn parses_materials() {
+        let materials = parse_mtl(EXAMPLE.as_bytes()).unwrap();
+        assert_eq!(materials.len(), 2);
+
+        let red = &materials[0];
+        assert_eq!(red.name, "red");
+        assert_eq!(red.kd, Some([0.8, 0.1, 0.1]));
+        assert_eq!(red.ks, Some([0.5, 0.5, 0.5]));
+        assert_eq!(red.ns, Some(32.0));
+        assert_eq!(red.illum, Some(2));
+        assert_eq!(red.ke, None);
+        assert_eq!(red.map_kd, Some(TextureMap::new("red.png".to_string())));
+        assert_eq!(red.diffuse_texture(), red.map_kd.as_ref());
+        let bump = red.map_bump.as_ref().unwrap();
+        assert_eq!(bump.file, "red_height.png");
+        assert_eq!(bump.bump_multiplier, 0.05);
+        assert_eq!(red.norm, None);
+
+        let lamp = &materials[1];
+        assert_eq!(lamp.kd, Some([1.0, 1.0, 1.0]));
+        assert_eq!(lamp.ke, Some([4.0, 4.0, 3.0]));
+        assert_eq!(lamp.ni, Some(1.5));
+        assert_eq!(lamp.d, Some(0.75));
+        assert_eq!(lamp.illum, Some(1));
+        let norm = lamp.norm.as_ref().unwrap();
+        assert_eq!(norm.file, "lamp_normal.png");
+        assert_eq!(norm.bump_multiplier, 2.0);
+        assert!(!norm.clamp);
+    }
+
+    #[test]
+    fn only_lit_materials_emit() {
+        let materials = parse_mtl(EXAMPLE.as_bytes()).unwrap();
+        assert!(materials[0].emission::<f64>().is_none());
+        let lamp = materials[1].emission::<f64>().expect("lamp glows");
+        assert_eq!((lamp.red, lamp.green, lamp.blue), (4.0, 4.0, 3.0));
+
+        let mut black = MtlMaterial::new("black".to_string());
+        black.ke = Some([0.0; 3]);
+        assert!(black.emission::<f64>().is_none());
+    }
+
+    #[test]
+    fn malformed_value_is_an_error() {
+        let err = parse_mtl("newmtl a\nKd 0.5 red 0.5\n".as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(parse_mtl("newmtl a\nNs\n".as_bytes()).is_err());
+        assert!(parse_mtl("newmtl a\nbump -bm high.png\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn texture_map_options() {
+        let parse = |line: &str| {
+            let mtl = format!("newmtl a\n{}\n", line);
+            parse_mtl(mtl.as_bytes()).map(|m| m[0].map_kd.clone().unwrap())
+        };
+        let map = parse("map_Kd -blendu off -clamp on -texres 512 wood.png").unwrap();
+        assert_eq!(map.file, "wood.png");
+        assert!(map.clamp);
+        assert!(!parse("map_Kd -clamp off wood.png").unwrap().clamp);
+        // options that would change the image aren't ignored
+        assert!(parse("map_Kd -o 0.5 0.5 wood.png").is_err());
+        assert!(parse("map_Kd -s 2 2 1 wood.png").is_err());
+        assert!(parse("map_Kd -clamp maybe wood.png").is_err());
+        assert!(parse("map_Kd -clamp on").is_err());
+    }
+
+    #[test]
+    fn diffuse_only_models_drop_specular() {
+        use super::super::material::BSDF;
+        use na::Vector3;
+        use std::f64::consts::PI;
+
+        let z = Vector3::z();
+        let mut material = MtlMaterial::new("a".to_string());
+        material.ks = Some([1.0, 1.0, 1.0]);
+        material.ns = Some(10.0);
+        // looking straight down the mirror direction sees the diffuse color plus the highlight
+        let highlight = material.to_bsdf().f(&z, &z) * PI;
+        assert!(abs_diff_eq!(
+            highlight.red,
+            DEFAULT_DIFFUSE as f64 + 6.0,
+            epsilon = 1e-6
+        ));
+        material.illum = Some(1);
+        let diffuse = material.to_bsdf().f(&z, &z) * PI;
+        assert!(abs_diff_eq!(
+            diffuse.red,
+            DEFAULT_DIFFUSE as f64,
+            epsilon = 1e-6
+        ));
+    }
+
+    #[test]
+    fn dissolve_lets_light_through() {
+        use super::super::material::{BsdfFlags, BSDF};
+        use na::Vector3;
+        use std::f64::consts::PI;
+
+        let materials = parse_mtl(EXAMPLE.as_bytes()).unwrap();
+        let z = Vector3::z();
+        // `Tr 0.25` leaves three quarters of the lamp's diffuse color
+        let lamp = materials[1].to_bsdf();
+        assert!(abs_diff_eq!(lamp.f(&z, &z).red * PI, 0.75, epsilon = 1e-6));
+        let through = lamp.sample_f(&z, 0.9, 0.5).unwrap();
+        assert!(through.flags.contains(BsdfFlags::TRANSMISSION));
+        assert_eq!(through.wi, -z);
+        // opaque materials stop everything
+        let red = materials[0].to_bsdf();
+        for &u1 in &[0.1, 0.5, 0.9] {
+            let sample = red.sample_f(&z, u1, 0.5).unwrap();
+            assert!(!sample.flags.contains(BsdfFlags::TRANSMISSION));
+        }
+    }
+
+    #[test]
+    fn illum_picks_specular_materials() {
+        use super::super::material::{BsdfFlags, BSDF};
+        use na::Vector3;
+
+        let wo = Vector3::new(0.6, 0.0, 0.8);
+        let mut material = MtlMaterial::new("a".to_string());
+        material.illum = Some(5);
+        let mirror = material.to_bsdf().sample_f(&wo, 0.99, 0.5).unwrap();
+        assert!(mirror
+            .flags
+            .contains(BsdfFlags::REFLECTION | BsdfFlags::SPECULAR));
+
+        for &illum in &[4, 6, 7] {
+            material.illum = Some(illum);
+            material.ni = Some(1.33);
+            let glass = material.to_bsdf().sample_f(&wo, 0.99, 0.5).unwrap();
+            assert!(glass.flags.contains(BsdfFlags::TRANSMISSION));
+            // Snell's law with the index of refraction of water
+            assert!(abs_diff_eq!(glass.wi.x, -0.6 / 1.33, epsilon = 1e-6));
+        }
+    }
+}
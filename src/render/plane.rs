@@ -1,5 +1,11 @@
-use na::{Point3, RealField, Scalar, Vector3};
+use super::aabb::Aabb;
+use super::ray::Ray;
+use super::sampling::coordinate_system;
+use super::shape::{Shape, SurfaceInteraction};
+use crate::number::Float;
+use na::{Point2, Point3, RealField, Scalar, Vector3};
 
+/// Infinite plane, the set of points `p` where `normal . p + dist = 0`.
 pub struct Plane<T: Scalar> {
     normal: Vector3<T>,
     dist: T,
@@ -23,6 +29,51 @@ impl<T: RealField> Plane<T> {
     }
 }
 
+impl<N: Float> Shape for Plane<N> {
+    type NumTy = N;
+
+    fn bounds(&self) -> Aabb<N> {
+        Aabb::unbounded()
+    }
+
+    /// The surface coordinates of the hit are its position along two axes in the plane.
+    fn intersection(&self, ray: &Ray<N>) -> Option<SurfaceInteraction<N>> {
+        let denominator = self.normal.dot(&ray.direction);
+        if denominator == N::zero() {
+            // parallel to the plane
+            return None;
+        }
+        let t = -(self.dist + self.normal.dot(&ray.origin.coords)) / denominator;
+        if !ray.contains(t) {
+            return None;
+        }
+        let normal_length_sq = self.normal.magnitude_squared();
+        let point = ray.at_time(t);
+        // move the point onto the plane, which leaves less error than the ray equation
+        let distance = self.dist + self.normal.dot(&point.coords);
+        let point = point - self.normal * (distance / normal_length_sq);
+        let normal_length = normal_length_sq.sqrt();
+        let projection_error = (point.coords.abs().dot(&self.normal.abs()) + self.dist.abs())
+            / normal_length
+            * N::gamma_bound(5);
+        let point_error =
+            Vector3::repeat(projection_error) + point.coords.abs() * N::gamma_bound(1);
+
+        let normal = self.normal / normal_length;
+        let (dpdu, dpdv) = coordinate_system(&normal);
+        let uv = Point2::new(point.coords.dot(&dpdu), point.coords.dot(&dpdv));
+        Some(SurfaceInteraction::new(
+            t,
+            point,
+            point_error,
+            normal,
+            uv,
+            dpdu,
+            dpdv,
+        ))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -48,6 +99,19 @@ mod tests {
         assert!(abs_diff_eq!(dist, 0.0));
     }
 
+    #[test]
+    fn intersection_test() {
+        let plane: Plane<f64> = Plane::new_at_point(Point3::new(0.0, -1.0, 0.0), Vector3::y());
+        let down = Ray::new_normalize(Point3::new(3.0, 1.0, 0.0), vec3(0.0, -1.0, 0.0));
+        let hit = plane.intersection(&down).expect("ray points at the plane");
+        assert!(abs_diff_eq!(hit.t, 2.0));
+
+        let up = Ray::new_normalize(Point3::new(3.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0));
+        assert!(plane.intersection(&up).is_none());
+        let parallel = Ray::new_normalize(Point3::new(3.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0));
+        assert!(plane.intersection(&parallel).is_none());
+    }
+
     #[test]
     fn new_at_point_test() {
         let pos = Point3::new(100.0, 50.0, 25.2);
@@ -1,26 +1,42 @@
 use crate::vector::*;
 use na::*;
 
-#[derive(Debug)]
+/// Half line starting at `origin`. Only points at times in `(tmin, tmax]` are part of the ray,
+/// and shapes ignore intersections outside that interval. The start is left out, so a ray
+/// starting exactly on a surface doesn't hit that surface.
+#[derive(Debug, Clone)]
 pub struct Ray<T: Scalar> {
     pub origin: Point3<T>,
     pub direction: UnitVec3<T>,
-}
-
-impl<T: Scalar> Ray<T> {
-    pub fn new(origin: Point3<T>, direction: UnitVec3<T>) -> Ray<T> {
-        Ray { origin, direction }
-    }
+    pub tmin: T,
+    pub tmax: T,
 }
 
 impl<T: RealField> Ray<T> {
-    pub fn new_normalize(origin: Point3<T>, direction: Vector3<T>) -> Ray<T> {
+    /// A ray covering every time after 0.
+    pub fn new(origin: Point3<T>, direction: UnitVec3<T>) -> Ray<T> {
         Ray {
             origin,
-            direction: Unit::new_normalize(direction),
+            direction,
+            tmin: T::zero(),
+            tmax: T::max_value(),
         }
     }
 
+    pub fn new_normalize(origin: Point3<T>, direction: Vector3<T>) -> Ray<T> {
+        Ray::new(origin, Unit::new_normalize(direction))
+    }
+
+    /// Limits the ray to times in `(tmin, tmax]`.
+    pub fn with_interval(self, tmin: T, tmax: T) -> Ray<T> {
+        Ray { tmin, tmax, ..self }
+    }
+
+    /// Returns true if time `t` is inside the ray's interval.
+    pub fn contains(&self, t: T) -> bool {
+        t > self.tmin && t <= self.tmax
+    }
+
     pub fn closest_point(&self, p: Point3<T>) -> Point3<T> {
         let p_trans = p - self.origin;
         let mag: T = self.direction.dot(&p_trans) / self.direction.magnitude_squared();
@@ -58,13 +74,10 @@ pub mod tests {
         st_vec3(st).prop_map(Point3::from)
     }
 
-    pub fn arb_ray<T: RealField>(
+    pub fn arb_ray<T: RealField + Arbitrary>(
         orig_st: impl Strategy<Value = T> + Clone,
         dir_st: impl Strategy<Value = T> + Clone,
-    ) -> impl Strategy<Value = Ray<T>>
-    where
-        T: Arbitrary,
-    {
+    ) -> impl Strategy<Value = Ray<T>> {
         (arb_point(orig_st), st_vec3(dir_st)).prop_map(|(o, d)| Ray::new_normalize(o, d))
     }
 
@@ -72,7 +85,7 @@ pub mod tests {
         #[test]
         fn closest_origin(point in arb_point(-1000f64..1000f64)) {
             let v0 = Point3::new(0.0, 0.0, 0.0);
-            let r: Ray<f64> = Ray::new_normalize(v0, (point - v0));
+            let r: Ray<f64> = Ray::new_normalize(v0, point - v0);
             let closest = r.closest_point(point);
             let diff = (point-closest).magnitude();
             prop_assert!(diff < DELTA, "diff = {}", diff);
@@ -1,11 +1,56 @@
-pub use super::material::Material;
+pub use super::material::{Material, BSDF};
+use super::aabb::Aabb;
 use super::ray::Ray;
-pub use super::shape::{DiffGeom, Shape};
-use na::{Point3, Vector3};
+pub use super::shape::{DiffGeom, Shape, SurfaceInteraction, SurfaceSample};
+use super::spectrum::Spec;
+use na::Scalar;
 
 /// This trait defines what the requirements to be renderable.
 /// This is essentially a combination of the `Shape` and `Material` traits
-pub trait Renderable: Shape + Material<NumTy = <Self as Shape>::NumTy> { }
+pub trait Renderable: Shape + Material<NumTy = <Self as Shape>::NumTy> {}
+
+/// Object safe form of `Renderable`, so that objects with different shapes and materials can be
+/// stored together as `Box<dyn SceneObject<N>>`. Objects are shared between render threads, so
+/// they must be `Send` and `Sync`.
+/// This is implemented for every `Renderable` whose material returns a `BSDF`.
+pub trait SceneObject<N: Scalar>: Shape<NumTy = N> + Send + Sync {
+    /// Same as `Material::get_bsdf`, with the `BSDF` boxed.
+    fn boxed_bsdf(&self, g: &DiffGeom<N>) -> Box<dyn BSDF<NumTy = N>>;
+
+    /// Same as `Material::shading_geometry`.
+    fn shading_geometry(&self, g: DiffGeom<N>) -> DiffGeom<N>;
+
+    /// Same as `Material::emission`.
+    fn emission(&self) -> Option<Spec<N>>;
+
+    /// Same as `Material::opacity`.
+    fn opacity(&self) -> Option<N>;
+}
+
+impl<R> SceneObject<<R as Shape>::NumTy> for R
+where
+    R: Renderable + Send + Sync,
+    R::BSDF_fn: BSDF<NumTy = <R as Shape>::NumTy> + 'static,
+{
+    fn boxed_bsdf(
+        &self,
+        g: &DiffGeom<<R as Shape>::NumTy>,
+    ) -> Box<dyn BSDF<NumTy = <R as Shape>::NumTy>> {
+        Box::new(self.get_bsdf(g))
+    }
+
+    fn shading_geometry(&self, g: DiffGeom<<R as Shape>::NumTy>) -> DiffGeom<<R as Shape>::NumTy> {
+        Material::shading_geometry(self, g)
+    }
+
+    fn emission(&self) -> Option<Spec<<R as Shape>::NumTy>> {
+        Material::emission(self)
+    }
+
+    fn opacity(&self) -> Option<<R as Shape>::NumTy> {
+        Material::opacity(self)
+    }
+}
 
 pub struct ShapeMat<S, M> {
     shape: S,
@@ -21,12 +66,16 @@ impl<S, M> ShapeMat<S, M> {
 impl<S: Shape, M> Shape for ShapeMat<S, M> {
     type NumTy = S::NumTy;
 
-    fn intersection(&self, ray: &Ray<Self::NumTy>) -> Option<Self::NumTy> {
+    fn bounds(&self) -> Aabb<Self::NumTy> {
+        self.shape.bounds()
+    }
+
+    fn intersection(&self, ray: &Ray<Self::NumTy>) -> Option<SurfaceInteraction<Self::NumTy>> {
         self.shape.intersection(ray)
     }
 
-    fn normal(&self, point: &Point3<Self::NumTy>) -> Vector3<Self::NumTy> {
-        self.shape.normal(point)
+    fn sample(&self, u1: Self::NumTy, u2: Self::NumTy) -> Option<SurfaceSample<Self::NumTy>> {
+        self.shape.sample(u1, u2)
     }
 }
 
@@ -37,6 +86,18 @@ impl<S: Shape, M: Material<NumTy = <Self as Shape>::NumTy>> Material for ShapeMa
     fn get_bsdf(&self, g: &DiffGeom<Self::NumTy>) -> Self::BSDF_fn {
         self.material.get_bsdf(g)
     }
+
+    fn shading_geometry(&self, g: DiffGeom<Self::NumTy>) -> DiffGeom<Self::NumTy> {
+        self.material.shading_geometry(g)
+    }
+
+    fn emission(&self) -> Option<Spec<Self::NumTy>> {
+        self.material.emission()
+    }
+
+    fn opacity(&self) -> Option<Self::NumTy> {
+        self.material.opacity()
+    }
 }
 
 impl<S, M> Renderable for ShapeMat<S, M>
@@ -48,17 +109,67 @@ where
 #[cfg(test)]
 mod tests {
     use super::super::material::{Lambert, UniformMaterial};
+    use super::super::plane::Plane;
+    use super::super::scene::Scene;
+    use super::super::sphere::Sphere;
     use super::super::triangle::Triangle;
     use super::*;
-    use crate::number::*;
+    use na::{Point3, Vector3};
+
+    #[test]
+    fn shapes_are_scene_objects() {
+        // a sphere in front of a plane in front of a triangle, along +z
+        let objects: Vec<Box<dyn SceneObject<f64>>> = vec![
+            Box::new(ShapeMat::new(
+                Sphere::new(Point3::new(0.0, 0.0, 2.0), 1.0),
+                UniformMaterial::new(Lambert::new(1.0, 0.0, 0.0)),
+            )),
+            Box::new(ShapeMat::new(
+                Plane::new(Vector3::z(), -5.0),
+                UniformMaterial::new(Lambert::new(0.0, 1.0, 0.0)),
+            )),
+            Box::new(ShapeMat::new(
+                Triangle::new(
+                    Vector3::new(-1.0, -1.0, 8.0),
+                    Vector3::new(1.0, -1.0, 8.0),
+                    Vector3::new(0.0, 1.0, 8.0),
+                ),
+                UniformMaterial::new(Lambert::new(0.0, 0.0, 1.0)),
+            )),
+        ];
+        let mut scene = Scene::empty();
+        scene.add_objects(objects);
+
+        // (ray start, expected object, distance to it, its albedo)
+        let cases = [
+            (0.0, 0, 1.0, Spec::new(1.0, 0.0, 0.0)),
+            (4.0, 1, 1.0, Spec::new(0.0, 1.0, 0.0)),
+            (6.0, 2, 2.0, Spec::new(0.0, 0.0, 1.0)),
+        ];
+        let z = Vector3::z();
+        for (start, id, t, albedo) in cases.iter() {
+            let ray = Ray::new_normalize(Point3::new(0.0, 0.0, *start), Vector3::z());
+            let (object, hit) = scene
+                .intersects_renderable(&ray)
+                .expect("ray hits an object");
+            assert_eq!(hit.primitive_id, *id);
+            assert!((hit.t - t).abs() < 1e-10);
+            let f = object.boxed_bsdf(&DiffGeom::from(&hit)).f(&z, &z) * std::f64::consts::PI;
+            assert!((f.red - albedo.red).abs() < 1e-10);
+            assert!((f.green - albedo.green).abs() < 1e-10);
+            assert!((f.blue - albedo.blue).abs() < 1e-10);
+        }
+    }
 
+    #[test]
     fn shapemat_is_renderable() {
-        fn is_renderable<R: Renderable>() {};
+        fn is_renderable<R: Renderable>() {}
         is_renderable::<ShapeMat<Triangle<f64>, UniformMaterial<Lambert<f64>>>>();
     }
 
+    #[test]
     fn uniform_is_material() {
-        fn is_material<M: Material>() {};
+        fn is_material<M: Material>() {}
         is_material::<UniformMaterial<Lambert<f64>>>();
     }
 }
@@ -1,59 +1,549 @@
 use super::camera::Camera;
-use super::spectrum::Spec;
-use super::material::BSDF;
+use super::filter::{BoxFilter, Filter};
+use super::frame::{cos_theta, same_hemisphere, Frame};
+use super::material::{BsdfFlags, BSDF};
 use super::ray::Ray;
-use super::renderable::*;
+use super::sampling::{stratified_2d, uniform};
 use super::scene::*;
-use super::shape::DiffGeom;
+use super::shape::{DiffGeom, SurfaceInteraction};
+use super::spectrum::Spec;
+use crate::number::Float;
 use image::{ImageBuffer, Pixel, Rgb};
+use na::{Scalar, Vector3};
 use num::ToPrimitive;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::sync::Arc;
 
-pub fn render(cam: Camera<f64>, scene: &Scene<f64>) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
-    let img_height = 100;
-    let img_width = 100;
+/// Number of bounces a path makes before it can be terminated by Russian roulette.
+const ROULETTE_DEPTH: u32 = 3;
 
-    ImageBuffer::from_fn(img_height, img_width, |x, y| {
-        render_ray(
-            cam.ray_at(
-                f64::from(x) / f64::from(img_width),
-                f64::from(y) / f64::from(img_height),
-            ),
-            &scene,
-        )
-    })
+/// Options controlling the output image and how much work is spent on it.
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    /// Width of the image in pixels.
+    pub width: u32,
+    /// Height of the image in pixels.
+    pub height: u32,
+    /// Number of paths traced for each pixel.
+    pub samples_per_pixel: u32,
+    /// Maximum number of bounces in each path.
+    pub max_depth: u32,
+    /// Filter used to spread each sample over the pixels around it.
+    pub filter: Arc<dyn Filter>,
+    /// Seed for the random numbers used while rendering. The same seed always gives the same
+    /// image, no matter how many threads are used.
+    pub seed: u64,
+    /// Width and height of the square tiles the image is split into. Tiles are rendered in
+    /// parallel.
+    pub tile_size: u32,
+    /// Number of threads to render with. When this is 0, rayon's global thread pool is used,
+    /// which has one thread per core.
+    pub threads: usize,
+}
+
+impl RenderSettings {
+    pub fn new(width: u32, height: u32) -> RenderSettings {
+        RenderSettings {
+            width,
+            height,
+            ..RenderSettings::default()
+        }
+    }
+
+    /// Ratio of the image width to its height.
+    pub fn aspect_ratio(&self) -> f64 {
+        f64::from(self.width) / f64::from(self.height)
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            width: 100,
+            height: 100,
+            samples_per_pixel: 1,
+            max_depth: 5,
+            filter: Arc::new(BoxFilter::default()),
+            seed: 0,
+            tile_size: 16,
+            threads: 0,
+        }
+    }
+}
+
+/// Renders the scene as seen by `cam`.
+///
+/// The camera is fitted to the aspect ratio of the image, so that pixels are square and the
+/// image is not stretched.
+///
+/// The image is split into tiles which are rendered in parallel on a work stealing thread pool.
+pub fn render<N: Float, C: Camera<NumTy = N>>(
+    cam: C,
+    scene: &Scene<N>,
+    settings: &RenderSettings,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let cam = cam.with_aspect_ratio(N::from_subset(&settings.aspect_ratio()));
+    let tiles = Tile::split(settings.width, settings.height, settings.tile_size);
+    let render_tiles = || -> Vec<Film<N>> {
+        tiles
+            .par_iter()
+            .map(|tile| render_tile(tile, &cam, scene, settings))
+            .collect()
+    };
+    let rendered = if settings.threads == 0 {
+        render_tiles()
+    } else {
+        ThreadPoolBuilder::new()
+            .num_threads(settings.threads)
+            .build()
+            .expect("Failed to create render thread pool")
+            .install(render_tiles)
+    };
+
+    // Tiles overlap where samples spread past their edges, so they are added up in a fixed
+    // order to keep the image the same on any number of threads.
+    let mut film = Film::new(Tile {
+        x: 0,
+        y: 0,
+        width: settings.width,
+        height: settings.height,
+    });
+    for tile_film in &rendered {
+        film.merge(tile_film);
+    }
+    let mut img = ImageBuffer::new(settings.width, settings.height);
+    for ((x, y), pixel) in film.region.pixels().zip(film.pixel_colors()) {
+        img.put_pixel(x, y, pixel);
+    }
+    img
+}
+
+/// Rectangular region of the image.
+#[derive(Debug, Clone, Copy)]
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Tile {
+    /// Covers an image with tiles of at most `size` by `size` pixels.
+    fn split(img_width: u32, img_height: u32, size: u32) -> Vec<Tile> {
+        let size = size.max(1);
+        let mut tiles = vec![];
+        for y in (0..img_height).step_by(size as usize) {
+            for x in (0..img_width).step_by(size as usize) {
+                tiles.push(Tile {
+                    x,
+                    y,
+                    width: size.min(img_width - x),
+                    height: size.min(img_height - y),
+                });
+            }
+        }
+        tiles
+    }
+
+    /// The tile with `margin` more pixels on each side, kept within an image of the given size.
+    fn grow(&self, margin: u32, img_width: u32, img_height: u32) -> Tile {
+        let x = self.x.saturating_sub(margin);
+        let y = self.y.saturating_sub(margin);
+        Tile {
+            x,
+            y,
+            width: (self.x + self.width + margin).min(img_width) - x,
+            height: (self.y + self.height + margin).min(img_height) - y,
+        }
+    }
+
+    /// Coordinates of the pixels in the tile, in row major order.
+    fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let Tile {
+            x,
+            y,
+            width,
+            height,
+        } = *self;
+        (y..y + height).flat_map(move |py| (x..x + width).map(move |px| (px, py)))
+    }
+}
+
+/// Renders the samples for every pixel in the tile. The film returned covers the tile and the
+/// pixels around it that the samples spread to.
+fn render_tile<N: Float, C: Camera<NumTy = N>>(
+    tile: &Tile,
+    cam: &C,
+    scene: &Scene<N>,
+    settings: &RenderSettings,
+) -> Film<N> {
+    let mut film = Film::new(tile.grow(
+        settings.filter.radius().ceil() as u32,
+        settings.width,
+        settings.height,
+    ));
+    for (x, y) in tile.pixels() {
+        let mut rng = pixel_rng(settings, x, y);
+        sample_pixel(x, y, cam, scene, settings, &mut film, &mut rng);
+    }
+    film
+}
+
+/// Random number generator for a single pixel.
+/// Each pixel gets its own generator, so the image does not depend on the order pixels are
+/// rendered in.
+fn pixel_rng(settings: &RenderSettings, x: u32, y: u32) -> SmallRng {
+    let index = u64::from(y) * u64::from(settings.width) + u64::from(x);
+    SmallRng::seed_from_u64(settings.seed ^ index.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
+/// Traces the samples for the pixel at `(x, y)`, and adds them to the film.
+///
+/// Sample positions are stratified over the area of the pixel. The film spreads each one over
+/// the pixels within the filter radius.
+fn sample_pixel<N: Float, C: Camera<NumTy = N>, R: Rng>(
+    x: u32,
+    y: u32,
+    cam: &C,
+    scene: &Scene<N>,
+    settings: &RenderSettings,
+    film: &mut Film<N>,
+    rng: &mut R,
+) {
+    let img_width = f64::from(settings.width);
+    let img_height = f64::from(settings.height);
+    let cone = RayCone::for_pixel(cam, settings);
+    for (u, v) in stratified_2d(settings.samples_per_pixel.max(1), rng) {
+        let sx = f64::from(x) + u;
+        let sy = f64::from(y) + v;
+        let px = N::from_subset(&(sx / img_width));
+        let py = N::from_subset(&(sy / img_height));
+        // points the camera can't see are black
+        let seen = match cam.sample_ray(px, py, uniform(rng), uniform(rng)) {
+            Some(ray) => radiance(ray, cone, scene, 0, settings.max_depth, true, rng),
+            None => Spec::new(N::zero(), N::zero(), N::zero()),
+        };
+        film.add_sample(sx, sy, seen, settings.filter.as_ref());
+    }
+}
+
+/// Filtered samples for a region of the image.
+///
+/// Each sample is added to every pixel whose centre is within the filter radius of it, weighted
+/// by the filter at its offset from that centre. Pixels are divided by their total weight once
+/// all the samples are in. As the total collects samples from all around the pixel, it stays
+/// well away from 0 for filters with negative lobes, where the weight of a single pixel's own
+/// samples could be 0 or negative.
+struct Film<N: Scalar> {
+    region: Tile,
+    /// Weighted sum of the samples and total weight of each pixel, in the order given by
+    /// `Tile::pixels`.
+    pixels: Vec<(Spec<N>, f64)>,
+}
+
+impl<N: Float> Film<N> {
+    /// Film with no samples, covering `region`.
+    fn new(region: Tile) -> Film<N> {
+        let black = Spec::new(N::zero(), N::zero(), N::zero());
+        let count = region.width as usize * region.height as usize;
+        Film {
+            region,
+            pixels: vec![(black, 0.0); count],
+        }
+    }
+
+    /// Index of the pixel at `(x, y)`, which must be in the region.
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y - self.region.y) as usize * self.region.width as usize + (x - self.region.x) as usize
+    }
+
+    /// Adds a sample at `(x, y)`, measured in pixels from the image's top left corner, to the
+    /// pixels in the region around it.
+    fn add_sample(&mut self, x: f64, y: f64, color: Spec<N>, filter: &dyn Filter) {
+        let radius = filter.radius();
+        let Tile {
+            x: left,
+            y: top,
+            width,
+            height,
+        } = self.region;
+        // pixel centres are at +0.5
+        let first = |s: f64, start: u32| (s - 0.5 - radius).ceil().max(f64::from(start)) as u32;
+        let last = |s: f64, start: u32, size: u32| {
+            (s - 0.5 + radius)
+                .floor()
+                .min(f64::from(start + size) - 1.0)
+        };
+        let (x_last, y_last) = (last(x, left, width), last(y, top, height));
+        if x_last < 0.0 || y_last < 0.0 {
+            return;
+        }
+        for py in first(y, top)..=y_last as u32 {
+            for px in first(x, left)..=x_last as u32 {
+                let weight = filter.evaluate(x - f64::from(px) - 0.5, y - f64::from(py) - 0.5);
+                if weight == 0.0 {
+                    continue;
+                }
+                let index = self.index(px, py);
+                let pixel = &mut self.pixels[index];
+                pixel.0 += color.clone() * N::from_subset(&weight);
+                pixel.1 += weight;
+            }
+        }
+    }
+
+    /// Adds the samples of another film, whose region must be inside this one.
+    fn merge(&mut self, other: &Film<N>) {
+        for ((x, y), (color, weight)) in other.region.pixels().zip(&other.pixels) {
+            let index = self.index(x, y);
+            let pixel = &mut self.pixels[index];
+            pixel.0 += color.clone();
+            pixel.1 += weight;
+        }
+    }
+
+    /// Final colors of the pixels, in the order given by `Tile::pixels`. Pixels no sample
+    /// reached are black.
+    fn pixel_colors(&self) -> impl Iterator<Item = Rgb<u8>> + '_ {
+        self.pixels.iter().map(|(color, weight)| {
+            if *weight == 0.0 {
+                return spectrum_to_pixel_color(Spec::new(N::zero(), N::zero(), N::zero()));
+            }
+            spectrum_to_pixel_color(color.clone() * N::from_subset(&(1.0 / weight)))
+        })
+    }
 }
 
-fn render_ray(ray: Ray<f64>, scene: &Scene<f64>) -> Rgb<u8> {
-    spectrum_to_pixel_color(radiance(ray, scene))
+/// Beam of light a sample ray stands for, used to blur textures by the right amount. It is
+/// `width` across at the ray origin, and widens by `spread` for each unit along the ray.
+#[derive(Debug, Clone, Copy)]
+struct RayCone<N> {
+    width: N,
+    spread: N,
+}
+
+impl<N: Float> RayCone<N> {
+    /// Cone covering one pixel, measured between two neighbouring pixels at the centre of the
+    /// image.
+    fn for_pixel<C: Camera<NumTy = N>>(cam: &C, settings: &RenderSettings) -> RayCone<N> {
+        let step = N::from_subset(&(1.0 / f64::from(settings.width.max(1))));
+        let half = N::from(0.5);
+        match (cam.ray_at(half, half), cam.ray_at(half + step, half)) {
+            (Some(centre), Some(next)) => RayCone {
+                width: (next.origin - centre.origin).norm(),
+                spread: centre.direction.angle(&next.direction),
+            },
+            _ => RayCone {
+                width: N::zero(),
+                spread: N::zero(),
+            },
+        }
+    }
+
+    fn width_at(&self, t: N) -> N {
+        self.width + self.spread * t
+    }
+}
+
+/// Width in surface coordinates of an area of the surface `footprint` across.
+fn uv_width<N: Float>(footprint: N, diff_geom: &DiffGeom<N>) -> N {
+    // the surface coordinates change slowest along the longer derivative
+    let scale = diff_geom.dpdu.norm().min(diff_geom.dpdv.norm());
+    if scale > N::zero() {
+        footprint / scale
+    } else {
+        N::zero()
+    }
 }
 
 /// Calculates the radiance of the light moving into the ray origin from the scene.
-fn radiance(ray: Ray<f64>, scene: &Scene<f64>) -> Spec<f64> {
+///
+/// This is an unbiased path tracer. At every intersection the light arriving directly from the
+/// scene lights is added to the light arriving from a new ray, sampled from the surface BSDF and
+/// traced recursively. Paths end after `max_depth` bounces, and after `ROULETTE_DEPTH` bounces they
+/// are randomly stopped with Russian roulette. Surviving paths are weighted up to compensate.
+///
+/// Emissive surfaces are only counted where the camera sees them directly, or through a
+/// specular bounce. Elsewhere along the path their light is already counted by the direct
+/// lighting, which can't find lights through a mirror or glass. `count_emitted` is set for those
+/// rays.
+///
+/// `cone` is the beam the ray stands for. Bounced rays keep spreading at the same rate from the
+/// width the beam had reached, which is rough but keeps textures seen in mirrors sharp.
+fn radiance<N: Float, R: Rng>(
+    ray: Ray<N>,
+    cone: RayCone<N>,
+    scene: &Scene<N>,
+    depth: u32,
+    max_depth: u32,
+    count_emitted: bool,
+    rng: &mut R,
+) -> Spec<N> {
+    let black = Spec::new(N::zero(), N::zero(), N::zero());
     let intersection = scene.intersects_renderable(&ray);
     match intersection {
-        None => Spec::new(0.0, 0.0, 0.0),
-        Some((renderable, t)) => {
-            let isct_pt = ray.at_time(t);
-            let norm = renderable.normal(&isct_pt);
-            let diff_geom = DiffGeom::new(isct_pt, norm);
-            let bsdf = renderable.get_bsdf(&diff_geom);
-            // TODO iterate over all lights
-            let reflect = bsdf.bsdf(
-                &(ray.direction.into_inner() * -1.0),
-                &norm,
-                &(scene.lights[0].position - isct_pt),
-            );
-            scene.lights[0].color.clone() * reflect
+        // Like emissive surfaces, lights around the scene are only counted where the direct
+        // lighting missed them.
+        None if count_emitted => {
+            let mut total = black;
+            for emission in scene.lights.iter().filter_map(|light| light.le(&ray)) {
+                total += emission;
+            }
+            total
+        }
+        None => black,
+        Some((renderable, interaction)) => {
+            let view = -ray.direction.into_inner();
+            // The normal is left on the outside of the surface, so that glass can tell whether
+            // the ray is entering or leaving it. Other BSDFs reflect from both sides.
+            let footprint = cone.width_at(interaction.t);
+            let mut diff_geom = DiffGeom::from(&interaction);
+            diff_geom.uv_width = uv_width(footprint, &diff_geom);
+            let diff_geom = renderable.shading_geometry(diff_geom);
+            let frame = Frame::from_normal_tangent(diff_geom.normal, &diff_geom.dpdu);
+            let wo = frame.to_local(&view);
+            let bsdf = renderable.boxed_bsdf(&diff_geom);
+            let mut direct = direct_lighting(&bsdf, scene, &interaction, &frame, &wo, rng);
+            if count_emitted {
+                if let Some(emission) = renderable.emission() {
+                    direct += emission;
+                }
+            }
+
+            if depth + 1 >= max_depth {
+                return direct;
+            }
+
+            let sample = match bsdf.sample_f(&wo, uniform(rng), uniform(rng)) {
+                Some(sample) if sample.pdf > N::zero() => sample,
+                _ => return direct,
+            };
+            let next_direction = frame.to_world(&sample.wi);
+            let geometric_normal = &interaction.geometric_normal;
+            if !follows_surface(geometric_normal, &view, &next_direction, &wo, &sample.wi) {
+                return direct;
+            }
+            let mut weight = sample.f * (cos_theta(&sample.wi).abs() / sample.pdf);
+
+            if depth >= ROULETTE_DEPTH {
+                let survive = weight.max_component().min(N::from_subset(&0.95));
+                if uniform::<N, R>(rng) >= survive {
+                    return direct;
+                }
+                weight = weight * (N::one() / survive);
+            }
+
+            let next_ray = interaction.spawn_ray(next_direction);
+            let specular = sample.flags.contains(BsdfFlags::SPECULAR);
+            let next_cone = RayCone {
+                width: footprint,
+                ..cone
+            };
+            direct
+                + weight
+                    * radiance(
+                        next_ray,
+                        next_cone,
+                        scene,
+                        depth + 1,
+                        max_depth,
+                        specular,
+                        rng,
+                    )
         }
     }
 }
 
+/// Sums the light reflected along `wo` from every light in the scene that is visible from the
+/// point of `interaction`. `frame` is the shading frame, and `wo` is relative to it.
+///
+/// Each emissive object is sampled at one random point.
+fn direct_lighting<N: Float, B: BSDF<NumTy = N>, R: Rng>(
+    bsdf: &B,
+    scene: &Scene<N>,
+    interaction: &SurfaceInteraction<N>,
+    frame: &Frame<N>,
+    wo: &Vector3<N>,
+    rng: &mut R,
+) -> Spec<N> {
+    let black = Spec::new(N::zero(), N::zero(), N::zero());
+    let view = frame.to_world(wo);
+    let reflected = |to_light: &Vector3<N>| {
+        let wi = frame.to_local(to_light);
+        if !follows_surface(&interaction.geometric_normal, &view, to_light, wo, &wi) {
+            return black.clone();
+        }
+        bsdf.f(wo, &wi) * cos_theta(&wi).abs()
+    };
+    let mut total = black.clone();
+    for light in &scene.lights {
+        let sample = match light.sample_li(interaction, uniform(rng), uniform(rng)) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        if sample.pdf <= N::zero() {
+            continue;
+        }
+        let visible = scene.transmittance(&sample.shadow_ray);
+        if visible <= N::zero() {
+            continue;
+        }
+        let to_light = sample.shadow_ray.direction.into_inner();
+        total += sample.radiance * reflected(&to_light) * (visible / sample.pdf);
+    }
+    for emitter in scene.emitters() {
+        let emission = match emitter.emission() {
+            Some(emission) => emission,
+            None => continue,
+        };
+        let sample = match emitter.sample(uniform(rng), uniform(rng)) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        let distance_sq = (sample.point - interaction.point).norm_squared();
+        if sample.pdf <= N::zero() || distance_sq <= N::zero() {
+            continue;
+        }
+        let shadow_ray = interaction.spawn_ray_to_sample(&sample);
+        let visible = scene.transmittance(&shadow_ray);
+        if visible <= N::zero() {
+            continue;
+        }
+        let to_light = shadow_ray.direction.into_inner();
+        // Converts the density over the emitter's area to a density over directions. Emitters
+        // shine from both sides, so the cosine is taken on whichever side faces the point.
+        let cos_light = sample.normal.dot(&to_light).abs();
+        let weight = visible * cos_light / (distance_sq * sample.pdf);
+        total += emission * reflected(&to_light) * weight;
+    }
+    total
+}
+
+/// Returns false where a bent shading normal disagrees with the actual surface about whether
+/// light is reflected or transmitted between `view` and `wi`, given in world space and as `wo`
+/// and `local_wi` in the shading frame. Those paths would let light leak through the surface, or
+/// reflect from below it, so they are dropped.
+fn follows_surface<N: Float>(
+    geometric_normal: &Vector3<N>,
+    view: &Vector3<N>,
+    wi: &Vector3<N>,
+    wo: &Vector3<N>,
+    local_wi: &Vector3<N>,
+) -> bool {
+    let reflects = view.dot(geometric_normal) * wi.dot(geometric_normal) > N::zero();
+    reflects == same_hemisphere(wo, local_wi)
+}
+
 /// Converts a light spectrum to a pixel color.
-fn spectrum_to_pixel_color(spec: Spec<f64>) -> image::Rgb<u8> {
+fn spectrum_to_pixel_color<N: Float>(spec: Spec<N>) -> image::Rgb<u8> {
+    let channel = |c: N| clamp_255(c.to_subset().unwrap_or(0.0));
     Rgb::from_channels(
-        clamp_255(spec.red),
-        clamp_255(spec.green),
-        clamp_255(spec.blue),
+        channel(spec.red),
+        channel(spec.green),
+        channel(spec.blue),
         255u8,
     )
 }
@@ -62,5 +552,524 @@ fn spectrum_to_pixel_color(spec: Spec<f64>) -> image::Rgb<u8> {
 /// 0.0 -> 0u8
 /// 1.0 -> 255u8
 fn clamp_255(x: f64) -> u8 {
-    (x.min(1.0).max(0.0) * 255.0).to_u8().unwrap_or(0u8)
+    (x.clamp(0.0, 1.0) * 255.0).to_u8().unwrap_or(0u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bump::{Detailed, ShadingDetail};
+    use super::super::camera::{EquirectangularCamera, FisheyeCamera, PerspectiveCamera};
+    use super::super::environment::EnvironmentLight;
+    use super::super::filter::{BoxFilter, LanczosFilter, MitchellFilter};
+    use super::super::light::PointLight;
+    use super::super::material::{Dissolved, Lambert, Mirror, UniformMaterial};
+    use super::super::plane::Plane;
+    use super::super::renderable::ShapeMat;
+    use super::super::sphere::Sphere;
+    use super::super::temp_dir::TempDir;
+    use super::super::triangle::Triangle;
+    use super::*;
+    use approx::abs_diff_eq;
+    use na::{Point3, Rotation3};
+    use std::f64::consts::PI;
+    use std::path::PathBuf;
+
+    #[test]
+    fn render_has_requested_size() {
+        let scene: Scene<f64> = Scene::empty();
+        let cam = PerspectiveCamera::look_at(
+            Point3::new(0.0, 0.0, -1.0),
+            Point3::origin(),
+            Vector3::y(),
+            1.0,
+            1.0,
+            PI / 2.0,
+        );
+        let img = render(cam, &scene, &RenderSettings::new(30, 20));
+        assert_eq!(img.dimensions(), (30, 20));
+    }
+
+    #[test]
+    fn fisheye_corners_are_black() {
+        let mut scene: Scene<f64> = Scene::empty();
+        scene.add_light(EnvironmentLight::new(1, 1, vec![Spec::new(0.5, 0.5, 0.5)]));
+        let cam = FisheyeCamera::new(Point3::origin(), Rotation3::identity(), PI);
+        let img = render(cam, &scene, &RenderSettings::new(16, 8));
+        assert_eq!(img.get_pixel(0, 0)[0], 0);
+        assert!(img.get_pixel(8, 4)[0] > 0);
+        // the image circle spans the width, so it is cut off at the top
+        assert!(img.get_pixel(8, 0)[0] > 0);
+    }
+
+    /// Cone of a single ray, with no width.
+    const POINT: RayCone<f64> = RayCone {
+        width: 0.0,
+        spread: 0.0,
+    };
+
+    #[test]
+    fn pixel_cone_spreads_by_pixel_angle() {
+        let cam = PerspectiveCamera::look_at(
+            Point3::new(0.0, 0.0, -1.0),
+            Point3::origin(),
+            Vector3::y(),
+            1.0,
+            1.0,
+            PI / 2.0,
+        );
+        let cone = RayCone::for_pixel(&cam, &RenderSettings::new(100, 100));
+        // the screen is 2 tan(45 degrees) across at unit distance, so pixels near the centre are
+        // about 1/50 radians apart
+        let expected = 2.0 * (PI / 4.0).tan() / 100.0;
+        assert!(
+            (cone.spread - expected).abs() < 0.05 * expected,
+            "spread {}",
+            cone.spread
+        );
+        assert!((cone.width - 0.01).abs() < 1e-10);
+    }
+
+    fn test_scene<N: Float>() -> Scene<N> {
+        let n = |v: f32| N::from(v);
+        let mut scene = Scene::empty();
+        scene.add_object(ShapeMat::new(
+            Sphere::new(Point3::new(n(0.0), n(0.5), n(0.0)), n(0.5)),
+            UniformMaterial::new(Lambert::new(n(0.8), n(0.3), n(0.3))),
+        ));
+        scene.add_object(ShapeMat::new(
+            Plane::new_at_point(Point3::origin(), Vector3::y()),
+            UniformMaterial::new(Lambert::new(n(0.8), n(0.8), n(0.8))),
+        ));
+        scene.add_light(PointLight::new(
+            Point3::new(n(2.0), n(3.0), n(-2.0)),
+            Spec::new(n(50.0), n(50.0), n(50.0)),
+        ));
+        scene
+    }
+
+    /// Camera looking at the sphere of `test_scene`.
+    fn test_camera<N: Float>() -> PerspectiveCamera<N> {
+        let n = |v: f32| N::from(v);
+        PerspectiveCamera::look_at(
+            Point3::new(n(0.0), n(1.0), n(-3.0)),
+            Point3::new(n(0.0), n(0.5), n(0.0)),
+            Vector3::y(),
+            n(1.0),
+            n(1.0),
+            n(std::f32::consts::FRAC_PI_2),
+        )
+    }
+
+    /// Writes a textured, bump mapped floor lit by a glowing ceiling, as OBJ and MTL files in
+    /// `dir`, and returns the path of the OBJ file.
+    fn write_test_obj(dir: &TempDir) -> PathBuf {
+        let path = dir.join("scene.obj");
+        let obj = "mtllib scene.mtl
+v -2 0 -2
+v 2 0 -2
+v 2 0 2
+v -2 0 2
+v -3 3 -3
+v 3 3 -3
+v 3 3 3
+v -3 3 3
+vt 0 0
+vt 2 0
+vt 2 2
+vt 0 2
+usemtl floor
+f 1/1 4/4 3/3 2/2
+usemtl glow
+f 5 6 7 8
+";
+        std::fs::write(&path, obj).unwrap();
+        let mtl = "newmtl floor
+Kd 0.9 0.8 0.7
+Ks 0.2 0.2 0.2
+Ns 10
+illum 2
+map_Kd check.png
+map_Bump -bm 0.1 check.png
+
+newmtl glow
+Kd 0.5 0.5 0.5
+Ke 1 1 1
+";
+        std::fs::write(path.with_extension("mtl"), mtl).unwrap();
+        let checks = image::RgbImage::from_fn(4, 4, |x, y| match (x + y) % 2 {
+            0 => image::Rgb([230, 200, 40]),
+            _ => image::Rgb([30, 90, 200]),
+        });
+        checks.save(dir.join("check.png")).unwrap();
+        path
+    }
+
+    /// Asserts that renders of the same scene in single and double precision differ only by
+    /// rounding.
+    fn assert_renders_match(single: &[u8], double: &[u8]) {
+        // Both draw the same random numbers, so only rounding tells them apart. Paths can still
+        // part ways where rounding moves a ray across the edge of an object.
+        let differences: Vec<i32> = single
+            .iter()
+            .zip(double)
+            .map(|(&a, &b)| (i32::from(a) - i32::from(b)).abs())
+            .collect();
+        let far_apart = differences.iter().filter(|&&d| d > 2).count();
+        assert!(far_apart * 100 < differences.len(), "{} differ", far_apart);
+        let mean = f64::from(differences.iter().sum::<i32>()) / differences.len() as f64;
+        assert!(mean < 0.5, "mean difference {}", mean);
+    }
+
+    #[test]
+    fn f32_render_matches_f64() {
+        let settings = RenderSettings {
+            samples_per_pixel: 4,
+            seed: 3,
+            ..RenderSettings::new(48, 32)
+        };
+        let single = render(test_camera::<f32>(), &test_scene::<f32>(), &settings).into_raw();
+        let double = render(test_camera::<f64>(), &test_scene::<f64>(), &settings).into_raw();
+        assert_renders_match(&single, &double);
+
+        // and scenes loaded from files, with their textures and bumps
+        let dir = TempDir::new("f32_render");
+        let path = write_test_obj(&dir);
+        let single_scene = Scene::<f32>::load(&path).unwrap();
+        let double_scene = Scene::<f64>::load(&path).unwrap();
+        assert_eq!(single_scene.emitters().count(), 2);
+        let single = render(test_camera::<f32>(), &single_scene, &settings).into_raw();
+        let double = render(test_camera::<f64>(), &double_scene, &settings).into_raw();
+        assert!(double.iter().any(|&c| c > 0), "the loaded scene is lit");
+        assert_renders_match(&single, &double);
+    }
+
+    #[test]
+    fn sphere_light_matches_analytic_irradiance() {
+        // A glowing sphere straight above a white floor. A sphere of radiance L seen at distance
+        // h, with radius r, reflects L * (r / h)^2 off a white diffuse surface below it.
+        let (radius, height, glow): (f64, f64, f64) = (0.5, 2.0, 3.0);
+        let mut scene = Scene::empty();
+        scene.add_object(ShapeMat::new(
+            Plane::new_at_point(Point3::origin(), Vector3::y()),
+            UniformMaterial::new(Lambert::new(1.0, 1.0, 1.0)),
+        ));
+        scene.add_object(ShapeMat::new(
+            Sphere::new(Point3::new(0.0, height, 0.0), radius),
+            UniformMaterial::new(Lambert::new(0.0, 0.0, 0.0))
+                .with_emission(Spec::new(glow, glow, glow)),
+        ));
+
+        let ray = Ray::new_normalize(Point3::new(0.0, 1.0, -1.0), Vector3::new(0.0, -1.0, 1.0));
+        let (floor, interaction) = scene.intersects_renderable(&ray).unwrap();
+        let view = -ray.direction.into_inner();
+        let bsdf = floor.boxed_bsdf(&DiffGeom::from(&interaction));
+        let frame = Frame::from_normal(Vector3::y());
+        let wo = frame.to_local(&view);
+        let mut rng = SmallRng::seed_from_u64(1);
+        let samples = 20000;
+        let mut total = 0.0;
+        for _ in 0..samples {
+            total += direct_lighting(&bsdf, &scene, &interaction, &frame, &wo, &mut rng).red;
+        }
+        let expected = glow * (radius / height).powi(2);
+        let estimate = total / f64::from(samples);
+        assert!(
+            (estimate - expected).abs() < 0.05 * expected,
+            "estimate {} expected {}",
+            estimate,
+            expected
+        );
+    }
+
+    #[test]
+    fn mirror_floor_reflects_emitter() {
+        // Direct lighting can't find lights through a mirror, so the reflected ray has to count
+        // the emission it hits.
+        let mut scene = Scene::empty();
+        scene.add_object(ShapeMat::new(
+            Plane::new_at_point(Point3::origin(), Vector3::y()),
+            UniformMaterial::new(Mirror::new(Spec::new(0.9, 0.9, 0.9))),
+        ));
+        scene.add_object(ShapeMat::new(
+            Sphere::new(Point3::new(0.0, 2.0, 2.0), 0.5),
+            UniformMaterial::new(Lambert::new(0.0, 0.0, 0.0))
+                .with_emission(Spec::new(3.0, 3.0, 3.0)),
+        ));
+
+        let ray = Ray::new_normalize(Point3::new(0.0, 1.0, -1.0), Vector3::new(0.0, -1.0, 1.0));
+        let mut rng = SmallRng::seed_from_u64(1);
+        let seen = radiance(ray, POINT, &scene, 0, 2, true, &mut rng);
+        assert!(abs_diff_eq!(seen.red, 0.9 * 3.0, epsilon = 1e-10));
+    }
+
+    /// Detail that replaces the shading normal.
+    struct Tilt(Vector3<f64>);
+
+    impl ShadingDetail for Tilt {
+        type NumTy = f64;
+
+        fn apply(&self, g: DiffGeom<f64>) -> DiffGeom<f64> {
+            DiffGeom {
+                normal: self.0,
+                ..g
+            }
+        }
+    }
+
+    #[test]
+    fn point_light_irradiance_falls_off_with_distance() {
+        let mut scene = Scene::empty();
+        scene.add_object(ShapeMat::new(
+            Plane::new_at_point(Point3::origin(), Vector3::y()),
+            UniformMaterial::new(Lambert::new(1.0, 1.0, 1.0)),
+        ));
+        scene.add_light(PointLight::new(
+            Point3::new(0.0, 2.0, 0.0),
+            Spec::new(8.0, 8.0, 8.0),
+        ));
+        let mut rng = SmallRng::seed_from_u64(1);
+        // (point on the floor, irradiance there), from intensity * cos / distance^2
+        for &(x, irradiance) in &[(0.0, 2.0), (1.5, 8.0 * 0.8 / 6.25)] {
+            let ray = Ray::new_normalize(Point3::new(x, 1.0, -1.0), Vector3::new(0.0, -1.0, 1.0));
+            let seen = radiance(ray, POINT, &scene, 0, 1, true, &mut rng);
+            // a white diffuse surface reflects irradiance / π in every direction
+            assert!(abs_diff_eq!(seen.red, irradiance / PI, epsilon = 1e-6));
+        }
+    }
+
+    #[test]
+    fn dissolved_surfaces_cast_partial_shadows() {
+        let mut scene = Scene::empty();
+        scene.add_object(ShapeMat::new(
+            Plane::new_at_point(Point3::origin(), Vector3::y()),
+            UniformMaterial::new(Lambert::new(1.0, 1.0, 1.0)),
+        ));
+        // a half dissolved pane between the floor and the light
+        scene.add_object(ShapeMat::new(
+            Triangle::new(
+                Vector3::new(-5.0, 1.5, -5.0),
+                Vector3::new(0.0, 1.5, 5.0),
+                Vector3::new(5.0, 1.5, -5.0),
+            ),
+            UniformMaterial::new(Dissolved::new(Lambert::new(1.0, 1.0, 1.0), 0.5)),
+        ));
+        scene.add_light(PointLight::new(
+            Point3::new(0.0, 2.0, 0.0),
+            Spec::new(8.0, 8.0, 8.0),
+        ));
+        let mut rng = SmallRng::seed_from_u64(1);
+        let ray = Ray::new_normalize(Point3::new(0.0, 1.0, -1.0), Vector3::new(0.0, -1.0, 1.0));
+        let seen = radiance(ray, POINT, &scene, 0, 1, true, &mut rng);
+        // half of the irradiance of 2 from the light gets through the pane
+        assert!(abs_diff_eq!(seen.red, 0.5 * 2.0 / PI, epsilon = 1e-6));
+
+        // an opaque pane stops all of it
+        scene.add_object(ShapeMat::new(
+            Triangle::new(
+                Vector3::new(-5.0, 1.8, -5.0),
+                Vector3::new(0.0, 1.8, 5.0),
+                Vector3::new(5.0, 1.8, -5.0),
+            ),
+            UniformMaterial::new(Lambert::new(1.0, 1.0, 1.0)),
+        ));
+        let ray = Ray::new_normalize(Point3::new(0.0, 1.0, -1.0), Vector3::new(0.0, -1.0, 1.0));
+        let seen = radiance(ray, POINT, &scene, 0, 1, true, &mut rng);
+        assert_eq!(seen.red, 0.0);
+    }
+
+    #[test]
+    fn bent_normals_dont_leak_light() {
+        // The shading normal leans towards a light under the floor, which it would light if only
+        // the shading normal were checked.
+        let tilted = Vector3::new(1.0, 1.0, 0.0).normalize();
+        let mut scene = Scene::empty();
+        scene.add_object(ShapeMat::new(
+            Plane::new_at_point(Point3::origin(), Vector3::y()),
+            Detailed::new(
+                UniformMaterial::new(Lambert::new(1.0, 1.0, 1.0)),
+                Tilt(tilted),
+            ),
+        ));
+        scene.add_light(PointLight::new(
+            Point3::new(3.0, -1.0, 0.0),
+            Spec::new(10.0, 10.0, 10.0),
+        ));
+
+        let ray = Ray::new_normalize(Point3::new(0.0, 1.0, -1.0), Vector3::new(0.0, -1.0, 1.0));
+        let (floor, interaction) = scene.intersects_renderable(&ray).unwrap();
+        let diff_geom = floor.shading_geometry(DiffGeom::from(&interaction));
+        assert_eq!(diff_geom.normal, tilted);
+        let mut rng = SmallRng::seed_from_u64(1);
+        let seen = radiance(ray, POINT, &scene, 0, 1, true, &mut rng);
+        assert_eq!(seen.red, 0.0);
+
+        // and lights above it still count
+        scene.lights.clear();
+        scene.add_light(PointLight::new(
+            Point3::new(3.0, 1.0, 0.0),
+            Spec::new(10.0, 10.0, 10.0),
+        ));
+        let ray = Ray::new_normalize(Point3::new(0.0, 1.0, -1.0), Vector3::new(0.0, -1.0, 1.0));
+        let seen = radiance(ray, POINT, &scene, 0, 1, true, &mut rng);
+        assert!(seen.red > 0.0);
+    }
+
+    #[test]
+    fn environment_light_matches_analytic_irradiance() {
+        // A white diffuse floor under a sky of radiance L reflects L. The sky is brighter on
+        // one side, which importance sampling has to account for.
+        let mut sky = vec![Spec::new(0.5, 0.5, 0.5); 32];
+        sky[1] = Spec::new(20.0, 20.0, 20.0);
+        let sky = EnvironmentLight::new(8, 4, sky);
+        let mut scene = Scene::empty();
+        scene.add_object(ShapeMat::new(
+            Plane::new_at_point(Point3::origin(), Vector3::y()),
+            UniformMaterial::new(Lambert::new(1.0, 1.0, 1.0)),
+        ));
+        scene.add_light(sky);
+
+        let up = Ray::new_normalize(Point3::new(0.0, 1.0, 0.0), Vector3::new(0.1, 0.2, 0.3));
+        let mut rng = SmallRng::seed_from_u64(1);
+        let seen = radiance(up.clone(), POINT, &scene, 0, 1, true, &mut rng);
+        let expected_seen = scene.lights[0].le(&up).unwrap();
+        assert!(abs_diff_eq!(seen.red, expected_seen.red));
+
+        let ray = Ray::new_normalize(Point3::new(0.0, 1.0, -1.0), Vector3::new(0.0, -1.0, 1.0));
+        let (floor, interaction) = scene.intersects_renderable(&ray).unwrap();
+        let view = -ray.direction.into_inner();
+        let bsdf = floor.boxed_bsdf(&DiffGeom::from(&interaction));
+        let frame = Frame::from_normal(Vector3::y());
+        let wo = frame.to_local(&view);
+        let samples = 20000;
+        let mut total = 0.0;
+        for _ in 0..samples {
+            total += direct_lighting(&bsdf, &scene, &interaction, &frame, &wo, &mut rng).red;
+        }
+        // each of the 4 rows of pixels covers a band of the sky, and the floor sees the top two
+        let band = |top: f64, bottom: f64| top.cos().powi(2) - bottom.cos().powi(2);
+        let bright_band = band(0.0, PI / 4.0);
+        let expected =
+            0.5 * (bright_band + band(PI / 4.0, PI / 2.0)) + (20.0 - 0.5) / 8.0 * bright_band;
+        let estimate = total / f64::from(samples);
+        assert!(
+            (estimate - expected).abs() < 0.05 * expected,
+            "estimate {} expected {}",
+            estimate,
+            expected
+        );
+    }
+
+    #[test]
+    fn negative_lobes_keep_flat_image_flat() {
+        let mut scene: Scene<f64> = Scene::empty();
+        scene.add_light(EnvironmentLight::new(1, 1, vec![Spec::new(0.5, 0.5, 0.5)]));
+        let cam = PerspectiveCamera::look_at(
+            Point3::origin(),
+            Point3::new(0.0, 0.0, 1.0),
+            Vector3::y(),
+            1.0,
+            1.0,
+            PI / 2.0,
+        );
+        let filters: Vec<Arc<dyn Filter>> = vec![
+            Arc::new(MitchellFilter::default()),
+            Arc::new(LanczosFilter::new(3.0, 3.0)),
+        ];
+        for filter in filters {
+            for samples_per_pixel in 1..=4 {
+                let settings = RenderSettings {
+                    samples_per_pixel,
+                    filter: filter.clone(),
+                    tile_size: 5,
+                    ..RenderSettings::new(16, 12)
+                };
+                let img = render(cam.clone(), &scene, &settings);
+                let expected = clamp_255(0.5);
+                assert!(
+                    img.pixels().all(|p| p.data == [expected; 3]),
+                    "{:?} at {} samples",
+                    filter,
+                    samples_per_pixel
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn equirectangular_render_gives_back_environment() {
+        let (width, height) = (8, 4);
+        let colors: Vec<Spec<f64>> = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f64, (i / width) as f64);
+                Spec::new(x / 8.0, y / 4.0, 0.9 - (x + y) / 20.0)
+            })
+            .collect();
+        let rotations = vec![
+            Rotation3::identity(),
+            Rotation3::from_euler_angles(0.3, 1.2, -0.7),
+        ];
+        for rotation in rotations {
+            let mut scene: Scene<f64> = Scene::empty();
+            let environment = EnvironmentLight::new(width, height, colors.clone());
+            scene.add_light(environment.with_rotation(rotation));
+            let cam = EquirectangularCamera::new(Point3::new(1.0, 2.0, 3.0), rotation);
+            // each pixel only sees its own samples
+            let settings = RenderSettings {
+                samples_per_pixel: 4,
+                filter: Arc::new(BoxFilter::new(0.5)),
+                ..RenderSettings::new(width as u32, height as u32)
+            };
+            let img = render(cam, &scene, &settings);
+            for (x, y, pixel) in img.enumerate_pixels() {
+                let color = &colors[y as usize * width + x as usize];
+                let expected = [color.red, color.green, color.blue].map(clamp_255);
+                assert_eq!(pixel.data, expected, "pixel {}, {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_cover_image() {
+        let tiles = Tile::split(37, 20, 8);
+        let mut covered = vec![0; 37 * 20];
+        for tile in tiles {
+            for (x, y) in tile.pixels() {
+                covered[(y * 37 + x) as usize] += 1;
+            }
+        }
+        assert!(covered.iter().all(|&c| c == 1));
+    }
+
+    #[test]
+    fn same_seed_renders_same_image_on_any_thread_count() {
+        let scene = test_scene::<f64>();
+        let cam = test_camera::<f64>();
+        let settings = RenderSettings {
+            samples_per_pixel: 4,
+            seed: 7,
+            threads: 1,
+            ..RenderSettings::new(24, 16)
+        };
+        let single = render(cam.clone(), &scene, &settings).into_raw();
+        let many = render(
+            cam.clone(),
+            &scene,
+            &RenderSettings {
+                threads: 4,
+                tile_size: 5,
+                ..settings.clone()
+            },
+        );
+        assert_eq!(single, many.into_raw());
+
+        let other_seed = render(
+            cam,
+            &scene,
+            &RenderSettings {
+                seed: 8,
+                ..settings
+            },
+        );
+        assert_ne!(single, other_seed.into_raw());
+    }
 }
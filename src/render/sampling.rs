@@ -0,0 +1,290 @@
+use crate::number::Float;
+use na::{Point2, RealField, Vector2, Vector3};
+use rand::Rng;
+
+/// Generates `count` jittered points on the unit square.
+///
+/// The square is split into a grid of strata, as close to square as `count` allows, with one
+/// random point in each. Any samples left over after filling the grid are placed uniformly.
+pub fn stratified_2d<R: Rng>(count: u32, rng: &mut R) -> Vec<(f64, f64)> {
+    let nx = f64::from(count).sqrt() as u32;
+    let ny = count.checked_div(nx).unwrap_or(0);
+    let mut samples = Vec::with_capacity(count as usize);
+    for j in 0..ny {
+        for i in 0..nx {
+            samples.push((
+                (f64::from(i) + rng.gen::<f64>()) / f64::from(nx),
+                (f64::from(j) + rng.gen::<f64>()) / f64::from(ny),
+            ));
+        }
+    }
+    while samples.len() < count as usize {
+        samples.push((rng.gen(), rng.gen()));
+    }
+    samples
+}
+
+/// Uniform random number in `[0, 1)`. It is drawn at f64 precision, and kept below 1 where
+/// rounding to a narrower type would reach it.
+pub fn uniform<N: Float, R: Rng>(rng: &mut R) -> N {
+    N::from_subset(&rng.gen::<f64>()).min(Float::next_down(N::one()))
+}
+
+/// Maps a uniform sample on the unit square to a direction on the hemisphere around +Z, with a
+/// probability density proportional to the cosine of the angle with +Z.
+///
+/// The pdf of the returned direction is `cos(theta) / PI`.
+pub fn cosine_sample_hemisphere<N: RealField>(u1: N, u2: N) -> Vector3<N> {
+    let r = u1.sqrt();
+    let phi = N::two_pi() * u2;
+    let z = (N::one() - u1).max(N::zero()).sqrt();
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Maps a uniform sample on the unit square to a uniform point on the unit disk.
+///
+/// This is Shirley's concentric mapping, which keeps nearby samples close together, so that
+/// stratified samples stay stratified.
+pub fn concentric_sample_disk<N: RealField>(u1: N, u2: N) -> Point2<N> {
+    let two = N::one() + N::one();
+    let (x, y) = (u1 * two - N::one(), u2 * two - N::one());
+    if x == N::zero() && y == N::zero() {
+        return Point2::origin();
+    }
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, N::frac_pi_4() * (y / x))
+    } else {
+        (y, N::frac_pi_2() - N::frac_pi_4() * (x / y))
+    };
+    Point2::new(r * theta.cos(), r * theta.sin())
+}
+
+/// Maps a uniform sample on the unit square to a uniform point in a regular polygon with
+/// `sides` corners on the unit circle, the first of them on +X. `sides` must be at least 3.
+pub fn sample_polygon<N: RealField>(sides: u32, u1: N, u2: N) -> Point2<N> {
+    let count = N::from_subset(&f64::from(sides));
+    let index = step_index(u1, sides as usize);
+    let corner = |i: usize| {
+        let angle = N::two_pi() * N::from_subset(&(i as f64)) / count;
+        Vector2::new(angle.cos(), angle.sin())
+    };
+    // a uniform point in the triangle between the centre and one edge
+    let u1 = (u1 * count - N::from_subset(&(index as f64))).max(N::zero());
+    let r = u1.sqrt();
+    Point2::from(corner(index) * (r * (N::one() - u2)) + corner(index + 1) * (r * u2))
+}
+
+/// Builds two unit vectors that form an orthonormal basis together with the unit vector `v`.
+pub fn coordinate_system<N: RealField>(v: &Vector3<N>) -> (Vector3<N>, Vector3<N>) {
+    let t = if v.x.abs() > v.y.abs() {
+        Vector3::new(-v.z, N::zero(), v.x) / (v.x * v.x + v.z * v.z).sqrt()
+    } else {
+        Vector3::new(N::zero(), v.z, -v.y) / (v.y * v.y + v.z * v.z).sqrt()
+    };
+    (t, v.cross(&t))
+}
+
+/// Piecewise constant function on `[0, 1)`, which can be sampled with a density proportional to
+/// its value.
+#[derive(Debug, Clone)]
+pub struct Distribution1D<N> {
+    func: Vec<N>,
+    /// Running integral of `func`, normalized so the last entry is 1.
+    cdf: Vec<N>,
+    integral: N,
+}
+
+impl<N: RealField> Distribution1D<N> {
+    /// Function taking the value `func[i]` over the `i`th of `func.len()` equal steps.
+    /// Values must not be negative. If they are all 0, the distribution is uniform.
+    pub fn new(func: Vec<N>) -> Distribution1D<N> {
+        let n = N::from_subset(&(func.len() as f64));
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(N::zero());
+        for (i, &value) in func.iter().enumerate() {
+            cdf.push(cdf[i] + value / n);
+        }
+        let integral = cdf[func.len()];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > N::zero() {
+                *c / integral
+            } else {
+                N::from_subset(&(i as f64)) / n
+            };
+        }
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    /// Average value of the function.
+    pub fn integral(&self) -> N {
+        self.integral
+    }
+
+    /// Maps a uniform sample `u` in `[0, 1)` to a point in `[0, 1)`, returning the point, its
+    /// pdf, and the index of the step it is in.
+    pub fn sample(&self, u: N) -> (N, N, usize) {
+        // the last step starting at or before u, which skips steps of zero width
+        let offset = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .saturating_sub(1)
+            .min(self.func.len() - 1);
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > N::zero() {
+            (u - self.cdf[offset]) / width
+        } else {
+            N::zero()
+        };
+        let n = N::from_subset(&(self.func.len() as f64));
+        (
+            (N::from_subset(&(offset as f64)) + du) / n,
+            self.pdf_at(offset),
+            offset,
+        )
+    }
+
+    /// Pdf of sampling a point in step `index`.
+    fn pdf_at(&self, index: usize) -> N {
+        if self.integral > N::zero() {
+            self.func[index] / self.integral
+        } else {
+            N::one()
+        }
+    }
+
+    /// Pdf of sampling the point `x` in `[0, 1)`.
+    pub fn pdf(&self, x: N) -> N {
+        self.pdf_at(step_index(x, self.func.len()))
+    }
+}
+
+/// Index of the step containing `x`, out of `count` equal steps over `[0, 1)`.
+pub fn step_index<N: RealField>(x: N, count: usize) -> usize {
+    let scaled = (x * N::from_subset(&(count as f64))).floor();
+    match scaled.to_subset() {
+        Some(i) if i > 0.0 => (i as usize).min(count - 1),
+        _ => 0,
+    }
+}
+
+/// Piecewise constant function on the unit square, sampled with a density proportional to its
+/// value. A row is picked from the total of each row, and then a point within the row.
+#[derive(Debug, Clone)]
+pub struct Distribution2D<N> {
+    rows: Vec<Distribution1D<N>>,
+    marginal: Distribution1D<N>,
+}
+
+impl<N: RealField> Distribution2D<N> {
+    /// Function taking the value `func[v * width + u]` over the cell in column `u` and row `v`.
+    pub fn new(func: &[N], width: usize, height: usize) -> Distribution2D<N> {
+        let rows: Vec<Distribution1D<N>> = func
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral()).collect());
+        Distribution2D { rows, marginal }
+    }
+
+    /// Maps uniform samples in `[0, 1)` to a point in the unit square, returning the point and
+    /// its pdf.
+    pub fn sample(&self, u1: N, u2: N) -> (Point2<N>, N) {
+        let (v, pdf_v, row) = self.marginal.sample(u2);
+        let (u, pdf_u, _) = self.rows[row].sample(u1);
+        (Point2::new(u, v), pdf_u * pdf_v)
+    }
+
+    /// Pdf of sampling the point `p` in the unit square.
+    pub fn pdf(&self, p: &Point2<N>) -> N {
+        let row = &self.rows[step_index(p.y, self.rows.len())];
+        self.marginal.pdf(p.y) * row.pdf(p.x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    proptest! {
+        #[test]
+        fn stratified_samples_in_unit_square(count in 0u32..100, seed in 0u32..u32::MAX) {
+            let mut rng = SmallRng::seed_from_u64(u64::from(seed));
+            let samples = stratified_2d(count, &mut rng);
+            prop_assert_eq!(samples.len(), count as usize);
+            for (x, y) in samples {
+                prop_assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+            }
+        }
+
+        #[test]
+        fn stratified_samples_fill_strata(seed in 0u32..u32::MAX) {
+            let mut rng = SmallRng::seed_from_u64(u64::from(seed));
+            let samples = stratified_2d(16, &mut rng);
+            // each of the 4x4 cells gets exactly one sample
+            let mut cells = [0; 16];
+            for (x, y) in samples {
+                cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+            }
+            prop_assert_eq!(cells, [1; 16]);
+        }
+
+        #[test]
+        fn hemisphere_sample_is_unit(u1 in 0f64..1.0, u2 in 0f64..1.0) {
+            let v = cosine_sample_hemisphere(u1, u2);
+            prop_assert!((v.magnitude() - 1.0).abs() < 0.00001);
+            prop_assert!(v.z >= 0.0);
+        }
+
+        #[test]
+        fn disk_sample_in_unit_disk(u1 in 0f64..1.0, u2 in 0f64..1.0) {
+            prop_assert!(concentric_sample_disk(u1, u2).coords.magnitude() <= 1.0 + 1e-10);
+        }
+
+        #[test]
+        fn polygon_sample_inside_polygon(sides in 3usize..10, u1 in 0f64..1.0, u2 in 0f64..1.0) {
+            let sides = sides as u32;
+            let p = sample_polygon(sides, u1, u2);
+            // inside every edge, which are all the same distance from the centre
+            let inner_radius = (std::f64::consts::PI / f64::from(sides)).cos();
+            for i in 0..sides {
+                let angle = std::f64::consts::PI * (2.0 * f64::from(i) + 1.0) / f64::from(sides);
+                let outward = Vector2::new(angle.cos(), angle.sin());
+                prop_assert!(p.coords.dot(&outward) <= inner_radius + 1e-10);
+            }
+        }
+
+        #[test]
+        fn distribution_samples_follow_function(func in prop::collection::vec(0f64..10.0, 1..20),
+                                                u in 0f64..1.0) {
+            let distribution = Distribution1D::new(func.clone());
+            let (x, pdf, index) = distribution.sample(u);
+            prop_assert!((0.0..1.0).contains(&x));
+            prop_assert_eq!(index, (x * func.len() as f64) as usize);
+            prop_assert!((distribution.pdf(x) - pdf).abs() < 1e-10);
+            if func.iter().any(|&f| f > 0.0) {
+                // steps where the function is 0 are never picked
+                prop_assert!(func[index] > 0.0);
+                let average = func.iter().sum::<f64>() / func.len() as f64;
+                prop_assert!((pdf - func[index] / average).abs() < 1e-10);
+            }
+        }
+
+        #[test]
+        fn distribution_2d_pdf_matches_sample(func in prop::collection::vec(0f64..10.0, 12),
+                                              u1 in 0f64..1.0,
+                                              u2 in 0f64..1.0) {
+            let distribution = Distribution2D::new(&func, 4, 3);
+            let (p, pdf) = distribution.sample(u1, u2);
+            prop_assert!((0.0..1.0).contains(&p.x) && (0.0..1.0).contains(&p.y));
+            prop_assert!((distribution.pdf(&p) - pdf).abs() < 1e-8);
+        }
+    }
+}
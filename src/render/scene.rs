@@ -1,27 +1,70 @@
-use super::spectrum::Spec;
-use super::light::PointLight;
+use super::bump::{BumpMap, Detailed, NormalMap, SharedDetail};
+use super::bvh::Bvh;
+use super::light::{Light, PointLight};
 use super::material::*;
+use super::mesh::{smooth_normals, smooth_tangents};
+use super::mtl::{parse_mtl, MtlMaterial, TextureMap};
 use super::ray::Ray;
 use super::renderable::*;
-use super::triangle::Triangle;
+use super::spectrum::Spec;
+use super::texture::{ImageTexture, ScaledTexture, TextureLoadError, WrapMode};
+use super::triangle::{Triangle, TriangleIntersection};
+use super::triangulate::triangulate;
+use crate::number::Float;
 use alga::general::RealField;
-use na::{Point3, Scalar, Vector3};
+use na::{Point2, Point3, Scalar, Vector3};
 use obj::{IndexTuple, Obj, SimplePolygon};
 use std::borrow::Borrow;
-use std::cmp::Ordering;
-use std::path::Path;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-type MatTri<T> = ShapeMat<Triangle<T>, UniformMaterial<Lambert<T>>>;
+/// Bump or normal map of a loaded material, if it has one.
+type MeshDetail<T> = Option<SharedDetail<T>>;
+type MatTri<T> = ShapeMat<Triangle<T>, Detailed<UniformMaterial<SharedBsdf<T>>, MeshDetail<T>>>;
+/// `map_Kd` image tinted by `Kd`.
+type MeshTexture<T> = ScaledTexture<Arc<ImageTexture<T>>, Spec<T>>;
+type TexturedTri<T> =
+    ShapeMat<Triangle<T>, Detailed<TexturedPhong<MeshTexture<T>, T>, MeshDetail<T>>>;
+
+/// Material of the faces of a loaded mesh.
+#[derive(Clone)]
+enum MeshMaterial<T: RealField> {
+    Uniform(Detailed<UniformMaterial<SharedBsdf<T>>, MeshDetail<T>>),
+    /// Material with a `map_Kd` image.
+    Textured(Detailed<TexturedPhong<MeshTexture<T>, T>, MeshDetail<T>>),
+}
 
 pub struct Scene<T: Scalar> {
-    pub objects: Vec<MatTri<T>>,
-    pub lights: Vec<PointLight<T>>,
+    /// Objects in the scene. The BVH is rebuilt whenever these change.
+    objects: Vec<Box<dyn SceneObject<T>>>,
+    /// Lights other than emissive objects.
+    pub lights: Vec<Box<dyn Light<NumTy = T>>>,
+    bvh: Bvh<T>,
+    /// Indices of the objects with emissive materials, which light the scene.
+    emitters: Vec<usize>,
+    /// Problems worked around while loading the scene.
+    warnings: Vec<LoadWarning>,
 }
 
 #[derive(Debug)]
 pub enum SceneLoadError {
     LoadObjError,
-    SceneContainsGeneralPolyError,
+    /// A face has fewer than 3 vertices.
+    DegeneratePolyError,
+}
+
+/// Problem with a scene file that `Scene::load` works around, leaving part of the scene out
+/// rather than failing.
+#[derive(Debug)]
+pub enum LoadWarning {
+    /// A material library can't be read or parsed, so faces using its materials get the
+    /// default material.
+    SkippedMaterialLibrary(PathBuf, io::Error),
+    /// An image named by a material can't be read, so the material is used without it.
+    SkippedTexture(PathBuf, TextureLoadError),
 }
 
 impl<T: Scalar> Scene<T> {
@@ -29,63 +72,365 @@ impl<T: Scalar> Scene<T> {
         Scene {
             objects: vec![],
             lights: vec![],
+            bvh: Bvh::empty(),
+            emitters: vec![],
+            warnings: vec![],
         }
     }
 }
 
 impl<N: RealField + From<f32>> Scene<N> {
-    pub fn intersects_renderable(&self, ray: &Ray<N>) -> Option<(&MatTri<N>, N)> {
-        let shape_inter = self.objects.iter().map(|s| (s, s.intersection(ray)));
-        let closest = shape_inter.min_by(order_by_closest);
-        closest.and_then(|(s, inter)| match inter {
-            None => None,
-            Some(i) => Some((s, i)),
-        })
+    /// Adds an object to the scene, rebuilding the BVH to include it. `add_objects` is much
+    /// faster for adding many objects.
+    pub fn add_object<O: SceneObject<N> + 'static>(&mut self, object: O) {
+        self.add_objects(vec![Box::new(object) as Box<dyn SceneObject<N>>]);
+    }
+
+    /// Adds objects to the scene, rebuilding the BVH once for all of them.
+    pub fn add_objects<I: IntoIterator<Item = Box<dyn SceneObject<N>>>>(&mut self, objects: I) {
+        self.objects.extend(objects);
+        self.build_bvh();
+    }
+
+    /// Problems worked around while loading the scene, such as images that can't be read.
+    /// Scenes that weren't loaded from a file have none.
+    pub fn warnings(&self) -> &[LoadWarning] {
+        &self.warnings
+    }
+
+    /// Objects in the scene.
+    pub fn objects(&self) -> &[Box<dyn SceneObject<N>>] {
+        &self.objects
+    }
+
+    pub fn add_light<L: Light<NumTy = N> + 'static>(&mut self, light: L) {
+        self.lights.push(Box::new(light));
     }
+
+    /// Rebuilds the bounding volume hierarchy used to intersect rays with the scene objects,
+    /// and finds the objects that give off light.
+    fn build_bvh(&mut self) {
+        self.bvh = Bvh::build(&self.objects);
+        self.emitters = (0..self.objects.len())
+            .filter(|&i| self.objects[i].emission().is_some())
+            .collect();
+    }
+
+    /// Objects with emissive materials. These are area lights, and are sampled for direct
+    /// lighting along with `lights`.
+    pub fn emitters(&self) -> impl Iterator<Item = &dyn SceneObject<N>> {
+        self.emitters.iter().map(move |&i| self.objects[i].as_ref())
+    }
+
+    pub fn intersects_renderable(
+        &self,
+        ray: &Ray<N>,
+    ) -> Option<(&dyn SceneObject<N>, SurfaceInteraction<N>)> {
+        debug_assert_eq!(self.bvh.len(), self.objects.len(), "BVH is out of date");
+        self.bvh
+            .closest_hit(&self.objects, ray)
+            .map(|(object, hit)| (object.as_ref(), hit))
+    }
+
+    /// Returns true if any object intersects the ray within its interval.
+    /// This stops at the first intersection found, so it is cheaper than finding the closest one.
+    pub fn is_occluded(&self, ray: &Ray<N>) -> bool {
+        debug_assert_eq!(self.bvh.len(), self.objects.len(), "BVH is out of date");
+        self.bvh.any_hit(&self.objects, ray)
+    }
+
+    /// Fraction of the light that gets along the ray, through the dissolved surfaces in its way.
+    /// Any opaque object stops all of it.
+    pub fn transmittance(&self, ray: &Ray<N>) -> N {
+        debug_assert_eq!(self.bvh.len(), self.objects.len(), "BVH is out of date");
+        let mut transmittance = N::one();
+        self.bvh.each_hit(&self.objects, ray, |object| {
+            match object.opacity() {
+                Some(opacity) if opacity < N::one() => transmittance *= N::one() - opacity,
+                _ => transmittance = N::zero(),
+            }
+            transmittance <= N::zero()
+        });
+        transmittance
+    }
+}
+
+/// Options for `Scene::load_with_options`.
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    /// For meshes without vertex normals, the largest angle in radians between neighbouring
+    /// faces that is shaded smoothly. Sharper edges are left sharp, and 0 gives flat shading.
+    pub crease_angle: f64,
+    /// Algorithm used to intersect rays with the mesh's triangles.
+    pub triangle_intersection: TriangleIntersection,
 }
 
-fn order_by_closest<S, T: PartialOrd>(
-    (_, t1): &(&S, Option<T>),
-    (_, t2): &(&S, Option<T>),
-) -> Ordering {
-    match (t1, t2) {
-        (None, _) => Ordering::Greater,
-        (_, None) => Ordering::Less,
-        (Some(x), Some(y)) => x.partial_cmp(y).unwrap_or(Ordering::Equal),
+impl Default for LoadOptions {
+    fn default() -> LoadOptions {
+        LoadOptions {
+            crease_angle: std::f64::consts::PI / 3.0,
+            triangle_intersection: TriangleIntersection::default(),
+        }
     }
 }
 
-impl Scene<f64> {
-    pub fn load(path: &Path) -> Result<Scene<f64>, SceneLoadError> {
+impl<N: Float> Scene<N> {
+    pub fn load(path: &Path) -> Result<Scene<N>, SceneLoadError> {
+        Scene::load_with_options(path, &LoadOptions::default())
+    }
+
+    pub fn load_with_options(
+        path: &Path,
+        options: &LoadOptions,
+    ) -> Result<Scene<N>, SceneLoadError> {
         let obj: Obj<SimplePolygon> = Obj::load(path).map_err(|_| SceneLoadError::LoadObjError)?;
-        let mut scene: Scene<f64> = Scene::empty();
+        let mut warnings = vec![];
+        let materials = load_materials(&obj, &mut warnings);
+        let default_material = MeshMaterial::Uniform(Detailed::new(
+            UniformMaterial::new(MtlMaterial::default_material().to_bsdf()),
+            None,
+        ));
+        let mut scene: Scene<N> = Scene::empty();
 
+        let mut faces = vec![];
         let objects: &Vec<_> = obj.objects.borrow();
         for object in objects {
             let groups: &Vec<_> = object.groups.borrow();
             for group in groups {
+                // faces without a known material get the default one
+                let material = group
+                    .material
+                    .as_ref()
+                    .and_then(|m| materials.get(&m.name))
+                    .unwrap_or(&default_material);
                 let polys: &Vec<_> = group.polys.borrow();
                 for poly in polys {
-                    let tri = to_triangle(&obj, poly)?;
-                    // use a standard color until we can load from the file
-                    let material = UniformMaterial::new(Lambert::new(1.0, 0.0, 0.0));
-                    scene.objects.push(MatTri::new(tri, material));
+                    for corners in triangulate_face(&obj, poly)? {
+                        faces.push((corners, material));
+                    }
                 }
             }
         }
 
-        // hard coded lights
-        let white = Spec::new(1.0, 1.0, 1.0);
-        let light: PointLight<f64> = PointLight {
-            position: Point3::new(5.0, 5.0, 1.0),
-            color: white,
-        };
-        scene.lights.push(light);
+        // Faces without normals in the file are smoothed together.
+        let positions: Vec<Point3<N>> = (0..obj.position.len())
+            .map(|i| Point3::from(get_point(&obj, IndexTuple(i, None, None))))
+            .collect();
+        let unsmoothed: Vec<usize> = (0..faces.len())
+            .filter(|&i| faces[i].0.iter().any(|c| c.2.is_none()))
+            .collect();
+        let position_indices: Vec<[usize; 3]> = unsmoothed
+            .iter()
+            .map(|&i| {
+                let [a, b, c] = faces[i].0;
+                [a.0, b.0, c.0]
+            })
+            .collect();
+        let mut generated_normals: HashMap<usize, [Vector3<N>; 3]> = unsmoothed
+            .into_iter()
+            .zip(smooth_normals(
+                &positions,
+                &position_indices,
+                N::from_subset(&options.crease_angle),
+            ))
+            .collect();
+
+        let mut triangles: Vec<Triangle<N>> = faces
+            .iter()
+            .enumerate()
+            .map(|(i, (corners, _))| {
+                let normals = generated_normals
+                    .remove(&i)
+                    .unwrap_or_else(|| corners.map(|c| get_normal(&obj, c)));
+                let tri = Triangle::new(
+                    positions[corners[0].0].coords,
+                    positions[corners[1].0].coords,
+                    positions[corners[2].0].coords,
+                )
+                .with_normals(normals)
+                .with_intersection(options.triangle_intersection);
+                if corners.iter().all(|c| c.1.is_some()) {
+                    tri.with_uvs(corners.map(|c| get_uv(&obj, c)))
+                } else {
+                    tri
+                }
+            })
+            .collect();
+
+        // Faces with texture coordinates get tangents for normal maps, smoothed between faces
+        // that share both a position and texture coordinates.
+        let textured: Vec<usize> = (0..faces.len())
+            .filter(|&i| faces[i].0.iter().all(|c| c.1.is_some()))
+            .collect();
+        let mut vertex_ids: HashMap<(usize, Option<usize>), usize> = HashMap::new();
+        let vertices: Vec<[usize; 3]> = textured
+            .iter()
+            .map(|&i| {
+                faces[i].0.map(|c| {
+                    let next_id = vertex_ids.len();
+                    *vertex_ids.entry((c.0, c.1)).or_insert(next_id)
+                })
+            })
+            .collect();
+        let face_tangents: Vec<Option<Vector3<N>>> = textured
+            .iter()
+            .map(|&i| triangles[i].uv_derivatives().map(|(dpdu, _)| dpdu))
+            .collect();
+        for (&i, tangents) in textured
+            .iter()
+            .zip(smooth_tangents(&vertices, &face_tangents))
+        {
+            triangles[i] = triangles[i].clone().with_tangents(tangents);
+        }
+
+        scene.add_objects(triangles.into_iter().zip(faces).map(
+            |(tri, (_, material))| match material {
+                MeshMaterial::Uniform(material) => {
+                    Box::new(MatTri::new(tri, material.clone())) as Box<dyn SceneObject<N>>
+                }
+                MeshMaterial::Textured(material) => {
+                    Box::new(TexturedTri::new(tri, material.clone()))
+                }
+            },
+        ));
 
+        // Scenes without emissive materials get a hard coded light, so they aren't black. It is
+        // a point light at (5, 5, 1) with an intensity of 160, about π times its squared
+        // distance of 51 from the origin, so a white diffuse surface there facing the light
+        // reflects a radiance of about 1.
+        if scene.emitters.is_empty() {
+            let white = Spec::new(N::from(160.0), N::from(160.0), N::from(160.0));
+            let position = Point3::new(N::from(5.0), N::from(5.0), N::one());
+            scene.add_light(PointLight::new(position, white));
+        }
+        scene.warnings = warnings;
         Ok(scene)
     }
 }
 
+/// Reads the materials from every `mtllib` the OBJ file references, keyed by name.
+/// Library paths are relative to the directory of the OBJ file, and images relative to the
+/// library. Libraries that can't be read are skipped and added to `warnings`.
+fn load_materials<N: Float>(
+    obj: &Obj<SimplePolygon>,
+    warnings: &mut Vec<LoadWarning>,
+) -> HashMap<String, MeshMaterial<N>> {
+    let mut materials = HashMap::new();
+    // materials often share an image, so each is only read once
+    let mut textures = TextureCache::new();
+    for lib in &obj.material_libs {
+        let lib_path = obj.path.join(lib);
+        let parsed = match File::open(&lib_path).and_then(|file| parse_mtl(BufReader::new(file))) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                warnings.push(LoadWarning::SkippedMaterialLibrary(lib_path, err));
+                continue;
+            }
+        };
+        let dir = lib_path.parent().unwrap_or(&obj.path);
+        for material in parsed {
+            let detail = load_detail(&material, dir, &mut textures, warnings);
+            // an image that can't be read leaves the plain `Kd` color
+            let texture = material
+                .diffuse_texture()
+                .and_then(|map| textures.load_or_skip(dir, map, true, warnings));
+            let mesh_material = match texture {
+                Some(texture) => {
+                    let mut textured = material.to_textured_phong(texture);
+                    if let Some(emission) = material.emission() {
+                        textured = textured.with_emission(emission);
+                    }
+                    MeshMaterial::Textured(Detailed::new(textured, detail))
+                }
+                None => {
+                    let mut uniform = UniformMaterial::new(material.to_bsdf());
+                    if let Some(emission) = material.emission() {
+                        uniform = uniform.with_emission(emission);
+                    }
+                    MeshMaterial::Uniform(Detailed::new(uniform, detail))
+                }
+            };
+            materials.insert(material.name, mesh_material);
+        }
+    }
+    materials
+}
+
+/// Images already read by `load_materials`, keyed by path, whether they hold sRGB color and
+/// whether they are clamped.
+struct TextureCache<N: Scalar>(HashMap<(PathBuf, bool, bool), Arc<ImageTexture<N>>>);
+
+impl<N: Float> TextureCache<N> {
+    fn new() -> TextureCache<N> {
+        TextureCache(HashMap::new())
+    }
+
+    /// Reads the image of a texture map in `dir`, or reuses it if it has been read before.
+    /// Color images are converted from sRGB, and data such as bump maps are not.
+    fn load(
+        &mut self,
+        dir: &Path,
+        map: &TextureMap,
+        color: bool,
+    ) -> Result<Arc<ImageTexture<N>>, TextureLoadError> {
+        let path = dir.join(&map.file);
+        let key = (path.clone(), color, map.clamp);
+        if let Some(texture) = self.0.get(&key) {
+            return Ok(texture.clone());
+        }
+        let mut texture = if color {
+            ImageTexture::load(&path)?
+        } else {
+            ImageTexture::load_linear(&path)?
+        };
+        if map.clamp {
+            texture = texture.with_wrap(WrapMode::Clamp);
+        }
+        let texture = Arc::new(texture);
+        self.0.insert(key, texture.clone());
+        Ok(texture)
+    }
+
+    /// Like `load`, but an image that can't be read is left out and added to `warnings`, so
+    /// that a missing file doesn't stop the rest of the scene from loading.
+    fn load_or_skip(
+        &mut self,
+        dir: &Path,
+        map: &TextureMap,
+        color: bool,
+        warnings: &mut Vec<LoadWarning>,
+    ) -> Option<Arc<ImageTexture<N>>> {
+        match self.load(dir, map, color) {
+            Ok(texture) => Some(texture),
+            Err(err) => {
+                warnings.push(LoadWarning::SkippedTexture(dir.join(&map.file), err));
+                None
+            }
+        }
+    }
+}
+
+/// Reads the normal or bump map of a material. A normal map is used in place of a bump map
+/// when a material has both. Maps that can't be read are skipped, leaving the surface smooth.
+fn load_detail<N: Float>(
+    material: &MtlMaterial,
+    dir: &Path,
+    textures: &mut TextureCache<N>,
+    warnings: &mut Vec<LoadWarning>,
+) -> MeshDetail<N> {
+    let normal_map = material.norm.as_ref().and_then(|map| {
+        let strength = N::from(map.bump_multiplier);
+        let image = textures.load_or_skip(dir, map, false, warnings)?;
+        Some(Arc::new(NormalMap::new(image).with_strength(strength)) as SharedDetail<N>)
+    });
+    normal_map.or_else(|| {
+        let map = material.map_bump.as_ref()?;
+        let height = textures.load_or_skip(dir, map, false, warnings)?;
+        let scale = N::from(map.bump_multiplier);
+        Some(Arc::new(BumpMap::new(height, scale)) as SharedDetail<N>)
+    })
+}
+
 fn get_point<N: Scalar + From<f32>>(
     obj: &Obj<SimplePolygon>,
     point_index: IndexTuple,
@@ -96,16 +441,398 @@ fn get_point<N: Scalar + From<f32>>(
     Vector3::new(N::from(point[0]), N::from(point[1]), N::from(point[2]))
 }
 
-fn to_triangle<N: Scalar + From<f32>>(
+/// Normal of a face corner. The corner must have a normal index.
+fn get_normal<N: Scalar + From<f32>>(
+    obj: &Obj<SimplePolygon>,
+    point_index: IndexTuple,
+) -> Vector3<N> {
+    let IndexTuple(_, _, ni) = point_index;
+    let normal = obj.normal[ni.expect("corner has a normal")];
+
+    Vector3::new(N::from(normal[0]), N::from(normal[1]), N::from(normal[2]))
+}
+
+/// Texture coordinates of a face corner. The corner must have a texture index.
+fn get_uv<N: Scalar + From<f32>>(obj: &Obj<SimplePolygon>, point_index: IndexTuple) -> Point2<N> {
+    let IndexTuple(_, ti, _) = point_index;
+    let uv = obj.texture[ti.expect("corner has texture coordinates")];
+
+    Point2::new(N::from(uv[0]), N::from(uv[1]))
+}
+
+/// Splits a face into triangles, given by the corners of the face they use.
+/// Faces with more than 3 vertices are triangulated.
+fn triangulate_face(
     obj: &Obj<SimplePolygon>,
     poly: &SimplePolygon,
-) -> Result<Triangle<N>, SceneLoadError> {
-    if poly.len() != 3 {
-        return Err(SceneLoadError::SceneContainsGeneralPolyError);
-    }
-    Ok(Triangle::new(
-        get_point(obj, poly[0]),
-        get_point(obj, poly[1]),
-        get_point(obj, poly[2]),
-    ))
+) -> Result<Vec<[IndexTuple; 3]>, SceneLoadError> {
+    if poly.len() < 3 {
+        return Err(SceneLoadError::DegeneratePolyError);
+    }
+    let points: Vec<Point3<f64>> = poly
+        .iter()
+        .map(|&i| Point3::from(get_point(obj, i)))
+        .collect();
+    Ok(triangulate(&points)
+        .into_iter()
+        .map(|[a, b, c]| [poly[a], poly[b], poly[c]])
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::plane::Plane;
+    use super::super::ray::tests::arb_ray;
+    use super::super::shape::DiffGeom;
+    use super::super::sphere::Sphere;
+    use super::super::temp_dir::TempDir;
+    use super::super::triangle::tests::arb_tri;
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    fn wall_scene() -> Scene<f64> {
+        let mut scene = Scene::empty();
+        let tri = Triangle::new(
+            Vector3::new(0.0, -1.0, 1.0),
+            Vector3::new(0.0, -1.0, -1.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        );
+        let material = UniformMaterial::new(Lambert::new(1.0, 1.0, 1.0));
+        scene.add_object(ShapeMat::new(tri, material));
+        scene
+    }
+
+    /// Writes an OBJ file with two faces facing -z, a triangle at x < 0 using material `blue`,
+    /// and a quad at x > 0 with no material.
+    fn write_obj_with_mtl(dir: &TempDir) -> PathBuf {
+        let path = write_obj(
+            dir,
+            "mtllib scene.mtl
+v -2 -1 0
+v -1 1 0
+v -3 1 0
+v 1 -1 0
+v 3 -1 0
+v 3 1 0
+v 1 1 0
+usemtl blue
+f 1 2 3
+g plain
+f 4 5 6 7
+",
+        );
+        std::fs::write(
+            path.with_extension("mtl"),
+            "newmtl blue\nKd 0.1 0.2 0.9\nKs 0.0 0.0 0.0\nillum 2\nmap_Kd unused.png\n",
+        )
+        .unwrap();
+        path
+    }
+
+    /// Writes `contents` to `scene.obj` in `dir`.
+    fn write_obj(dir: &TempDir, contents: &str) -> PathBuf {
+        let path = dir.join("scene.obj");
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Normal of the scene at the point straight down from `(x, y, 1)`.
+    fn normal_below(scene: &Scene<f64>, x: f64, y: f64) -> Vector3<f64> {
+        let ray = Ray::new_normalize(Point3::new(x, y, 1.0), Vector3::new(0.0, 0.0, -1.0));
+        let (_, hit) = scene.intersects_renderable(&ray).expect("ray hits a face");
+        hit.shading_normal
+    }
+
+    #[test]
+    fn load_uses_vertex_normals() {
+        let dir = TempDir::new("vertex_normals");
+        let path = write_obj(
+            &dir,
+            "v 0 0 0
+v 1 0 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 0 1
+vn 1 0 0
+vn 0 1 0
+vn 0 0 1
+f 1/1/1 2/2/2 3/3/3
+",
+        );
+        let scene = Scene::load(&path).unwrap();
+        let normal = normal_below(&scene, 0.25, 0.5);
+        let expected = Vector3::new(0.25, 0.25, 0.5).normalize();
+        assert!((normal - expected).magnitude() < 0.00001);
+    }
+
+    #[test]
+    fn load_smooths_across_crease_angle() {
+        // two faces meeting at a shallow fold along the y axis, without normals
+        let dir = TempDir::new("smooth_normals");
+        let path = write_obj(
+            &dir,
+            "v 0 0 0
+v 0 1 0
+v -1 0 0
+v 0.9553 0 0.2955
+f 1 2 3
+f 1 4 2
+",
+        );
+        let smooth = Scene::load(&path).unwrap();
+        let left = normal_below(&smooth, -0.001, 0.5);
+        let right = normal_below(&smooth, 0.001, 0.5);
+        assert!((left - right).magnitude() < 0.01);
+
+        let flat = Scene::load_with_options(
+            &path,
+            &LoadOptions {
+                crease_angle: 0.0,
+                ..LoadOptions::default()
+            },
+        )
+        .unwrap();
+        let left = normal_below(&flat, -0.001, 0.5);
+        let right = normal_below(&flat, 0.001, 0.5);
+        assert!((left - Vector3::z()).magnitude() < 0.00001);
+        assert!((left - right).magnitude() > 0.2);
+    }
+
+    fn color_at(scene: &Scene<f64>, x: f64) -> Spec<f64> {
+        let ray = Ray::new_normalize(Point3::new(x, 0.0, -1.0), Vector3::z());
+        let (object, hit) = scene.intersects_renderable(&ray).expect("ray hits a face");
+        // white light straight on is reflected as the diffuse color
+        let z = Vector3::z();
+        object.boxed_bsdf(&DiffGeom::from(&hit)).f(&z, &z) * std::f64::consts::PI
+    }
+
+    #[test]
+    fn load_uses_mtl_materials() {
+        let dir = TempDir::new("load_mtl");
+        let scene = Scene::load(&write_obj_with_mtl(&dir)).unwrap();
+        let blue = color_at(&scene, -2.0);
+        assert!((blue.red - 0.1).abs() < 0.00001);
+        assert!((blue.blue - 0.9).abs() < 0.00001);
+        // faces without a material get the default gray
+        let plain = color_at(&scene, 2.0);
+        assert!((plain.red - plain.blue).abs() < 0.00001);
+        assert!(plain.red > 0.0);
+        // the quad is split into two triangles
+        assert_eq!(scene.objects().len(), 3);
+        assert!(scene
+            .intersects_renderable(&Ray::new_normalize(
+                Point3::new(2.9, -0.9, -1.0),
+                Vector3::z()
+            ))
+            .is_some());
+    }
+
+    #[test]
+    fn load_uses_mtl_emission() {
+        let dir = TempDir::new("load_emission");
+        let path = write_obj_with_mtl(&dir);
+        let scene = Scene::<f64>::load(&path).unwrap();
+        assert_eq!(scene.emitters().count(), 0);
+        // without emitters the scene is lit by the fallback light
+        assert_eq!(scene.lights.len(), 1);
+
+        std::fs::write(path.with_extension("mtl"), "newmtl blue\nKe 2 2 2\n").unwrap();
+        let scene = Scene::<f64>::load(&path).unwrap();
+        assert_eq!(scene.emitters().count(), 1);
+        assert!(scene.lights.is_empty());
+    }
+
+    #[test]
+    fn load_uses_mtl_textures() {
+        let dir = TempDir::new("load_texture");
+        let path = write_obj(
+            &dir,
+            "mtllib scene.mtl
+v -1 -1 0
+v 1 -1 0
+v 1 1 0
+v -1 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+usemtl checked
+f 1/1 2/2 3/3 4/4
+",
+        );
+        let mtl = "newmtl checked\nKd 0.5 0.5 0.5\nmap_Kd -clamp on textures/check.png\n";
+        std::fs::write(path.with_extension("mtl"), mtl).unwrap();
+        // without the image the face keeps its `Kd`
+        let missing = Scene::load(&path).unwrap();
+        assert!((color_at(&missing, 0.5).red - 0.5).abs() < 1e-6);
+        match missing.warnings() {
+            [LoadWarning::SkippedTexture(skipped, TextureLoadError::OpenError)] => {
+                assert_eq!(skipped, &dir.join("textures/check.png"))
+            }
+            warnings => panic!("unexpected warnings {:?}", warnings),
+        }
+
+        // a 2 by 1 image, black on the left and white on the right
+        std::fs::create_dir_all(dir.join("textures")).unwrap();
+        let mut image = image::RgbImage::new(2, 1);
+        image.put_pixel(1, 0, image::Rgb([255, 255, 255]));
+        image.save(dir.join("textures/check.png")).unwrap();
+        let scene = Scene::load(&path).unwrap();
+        assert!(scene.warnings().is_empty());
+        // the centres of the pixels are a quarter of the way in from each side
+        let dark = color_at(&scene, -0.5);
+        assert!(dark.red < 1e-6);
+        // `Kd` scales the texture
+        let light = color_at(&scene, 0.5);
+        assert!((light.red - 0.5).abs() < 1e-6);
+        // clamped, the left edge doesn't blend with the white texel on the other side
+        let edge = color_at(&scene, -0.99);
+        assert!(edge.red < 1e-6);
+    }
+
+    #[test]
+    fn load_uses_mtl_normal_maps() {
+        let dir = TempDir::new("load_normal_map");
+        let path = write_obj(
+            &dir,
+            "mtllib scene.mtl
+v -1 -1 0
+v 1 -1 0
+v 1 1 0
+v -1 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+usemtl bent
+f 1/1 2/2 3/3 4/4
+",
+        );
+        let normal_at = |scene: &Scene<f64>| {
+            let ray = Ray::new_normalize(Point3::new(0.5, 0.0, -1.0), Vector3::z());
+            let (object, hit) = scene.intersects_renderable(&ray).expect("ray hits a face");
+            object.shading_geometry(DiffGeom::from(&hit)).normal
+        };
+        std::fs::write(path.with_extension("mtl"), "newmtl bent\nnorm bent.png\n").unwrap();
+        // without the image the surface stays smooth
+        let smooth = Scene::load(&path).unwrap();
+        assert!((normal_at(&smooth) - Vector3::z()).magnitude() < 1e-10);
+        assert!(matches!(
+            smooth.warnings(),
+            [LoadWarning::SkippedTexture(_, _)]
+        ));
+
+        // a normal leaning towards increasing u, which runs along +x
+        let mut image = image::RgbImage::new(1, 1);
+        image.put_pixel(0, 0, image::Rgb([218, 128, 218]));
+        image.save(path.with_file_name("bent.png")).unwrap();
+        let normal = normal_at(&Scene::load(&path).unwrap());
+        assert!((normal.x - (0.5f64).sqrt()).abs() < 0.01);
+        assert!(normal.y.abs() < 0.01);
+
+        // `-bm` scales the tilt
+        let mtl = "newmtl bent\nnorm -bm 0.5 bent.png\n";
+        std::fs::write(path.with_extension("mtl"), mtl).unwrap();
+        let normal = normal_at(&Scene::load(&path).unwrap());
+        assert!((normal.x - (0.2f64).sqrt()).abs() < 0.01);
+    }
+
+    #[test]
+    fn unreadable_mtl_gives_default_material() {
+        let dir = TempDir::new("missing_mtl");
+        let path = write_obj_with_mtl(&dir);
+        std::fs::remove_file(path.with_extension("mtl")).unwrap();
+        let missing = Scene::load(&path).unwrap();
+        match missing.warnings() {
+            [LoadWarning::SkippedMaterialLibrary(skipped, _)] => {
+                assert_eq!(skipped, &path.with_extension("mtl"))
+            }
+            warnings => panic!("unexpected warnings {:?}", warnings),
+        }
+        // the blue face is the same as the one without a material
+        let default = color_at(&missing, 2.0);
+        let blue = color_at(&missing, -2.0);
+        assert!((blue.red - default.red).abs() < 1e-6);
+        assert!((blue.blue - default.blue).abs() < 1e-6);
+
+        std::fs::write(path.with_extension("mtl"), "newmtl blue\nKd blue\n").unwrap();
+        let malformed = Scene::load(&path).unwrap();
+        assert!(matches!(
+            malformed.warnings(),
+            [LoadWarning::SkippedMaterialLibrary(_, _)]
+        ));
+        assert!((color_at(&malformed, -2.0).red - default.red).abs() < 1e-6);
+    }
+
+    #[test]
+    fn occluded_by_object_in_front() {
+        let scene = wall_scene();
+        let ray = Ray::new_normalize(Point3::new(-1.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0))
+            .with_interval(0.0, 2.0);
+        assert!(scene.is_occluded(&ray));
+    }
+
+    #[test]
+    fn not_occluded_past_max_t() {
+        let scene = wall_scene();
+        let ray = Ray::new_normalize(Point3::new(-1.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0))
+            .with_interval(0.0, 0.5);
+        assert!(!scene.is_occluded(&ray));
+    }
+
+    #[test]
+    fn mixed_objects_closest_hit() {
+        let mut scene = wall_scene();
+        let gray = UniformMaterial::new(Lambert::new(0.5, 0.5, 0.5));
+        scene.add_object(ShapeMat::new(
+            Sphere::new(Point3::new(-2.0, 0.0, 0.0), 0.5),
+            gray.clone(),
+        ));
+        scene.add_object(ShapeMat::new(Plane::new(Vector3::x(), 5.0), gray));
+
+        let ray = Ray::new_normalize(Point3::new(-10.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        // the plane at x = -5 is in front of the sphere
+        let (_, hit) = scene
+            .intersects_renderable(&ray)
+            .expect("ray hits the plane");
+        assert!((hit.t - 5.0).abs() < 0.00001);
+        assert_eq!(hit.primitive_id, 2);
+
+        let ray = Ray::new_normalize(Point3::new(-4.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        let (_, hit) = scene
+            .intersects_renderable(&ray)
+            .expect("ray hits the sphere");
+        assert!((hit.t - 1.5).abs() < 0.00001);
+        assert_eq!(hit.primitive_id, 1);
+        assert!((hit.geometric_normal - Vector3::new(-1.0, 0.0, 0.0)).magnitude() < 0.00001);
+    }
+
+    proptest! {
+        #[test]
+        fn added_objects_are_found(first in vec(arb_tri(-10f64..10.0), 0..32),
+                                   later in vec(arb_tri(-10f64..10.0), 0..8),
+                                   rays in vec(arb_ray(-10f64..10.0, -1f64..1.0), 16)) {
+            let material = UniformMaterial::new(Lambert::new(1.0, 1.0, 1.0));
+            let mut scene = Scene::empty();
+            scene.add_objects(first.into_iter().map(|tri| {
+                Box::new(ShapeMat::new(tri, material.clone())) as Box<dyn SceneObject<f64>>
+            }));
+            // objects added one at a time after the first batch must be found too
+            for tri in later {
+                scene.add_object(ShapeMat::new(tri, material.clone()));
+            }
+            for ray in rays.iter().filter(|r| r.direction.magnitude() > 0.5) {
+                let expected = scene
+                    .objects()
+                    .iter()
+                    .filter_map(|o| o.intersection(ray).map(|hit| hit.t))
+                    .fold(None, |closest: Option<f64>, t| Some(closest.map_or(t, |c| c.min(t))));
+                let actual = scene.intersects_renderable(ray).map(|(_, hit)| hit.t);
+                prop_assert_eq!(expected, actual);
+                prop_assert_eq!(expected.is_some(), scene.is_occluded(ray));
+            }
+        }
+    }
 }
@@ -1,29 +1,208 @@
-use na::{Point3, Scalar, Vector3};
+use na::{Point2, Point3, RealField, Scalar, Vector3};
 
+use super::aabb::Aabb;
 use super::ray::Ray;
+use crate::number::Float;
 
 pub trait Shape {
     type NumTy: Scalar;
+
+    /// Returns a box that contains the whole shape.
+    fn bounds(&self) -> Aabb<Self::NumTy>;
+
     /// Returns where the ray intersects the given shape, or None if no intersection exists.
     /// If there are multiple intersections, this should return the closest point
     ///
-    /// The value returned is the scalar on the direction of the ray to reach to the intersection
-    /// point. That is `intersection_point = ray.time_at(return_value)` is the actual point value
-    /// of the intersection.
-    fn intersection(&self, ray: &Ray<Self::NumTy>) -> Option<Self::NumTy>;
+    /// `t` of the returned interaction is the scalar on the direction of the ray to reach to the
+    /// intersection point. That is `interaction.point = ray.time_at(interaction.t)`.
+    fn intersection(&self, ray: &Ray<Self::NumTy>) -> Option<SurfaceInteraction<Self::NumTy>>;
+
+    /// Picks a point on the surface, mapped from the uniform random numbers `u1` and `u2` in
+    /// `[0, 1)`. Shapes that can't be sampled, such as infinite planes, return None.
+    fn sample(&self, _u1: Self::NumTy, _u2: Self::NumTy) -> Option<SurfaceSample<Self::NumTy>> {
+        None
+    }
+}
+
+impl<S: Shape + ?Sized> Shape for Box<S> {
+    type NumTy = S::NumTy;
+
+    fn bounds(&self) -> Aabb<Self::NumTy> {
+        (**self).bounds()
+    }
+
+    fn intersection(&self, ray: &Ray<Self::NumTy>) -> Option<SurfaceInteraction<Self::NumTy>> {
+        (**self).intersection(ray)
+    }
+
+    fn sample(&self, u1: Self::NumTy, u2: Self::NumTy) -> Option<SurfaceSample<Self::NumTy>> {
+        (**self).sample(u1, u2)
+    }
+}
+
+/// Point picked on the surface of a shape by `Shape::sample`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SurfaceSample<T: Scalar> {
+    pub point: Point3<T>,
+    /// Bound on the absolute rounding error in each component of `point`.
+    pub point_error: Vector3<T>,
+    /// Unit normal of the surface.
+    pub normal: Vector3<T>,
+    /// Probability density of picking the point, with respect to surface area.
+    pub pdf: T,
+}
+
+/// Everything known about where a ray hits a shape.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SurfaceInteraction<T: Scalar> {
+    /// Ray time of the hit.
+    pub t: T,
+    pub point: Point3<T>,
+    /// Bound on the absolute rounding error in each component of `point`.
+    pub point_error: Vector3<T>,
+    /// Unit normal of the actual surface.
+    pub geometric_normal: Vector3<T>,
+    /// Unit normal used for shading. This differs from the geometric normal when normals are
+    /// interpolated across a surface.
+    pub shading_normal: Vector3<T>,
+    /// Surface coordinates of the hit.
+    pub uv: Point2<T>,
+    /// Partial derivatives of the point with respect to the surface coordinates.
+    pub dpdu: Vector3<T>,
+    pub dpdv: Vector3<T>,
+    /// Index of the shape that was hit, when searching a list of shapes. Shapes set this to 0,
+    /// and it is filled in by whatever searched the list.
+    pub primitive_id: usize,
+}
 
-    /// Returns a normal for the shape on a given surface point.
-    /// This does not need to be meaningful for points that cannot be intersection points.
-    fn normal(&self, point: &Point3<Self::NumTy>) -> Vector3<Self::NumTy>;
+impl<T: Scalar> SurfaceInteraction<T> {
+    /// An interaction with matching geometric and shading normals.
+    pub fn new(
+        t: T,
+        point: Point3<T>,
+        point_error: Vector3<T>,
+        normal: Vector3<T>,
+        uv: Point2<T>,
+        dpdu: Vector3<T>,
+        dpdv: Vector3<T>,
+    ) -> SurfaceInteraction<T> {
+        SurfaceInteraction {
+            t,
+            point,
+            point_error,
+            geometric_normal: normal,
+            shading_normal: normal,
+            uv,
+            dpdu,
+            dpdv,
+            primitive_id: 0,
+        }
+    }
+
+    /// Replaces the shading normal.
+    pub fn with_shading_normal(self, shading_normal: Vector3<T>) -> SurfaceInteraction<T> {
+        SurfaceInteraction {
+            shading_normal,
+            ..self
+        }
+    }
+}
+
+impl<T: Float> SurfaceInteraction<T> {
+    /// Origin for rays leaving the surface in direction `w`. The point is moved off the surface
+    /// along the geometric normal, far enough that the ray can't hit the surface it is leaving.
+    pub fn offset_origin(&self, w: &Vector3<T>) -> Point3<T> {
+        offset_point(&self.point, &self.point_error, &self.geometric_normal, w)
+    }
+
+    /// Ray leaving the surface in `direction`.
+    pub fn spawn_ray(&self, direction: Vector3<T>) -> Ray<T> {
+        Ray::new_normalize(self.offset_origin(&direction), direction)
+    }
+
+    /// Ray leaving the surface towards `target`, ending at the target.
+    pub fn spawn_ray_to(&self, target: &Point3<T>) -> Ray<T> {
+        let origin = self.offset_origin(&(target - self.point));
+        let direction = target - origin;
+        let distance = direction.magnitude();
+        Ray::new_normalize(origin, direction).with_interval(T::zero(), distance)
+    }
+
+    /// Ray leaving the surface towards a point sampled on another surface. Both ends are
+    /// offset, so the ray hits neither surface because of rounding error.
+    pub fn spawn_ray_to_sample(&self, target: &SurfaceSample<T>) -> Ray<T> {
+        let origin = self.offset_origin(&(target.point - self.point));
+        let end = offset_point(
+            &target.point,
+            &target.point_error,
+            &target.normal,
+            &(origin - target.point),
+        );
+        let direction = end - origin;
+        let distance = direction.magnitude();
+        Ray::new_normalize(origin, direction).with_interval(T::zero(), distance)
+    }
+}
+
+/// Moves `point` along the surface `normal`, to the side `w` points to, just far enough to be
+/// outside the error bounds of the point. Rays from there cannot hit the surface because of
+/// rounding error.
+fn offset_point<T: Float>(
+    point: &Point3<T>,
+    point_error: &Vector3<T>,
+    normal: &Vector3<T>,
+    w: &Vector3<T>,
+) -> Point3<T> {
+    let distance = normal.abs().dot(point_error);
+    let mut offset = normal * distance;
+    if w.dot(normal) < T::zero() {
+        offset = -offset;
+    }
+    let mut origin = point + offset;
+    // round away from the surface, so the rounding of the addition can't undo the offset
+    for axis in 0..3 {
+        if offset[axis] > T::zero() {
+            origin[axis] = Float::next_up(origin[axis]);
+        } else if offset[axis] < T::zero() {
+            origin[axis] = Float::next_down(origin[axis]);
+        }
+    }
+    origin
 }
 
+/// Local surface geometry at a point, used by materials to build their BSDF.
+#[derive(Debug, Clone)]
 pub struct DiffGeom<T: Scalar> {
-    position: Point3<T>,
-    normal: Vector3<T>,
+    pub position: Point3<T>,
+    /// Shading normal, on the outside of the surface. Materials may bend it, to add detail like
+    /// bumps.
+    pub normal: Vector3<T>,
+    /// Unit normal of the actual surface, on the same side as `normal`. Light can't cross the
+    /// surface without passing through it, even where the shading normal says otherwise.
+    pub geometric_normal: Vector3<T>,
+    pub uv: Point2<T>,
+    pub dpdu: Vector3<T>,
+    pub dpdv: Vector3<T>,
+    /// Width, in surface coordinates, of the area of the surface a sample stands for. Textures
+    /// average away detail smaller than this. It is 0 for a single point.
+    pub uv_width: T,
 }
 
-impl<T: Scalar> DiffGeom<T> {
-    pub fn new(position: Point3<T>, normal: Vector3<T>) -> DiffGeom<T> {
-        DiffGeom { position, normal }
+impl<'a, T: RealField> From<&'a SurfaceInteraction<T>> for DiffGeom<T> {
+    fn from(interaction: &'a SurfaceInteraction<T>) -> DiffGeom<T> {
+        let geometric = interaction.geometric_normal;
+        DiffGeom {
+            position: interaction.point,
+            normal: interaction.shading_normal,
+            geometric_normal: if geometric.dot(&interaction.shading_normal) < T::zero() {
+                -geometric
+            } else {
+                geometric
+            },
+            uv: interaction.uv,
+            dpdu: interaction.dpdu,
+            dpdv: interaction.dpdv,
+            uv_width: T::zero(),
+        }
     }
 }
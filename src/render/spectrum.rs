@@ -1,4 +1,5 @@
-use std::ops::Mul;
+use crate::number::Float;
+use std::ops::{Add, AddAssign, Mul};
 
 #[derive(Clone)]
 pub struct Spec<T> {
@@ -13,6 +14,27 @@ impl<T> Spec<T> {
     }
 }
 
+impl<T: PartialOrd + Copy> Spec<T> {
+    /// Returns the largest of the three color channels.
+    pub fn max_component(&self) -> T {
+        let mut max = self.red;
+        if self.green > max {
+            max = self.green;
+        }
+        if self.blue > max {
+            max = self.blue;
+        }
+        max
+    }
+}
+
+impl<N: Float> Spec<N> {
+    /// Brightness of a linear RGB color as seen by the eye.
+    pub fn luminance(&self) -> N {
+        self.red * N::from(0.2126) + self.green * N::from(0.7152) + self.blue * N::from(0.0722)
+    }
+}
+
 impl<T: Mul<T, Output = T> + Copy> Mul<T> for Spec<T> {
     type Output = Spec<T>;
 
@@ -32,3 +54,23 @@ impl<T: Mul<T, Output = T>> Mul<Spec<T>> for Spec<T> {
         )
     }
 }
+
+impl<T: Add<T, Output = T>> Add<Spec<T>> for Spec<T> {
+    type Output = Spec<T>;
+
+    fn add(self, rhs: Spec<T>) -> Spec<T> {
+        Spec::new(
+            self.red + rhs.red,
+            self.green + rhs.green,
+            self.blue + rhs.blue,
+        )
+    }
+}
+
+impl<T: AddAssign<T>> AddAssign<Spec<T>> for Spec<T> {
+    fn add_assign(&mut self, rhs: Spec<T>) {
+        self.red += rhs.red;
+        self.green += rhs.green;
+        self.blue += rhs.blue;
+    }
+}
@@ -1,6 +1,8 @@
+use super::aabb::Aabb;
 use super::ray;
 use super::shape::*;
-use na::{Point3, RealField, Scalar, Vector3};
+use crate::number::Float;
+use na::{Point2, Point3, Scalar, Vector3};
 use num::Zero;
 use std::cmp::PartialOrd;
 
@@ -12,23 +14,24 @@ pub struct Sphere<T: Scalar> {
 
 impl<T: Scalar + Zero + PartialOrd> Sphere<T> {
     pub fn new(center: Point3<T>, radius: T) -> Sphere<T> {
-        let r: T;
-        if T::zero().lt(&radius) {
-            r = radius;
+        let radius = if T::zero().lt(&radius) {
+            radius
         } else {
-            r = T::zero();
-        }
-        Sphere {
-            center: center,
-            radius: r,
-        }
+            T::zero()
+        };
+        Sphere { center, radius }
     }
 }
 
-impl<N: RealField> Shape for Sphere<N> {
+impl<N: Float> Shape for Sphere<N> {
     type NumTy = N;
 
-    fn intersection(&self, ray: &ray::Ray<N>) -> Option<N> {
+    fn bounds(&self) -> Aabb<N> {
+        let r = Vector3::repeat(self.radius);
+        Aabb::new(self.center - r, self.center + r)
+    }
+
+    fn intersection(&self, ray: &ray::Ray<N>) -> Option<SurfaceInteraction<N>> {
         let ray_offset: Vector3<N> = ray.origin - self.center;
         let a: N = ray.direction.norm_squared();
         let b: N = double(ray_offset.dot(&ray.direction));
@@ -36,24 +39,58 @@ impl<N: RealField> Shape for Sphere<N> {
         let discriminant = (b * b) - double(double(a * c));
         if discriminant < N::zero() {
             return None;
+        }
+        let disc_sq = discriminant.sqrt();
+        let near = (-b - disc_sq) / double(a);
+        let far = (-b + disc_sq) / double(a);
+        if ray.contains(near) {
+            Some(self.interaction_at(ray, near))
+        } else if ray.contains(far) {
+            Some(self.interaction_at(ray, far))
         } else {
-            let disc_sq = discriminant.sqrt();
-            let numerator = -b - disc_sq;
-            if numerator > N::zero() {
-                return Some(numerator / double(a));
-            }
-
-            let numerator = -b + disc_sq;
-            if numerator > N::zero() {
-                return Some(numerator / double(a));
-            } else {
-                return None;
-            }
+            None
         }
     }
 
-    fn normal(&self, point: &Point3<Self::NumTy>) -> Vector3<Self::NumTy> {
-        point - self.center
+    /// Picks a point uniformly over the whole surface.
+    fn sample(&self, u1: N, u2: N) -> Option<SurfaceSample<N>> {
+        let z = N::one() - double(u1);
+        let r = (N::one() - z * z).max(N::zero()).sqrt();
+        let phi = N::two_pi() * u2;
+        let normal = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+        let p = normal * self.radius;
+        let point = self.center + p;
+        let area = double(N::two_pi()) * self.radius * self.radius;
+        Some(SurfaceSample {
+            point,
+            point_error: p.abs() * N::gamma_bound(5) + point.coords.abs() * N::gamma_bound(1),
+            normal,
+            pdf: N::one() / area,
+        })
+    }
+}
+
+impl<N: Float> Sphere<N> {
+    /// Interaction at time `t` on the ray. The surface coordinates are the longitude `u` around
+    /// the z axis and latitude `v` from +z, both scaled to `[0, 1]`.
+    fn interaction_at(&self, ray: &ray::Ray<N>, t: N) -> SurfaceInteraction<N> {
+        let p = ray.at_time(t) - self.center;
+        let normal = p.try_normalize(N::zero()).unwrap_or_else(Vector3::z);
+        // move the point onto the surface, which leaves less error than the ray equation
+        let p = normal * self.radius;
+        let point = self.center + p;
+        let point_error = p.abs() * N::gamma_bound(5) + point.coords.abs() * N::gamma_bound(1);
+        let mut phi = p.y.atan2(p.x);
+        if phi < N::zero() {
+            phi += N::two_pi();
+        }
+        let cos_theta = normal.z.max(-N::one()).min(N::one());
+        let sin_theta = (N::one() - cos_theta * cos_theta).max(N::zero()).sqrt();
+        let uv = Point2::new(phi / N::two_pi(), cos_theta.acos() / N::pi());
+        let dpdu = Vector3::new(-p.y, p.x, N::zero()) * N::two_pi();
+        let dpdv =
+            Vector3::new(p.z * phi.cos(), p.z * phi.sin(), -self.radius * sin_theta) * N::pi();
+        SurfaceInteraction::new(t, point, point_error, normal, uv, dpdu, dpdv)
     }
 }
 
@@ -72,13 +109,10 @@ mod tests {
 
     const DELTA: f32 = 0.001;
 
-    pub fn arb_sphere<T: Scalar>(
+    pub fn arb_sphere<T: Scalar + Arbitrary + Zero + PartialOrd>(
         c: impl Strategy<Value = T> + Clone,
         r: impl Strategy<Value = T> + Clone,
-    ) -> impl Strategy<Value = Sphere<T>>
-    where
-        T: Arbitrary + Zero + PartialOrd,
-    {
+    ) -> impl Strategy<Value = Sphere<T>> {
         (arb_point(c.clone()), r.clone()).prop_map(|(cnt, rad)| Sphere::new(cnt, rad))
     }
 
@@ -87,7 +121,7 @@ mod tests {
         let s: Sphere<f32> = Sphere {
             center: Point3::origin(),
             radius: 5.0,
-        };;
+        };
         let r: Ray<f32> = Ray::new_normalize(Point3::origin(), Vector3::new(1.0, 0.0, 0.0));
         let r2: Ray<f32> =
             Ray::new_normalize(Point3::new(100.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
@@ -112,12 +146,47 @@ mod tests {
         fn intersection_on_surface(r in arb_ray(-100f32..100f32, -100f32..100f32),
                                    s in arb_sphere(-100f32..100f32, 0f32..100f32)) {
             prop_assume!(r.direction.magnitude() > DELTA);
-            let intersection = s.intersection(&r).map(|t| r.at_time(t));
+            let intersection = s.intersection(&r).map(|si| si.point);
 
-            match intersection {
-                Some(point) => prop_assert!(abs_diff_eq!((point - s.center).magnitude(), s.radius, epsilon = DELTA)),
-                None => (),
+            if let Some(point) = intersection {
+                prop_assert!(abs_diff_eq!((point - s.center).magnitude(), s.radius, epsilon = DELTA));
             }
         }
+
+        #[test]
+        fn surface_coordinates_in_range(r in arb_ray(-100f64..100.0, -100f64..100.0),
+                                        s in arb_sphere(-100f64..100.0, 0.1f64..100.0)) {
+            prop_assume!(r.direction.magnitude() > 0.001);
+            if let Some(si) = s.intersection(&r) {
+                prop_assert!((0.0..=1.0).contains(&si.uv.x));
+                prop_assert!((0.0..=1.0).contains(&si.uv.y));
+                // the derivatives lie in the tangent plane
+                prop_assert!((si.dpdu.dot(&si.geometric_normal) / s.radius).abs() < 0.0001);
+                prop_assert!((si.dpdv.dot(&si.geometric_normal) / s.radius).abs() < 0.0001);
+            }
+        }
+
+        #[test]
+        fn spawned_ray_leaves_surface(r in arb_ray(-100f32..100f32, -100f32..100f32),
+                                      s in arb_sphere(-100f32..100f32, 0.1f32..100f32)) {
+            prop_assume!(r.direction.magnitude() > DELTA);
+            if let Some(si) = s.intersection(&r) {
+                // leaving straight out of the sphere can't hit it again
+                let outward = si.spawn_ray(si.geometric_normal);
+                prop_assert!(s.intersection(&outward).is_none());
+            }
+        }
+
+        #[test]
+        fn samples_on_surface(s in arb_sphere(-100f64..100.0, 0.1f64..100.0),
+                              u1 in 0f64..1.0,
+                              u2 in 0f64..1.0) {
+            let sample = s.sample(u1, u2).unwrap();
+            let offset = sample.point - s.center;
+            prop_assert!(abs_diff_eq!(offset.magnitude(), s.radius, epsilon = 0.0001));
+            prop_assert!((offset.normalize() - sample.normal).magnitude() < 0.0001);
+            let area = 4.0 * std::f64::consts::PI * s.radius * s.radius;
+            prop_assert!(abs_diff_eq!(sample.pdf * area, 1.0, epsilon = 0.0001));
+        }
     }
 }
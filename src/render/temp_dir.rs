@@ -0,0 +1,56 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Directory for tests that read and write files, removed with everything in it when dropped.
+///
+/// Each directory gets a new name, so tests running at the same time never share files.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates an empty directory, with `name` in its path to tell which test made it.
+    pub fn new(name: &str) -> TempDir {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "rust-tracer-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path of `file` in the directory.
+    pub fn join<P: AsRef<Path>>(&self, file: P) -> PathBuf {
+        self.path.join(file)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        // failing to clean up shouldn't fail the test
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_when_dropped() {
+        let dir = TempDir::new("temp_dir");
+        let other = TempDir::new("temp_dir");
+        assert_ne!(dir.path(), other.path());
+        std::fs::write(dir.join("file.txt"), "contents").unwrap();
+        let path = dir.path().to_path_buf();
+        drop(dir);
+        assert!(!path.exists());
+    }
+}
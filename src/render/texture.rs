@@ -0,0 +1,724 @@
+use super::shape::DiffGeom;
+use super::spectrum::Spec;
+use crate::number::Float;
+use image::ImageError;
+use na::{Point2, Point3, Scalar};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Value that varies across a surface, like the color of a material.
+///
+/// Textures are evaluated at the surface coordinates or the position of a point, and may use the
+/// rest of the `DiffGeom` to filter out detail smaller than the area a sample covers.
+pub trait Texture<T>: Send + Sync {
+    type NumTy: Scalar;
+
+    fn evaluate(&self, g: &DiffGeom<Self::NumTy>) -> T;
+}
+
+impl<T, X: Texture<T> + ?Sized> Texture<T> for Arc<X> {
+    type NumTy = X::NumTy;
+
+    fn evaluate(&self, g: &DiffGeom<Self::NumTy>) -> T {
+        (**self).evaluate(g)
+    }
+}
+
+/// The same value everywhere.
+#[derive(Debug, Clone)]
+pub struct ConstantTexture<T> {
+    value: T,
+}
+
+impl<T> ConstantTexture<T> {
+    pub fn new(value: T) -> ConstantTexture<T> {
+        ConstantTexture { value }
+    }
+}
+
+impl<N: Float> Texture<Spec<N>> for ConstantTexture<Spec<N>> {
+    type NumTy = N;
+
+    fn evaluate(&self, _g: &DiffGeom<N>) -> Spec<N> {
+        self.value.clone()
+    }
+}
+
+impl<N: Float> Texture<N> for ConstantTexture<N> {
+    type NumTy = N;
+
+    fn evaluate(&self, _g: &DiffGeom<N>) -> N {
+        self.value
+    }
+}
+
+/// Texture multiplied by a constant, like an MTL `map_Kd` image tinted by `Kd`.
+#[derive(Debug, Clone)]
+pub struct ScaledTexture<X, T> {
+    texture: X,
+    scale: T,
+}
+
+impl<X, T> ScaledTexture<X, T> {
+    pub fn new(texture: X, scale: T) -> ScaledTexture<X, T> {
+        ScaledTexture { texture, scale }
+    }
+}
+
+impl<N: Float, X: Texture<Spec<N>, NumTy = N>> Texture<Spec<N>> for ScaledTexture<X, Spec<N>> {
+    type NumTy = N;
+
+    fn evaluate(&self, g: &DiffGeom<N>) -> Spec<N> {
+        self.texture.evaluate(g) * self.scale.clone()
+    }
+}
+
+/// What an image texture shows outside of `[0, 1]` surface coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WrapMode {
+    /// Tiles the image.
+    #[default]
+    Repeat,
+    /// Stretches the edge pixels outwards.
+    Clamp,
+    /// Black outside the image.
+    Black,
+}
+
+/// How an image texture blends its pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextureFilter {
+    /// Blends the 4 nearest pixels of the full size image. Detail smaller than a sample flickers
+    /// from sample to sample.
+    Bilinear,
+    /// Blends the 4 nearest pixels in the two sizes of the image closest to the area a sample
+    /// covers, so that detail too small to see is averaged away.
+    #[default]
+    Trilinear,
+}
+
+#[derive(Debug)]
+pub enum TextureLoadError {
+    OpenError,
+    DecodeError,
+}
+
+/// Color read from an image, mapped onto the surface coordinates.
+///
+/// `(0, 0)` is the bottom left of the image and `(1, 1)` the top right, as in OBJ files. Smaller
+/// copies of the image, each half the size of the last, are made up front for `Trilinear`
+/// filtering.
+#[derive(Clone)]
+pub struct ImageTexture<N: Scalar> {
+    /// The image at full size followed by each smaller copy, down to a single pixel.
+    levels: Vec<MipLevel<N>>,
+    wrap: WrapMode,
+    filter: TextureFilter,
+}
+
+#[derive(Clone)]
+struct MipLevel<N: Scalar> {
+    width: usize,
+    height: usize,
+    /// Linear color of each pixel, row by row from the top.
+    pixels: Vec<Spec<N>>,
+}
+
+impl<N: Float> ImageTexture<N> {
+    /// Texture with the given linear color for each pixel, row by row from the top.
+    pub fn new(width: usize, height: usize, pixels: Vec<Spec<N>>) -> ImageTexture<N> {
+        assert_eq!(pixels.len(), width * height, "wrong number of pixels");
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            pixels,
+        }];
+        while let Some(smaller) = levels.last().and_then(MipLevel::half_size) {
+            levels.push(smaller);
+        }
+        ImageTexture {
+            levels,
+            wrap: WrapMode::default(),
+            filter: TextureFilter::default(),
+        }
+    }
+
+    /// Reads an 8 bit image, such as a PNG or JPEG. The pixels are taken to be sRGB, as in most
+    /// image files, and converted to linear color.
+    pub fn load(path: &Path) -> Result<ImageTexture<N>, TextureLoadError> {
+        ImageTexture::load_with(path, srgb_to_linear)
+    }
+
+    /// Reads an 8 bit image holding data rather than color, like a bump or normal map. Each
+    /// channel is scaled to `[0, 1]` with no sRGB conversion.
+    pub fn load_linear(path: &Path) -> Result<ImageTexture<N>, TextureLoadError> {
+        ImageTexture::load_with(path, |value| N::from(f32::from(value) / 255.0))
+    }
+
+    fn load_with<F: Fn(u8) -> N>(
+        path: &Path,
+        decode: F,
+    ) -> Result<ImageTexture<N>, TextureLoadError> {
+        let image = image::open(path)
+            .map_err(|err| match err {
+                ImageError::IoError(_) => TextureLoadError::OpenError,
+                _ => TextureLoadError::DecodeError,
+            })?
+            .to_rgb();
+        let (width, height) = image.dimensions();
+        let pixels = image
+            .pixels()
+            .map(|p| Spec::new(decode(p[0]), decode(p[1]), decode(p[2])))
+            .collect();
+        Ok(ImageTexture::new(width as usize, height as usize, pixels))
+    }
+
+    pub fn with_wrap(self, wrap: WrapMode) -> ImageTexture<N> {
+        ImageTexture { wrap, ..self }
+    }
+
+    pub fn with_filter(self, filter: TextureFilter) -> ImageTexture<N> {
+        ImageTexture { filter, ..self }
+    }
+
+    /// Color at `uv`, averaged over an area about `width` across in surface coordinates.
+    /// Bilinear filtering ignores `width`.
+    pub fn lookup(&self, uv: &Point2<N>, width: N) -> Spec<N> {
+        if self.filter == TextureFilter::Bilinear {
+            return self.bilinear(0, uv);
+        }
+        // the level where one pixel is about `width` across
+        let full_size = self.levels[0].width.max(self.levels[0].height) as f32;
+        let top = (self.levels.len() - 1) as f32;
+        let level = (width * N::from(full_size))
+            .max(N::from(1e-8))
+            .log2()
+            .max(N::zero())
+            .min(N::from(top));
+        let below = level.floor();
+        let blend = level - below;
+        let index = below.to_subset().unwrap_or(0.0) as usize;
+        if blend <= N::zero() {
+            return self.bilinear(index, uv);
+        }
+        self.bilinear(index, uv) * (N::one() - blend) + self.bilinear(index + 1, uv) * blend
+    }
+
+    /// Blend of the 4 pixels of `level` nearest to `uv`.
+    fn bilinear(&self, level: usize, uv: &Point2<N>) -> Spec<N> {
+        let image = &self.levels[level];
+        let half = N::from(0.5);
+        let x = uv.x * N::from(image.width as f32) - half;
+        let y = (N::one() - uv.y) * N::from(image.height as f32) - half;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let dx = x - x0;
+        let dy = y - y0;
+        let x0 = floor_index(x0);
+        let y0 = floor_index(y0);
+        let texel = |x, y| image.texel(x, y, self.wrap);
+        texel(x0, y0) * ((N::one() - dx) * (N::one() - dy))
+            + texel(x0 + 1, y0) * (dx * (N::one() - dy))
+            + texel(x0, y0 + 1) * ((N::one() - dx) * dy)
+            + texel(x0 + 1, y0 + 1) * (dx * dy)
+    }
+}
+
+impl<N: Float> MipLevel<N> {
+    /// Pixel at column `x` and row `y`, which may be outside the image.
+    fn texel(&self, x: i64, y: i64, wrap: WrapMode) -> Spec<N> {
+        let wrap_axis = |i: i64, size: usize| -> Option<usize> {
+            let size = size as i64;
+            match wrap {
+                WrapMode::Repeat => Some(i.rem_euclid(size) as usize),
+                WrapMode::Clamp => Some(i.max(0).min(size - 1) as usize),
+                WrapMode::Black if i < 0 || i >= size => None,
+                WrapMode::Black => Some(i as usize),
+            }
+        };
+        match (wrap_axis(x, self.width), wrap_axis(y, self.height)) {
+            (Some(x), Some(y)) => self.pixels[y * self.width + x].clone(),
+            _ => Spec::new(N::zero(), N::zero(), N::zero()),
+        }
+    }
+
+    /// The image at half the size, each pixel the average of the pixels it covers. None once the
+    /// image is a single pixel.
+    fn half_size(&self) -> Option<MipLevel<N>> {
+        if self.width == 1 && self.height == 1 {
+            return None;
+        }
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                // odd sizes fold the last row or column into the pixel before it
+                let xs = (2 * x)..(if x == width - 1 {
+                    self.width
+                } else {
+                    2 * x + 2
+                });
+                let ys = (2 * y)..(if y == height - 1 {
+                    self.height
+                } else {
+                    2 * y + 2
+                });
+                let mut total = Spec::new(N::zero(), N::zero(), N::zero());
+                for sy in ys.clone() {
+                    for sx in xs.clone() {
+                        total += self.pixels[sy * self.width + sx].clone();
+                    }
+                }
+                let count = (xs.len() * ys.len()) as f32;
+                pixels.push(total * (N::one() / N::from(count)));
+            }
+        }
+        Some(MipLevel {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+impl<N: Float> Texture<Spec<N>> for ImageTexture<N> {
+    type NumTy = N;
+
+    fn evaluate(&self, g: &DiffGeom<N>) -> Spec<N> {
+        self.lookup(&g.uv, g.uv_width)
+    }
+}
+
+/// Mean of the channels, for images used as height maps.
+impl<N: Float> Texture<N> for ImageTexture<N> {
+    type NumTy = N;
+
+    fn evaluate(&self, g: &DiffGeom<N>) -> N {
+        let color = self.lookup(&g.uv, g.uv_width);
+        (color.red + color.green + color.blue) / N::from(3.0)
+    }
+}
+
+/// Converts an 8 bit sRGB channel to linear light.
+fn srgb_to_linear<N: Float>(value: u8) -> N {
+    let c = f32::from(value) / 255.0;
+    let linear = if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    };
+    N::from(linear)
+}
+
+/// Alternating squares of two textures over the surface coordinates.
+#[derive(Debug, Clone)]
+pub struct Checkerboard<A, B, N> {
+    even: A,
+    odd: B,
+    /// Number of squares across each unit of the surface coordinates.
+    frequency: N,
+}
+
+impl<A, B, N> Checkerboard<A, B, N> {
+    pub fn new(even: A, odd: B, frequency: N) -> Checkerboard<A, B, N> {
+        Checkerboard {
+            even,
+            odd,
+            frequency,
+        }
+    }
+}
+
+impl<T, N, A, B> Texture<T> for Checkerboard<A, B, N>
+where
+    N: Float,
+    A: Texture<T, NumTy = N>,
+    B: Texture<T, NumTy = N>,
+{
+    type NumTy = N;
+
+    fn evaluate(&self, g: &DiffGeom<N>) -> T {
+        let u = floor_index((g.uv.x * self.frequency).floor());
+        let v = floor_index((g.uv.y * self.frequency).floor());
+        if (u + v).rem_euclid(2) == 0 {
+            self.even.evaluate(g)
+        } else {
+            self.odd.evaluate(g)
+        }
+    }
+}
+
+/// Fractal noise over space, between about -1 and 1.
+#[derive(Debug, Clone)]
+pub struct FbmTexture<N> {
+    /// Size of the largest features of the noise.
+    scale: N,
+    octaves: usize,
+    roughness: N,
+}
+
+impl<N> FbmTexture<N> {
+    /// Noise of `octaves` layers, see `fbm`.
+    pub fn new(scale: N, octaves: usize, roughness: N) -> FbmTexture<N> {
+        FbmTexture {
+            scale,
+            octaves,
+            roughness,
+        }
+    }
+}
+
+impl<N: Float> Texture<N> for FbmTexture<N> {
+    type NumTy = N;
+
+    fn evaluate(&self, g: &DiffGeom<N>) -> N {
+        fbm(&(g.position / self.scale), self.octaves, self.roughness)
+    }
+}
+
+/// Layers of colored stone, bent by turbulence.
+#[derive(Debug, Clone)]
+pub struct Marble<N> {
+    /// Distance between the layers.
+    scale: N,
+    octaves: usize,
+    roughness: N,
+    /// How far the turbulence bends the layers.
+    variation: N,
+}
+
+impl<N> Marble<N> {
+    pub fn new(scale: N, octaves: usize, roughness: N, variation: N) -> Marble<N> {
+        Marble {
+            scale,
+            octaves,
+            roughness,
+            variation,
+        }
+    }
+}
+
+impl<N: Float> Texture<Spec<N>> for Marble<N> {
+    type NumTy = N;
+
+    fn evaluate(&self, g: &DiffGeom<N>) -> Spec<N> {
+        let p = g.position / self.scale;
+        let turbulence = turbulence(&p, self.octaves, self.roughness);
+        let t = N::from(0.5) + N::from(0.5) * (p.y + self.variation * turbulence).sin();
+        // colors of the layers, from the blue grey of the veins to off white
+        let colors: [(f32, f32, f32); 9] = [
+            (0.58, 0.58, 0.6),
+            (0.58, 0.58, 0.6),
+            (0.58, 0.58, 0.6),
+            (0.5, 0.5, 0.5),
+            (0.6, 0.59, 0.58),
+            (0.58, 0.58, 0.6),
+            (0.58, 0.58, 0.6),
+            (0.2, 0.2, 0.33),
+            (0.58, 0.58, 0.6),
+        ];
+        let colors: Vec<Spec<N>> = colors
+            .iter()
+            .map(|&(r, g, b)| Spec::new(N::from(r), N::from(g), N::from(b)))
+            .collect();
+        bspline(&colors, t) * N::from(1.5)
+    }
+}
+
+/// Rings of light and dark wood around the y axis, wobbled by noise.
+#[derive(Clone)]
+pub struct Wood<N> {
+    light: Spec<N>,
+    dark: Spec<N>,
+    /// Distance between the rings.
+    scale: N,
+    /// How far the noise moves the rings, as a fraction of the distance between them.
+    distortion: N,
+}
+
+impl<N> Wood<N> {
+    pub fn new(light: Spec<N>, dark: Spec<N>, scale: N, distortion: N) -> Wood<N> {
+        Wood {
+            light,
+            dark,
+            scale,
+            distortion,
+        }
+    }
+}
+
+impl<N: Float> Texture<Spec<N>> for Wood<N> {
+    type NumTy = N;
+
+    fn evaluate(&self, g: &DiffGeom<N>) -> Spec<N> {
+        let p = g.position / self.scale;
+        let radius = (p.x * p.x + p.z * p.z).sqrt();
+        let wobble = fbm(&(p * N::from(2.0)), 4, N::from(0.5));
+        let ring = radius + self.distortion * wobble;
+        // each ring fades from light wood to a sharp dark edge
+        let t = (ring - ring.floor()).powf(N::from(3.0));
+        self.light.clone() * (N::one() - t) + self.dark.clone() * t
+    }
+}
+
+/// Uniform cubic B-spline through `points`, at `t` in `[0, 1]`. There must be at least 4 points.
+fn bspline<N: Float>(points: &[Spec<N>], t: N) -> Spec<N> {
+    let segments = points.len() - 3;
+    let x = t.max(N::zero()).min(N::one()) * N::from(segments as f32);
+    let first = (x.floor().to_subset().unwrap_or(0.0) as usize).min(segments - 1);
+    let t = x - N::from(first as f32);
+    let p = &points[first..first + 4];
+    let one = N::one();
+    let sixth = one / N::from(6.0);
+    let w0 = (one - t).powi(3) * sixth;
+    let w1 = (N::from(3.0) * t.powi(3) - N::from(6.0) * t * t + N::from(4.0)) * sixth;
+    let w2 = (N::from(-3.0) * t.powi(3) + N::from(3.0) * t * t + N::from(3.0) * t + one) * sixth;
+    let w3 = t.powi(3) * sixth;
+    p[0].clone() * w0 + p[1].clone() * w1 + p[2].clone() * w2 + p[3].clone() * w3
+}
+
+/// Integer part of `x`, which must already be a whole number.
+fn floor_index<N: Float>(x: N) -> i64 {
+    x.to_subset().unwrap_or(0.0) as i64
+}
+
+/// Ken Perlin's permutation of 0 to 255, used to hash lattice points.
+const PERMUTATION: [u8; 256] = [
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30, 69,
+    142, 8, 99, 37, 240, 21, 10, 23, 190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219,
+    203, 117, 35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87, 174, 20, 125, 136, 171, 168, 68, 175,
+    74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122, 60, 211, 133, 230,
+    220, 105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54, 65, 25, 63, 161, 1, 216, 80, 73, 209, 76,
+    132, 187, 208, 89, 18, 169, 200, 196, 135, 130, 116, 188, 159, 86, 164, 100, 109, 198, 173,
+    186, 3, 64, 52, 217, 226, 250, 124, 123, 5, 202, 38, 147, 118, 126, 255, 82, 85, 212, 207, 206,
+    59, 227, 47, 16, 58, 17, 182, 189, 28, 42, 223, 183, 170, 213, 119, 248, 152, 2, 44, 154, 163,
+    70, 221, 153, 101, 155, 167, 43, 172, 9, 129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232,
+    178, 185, 112, 104, 218, 246, 97, 228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162,
+    241, 81, 51, 145, 235, 249, 14, 239, 107, 49, 192, 214, 31, 181, 199, 106, 157, 184, 84, 204,
+    176, 115, 121, 50, 45, 127, 4, 150, 254, 138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141,
+    128, 195, 78, 66, 215, 61, 156, 180,
+];
+
+fn hash(x: i64, y: i64, z: i64) -> u8 {
+    let p = |i: i64| i64::from(PERMUTATION[i.rem_euclid(256) as usize]);
+    p(p(p(x) + y) + z) as u8
+}
+
+/// Perlin noise, smoothly varying between about -1 and 1 with features about 1 unit across. It
+/// is 0 at every point with whole number coordinates.
+pub fn noise<N: Float>(p: &Point3<N>) -> N {
+    let cell = [p.x.floor(), p.y.floor(), p.z.floor()];
+    let (x, y, z) = (
+        floor_index(cell[0]),
+        floor_index(cell[1]),
+        floor_index(cell[2]),
+    );
+    let (dx, dy, dz) = (p.x - cell[0], p.y - cell[1], p.z - cell[2]);
+
+    // gradient of a lattice corner dotted with the offset from it
+    let corner = |i: i64, j: i64, k: i64| {
+        let offset = (
+            dx - N::from(i as f32),
+            dy - N::from(j as f32),
+            dz - N::from(k as f32),
+        );
+        let h = hash(x + i, y + j, z + k) & 15;
+        let u = if h < 8 { offset.0 } else { offset.1 };
+        let v = match h {
+            0..=3 => offset.1,
+            12 | 14 => offset.0,
+            _ => offset.2,
+        };
+        let u = if h & 1 == 0 { u } else { -u };
+        let v = if h & 2 == 0 { v } else { -v };
+        u + v
+    };
+    let lerp = |t: N, a: N, b: N| a + t * (b - a);
+    let (sx, sy, sz) = (smootherstep(dx), smootherstep(dy), smootherstep(dz));
+    let y0 = lerp(
+        sy,
+        lerp(sx, corner(0, 0, 0), corner(1, 0, 0)),
+        lerp(sx, corner(0, 1, 0), corner(1, 1, 0)),
+    );
+    let y1 = lerp(
+        sy,
+        lerp(sx, corner(0, 0, 1), corner(1, 0, 1)),
+        lerp(sx, corner(0, 1, 1), corner(1, 1, 1)),
+    );
+    lerp(sz, y0, y1)
+}
+
+/// `6t^5 - 15t^4 + 10t^3`, which eases between 0 and 1 with no jump in the first two derivatives.
+fn smootherstep<N: Float>(t: N) -> N {
+    t * t * t * (t * (t * N::from(6.0) - N::from(15.0)) + N::from(10.0))
+}
+
+/// Fractional Brownian motion: `octaves` layers of `noise`, each with features half the size of
+/// the last and `roughness` times the strength.
+pub fn fbm<N: Float>(p: &Point3<N>, octaves: usize, roughness: N) -> N {
+    let mut total = N::zero();
+    let mut frequency = N::one();
+    let mut strength = N::one();
+    for _ in 0..octaves {
+        total += strength * noise(&(p * frequency));
+        frequency *= N::from(2.0);
+        strength *= roughness;
+    }
+    total
+}
+
+/// Like `fbm`, but adding the absolute value of each layer, which gives sharp creases where the
+/// noise crosses 0.
+pub fn turbulence<N: Float>(p: &Point3<N>, octaves: usize, roughness: N) -> N {
+    let mut total = N::zero();
+    let mut frequency = N::one();
+    let mut strength = N::one();
+    for _ in 0..octaves {
+        total += strength * noise(&(p * frequency)).abs();
+        frequency *= N::from(2.0);
+        strength *= roughness;
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::ray::tests::st_vec3;
+    use crate::render::temp_dir::TempDir;
+    use na::Vector3;
+    use proptest::prelude::*;
+
+    fn geom_at(uv: Point2<f64>, position: Point3<f64>) -> DiffGeom<f64> {
+        DiffGeom {
+            position,
+            normal: Vector3::z(),
+            geometric_normal: Vector3::z(),
+            uv,
+            dpdu: Vector3::x(),
+            dpdv: Vector3::y(),
+            uv_width: 0.0,
+        }
+    }
+
+    /// 2 by 2 image, red and green along the top, blue and white along the bottom.
+    fn square() -> ImageTexture<f64> {
+        ImageTexture::new(
+            2,
+            2,
+            vec![
+                Spec::new(1.0, 0.0, 0.0),
+                Spec::new(0.0, 1.0, 0.0),
+                Spec::new(0.0, 0.0, 1.0),
+                Spec::new(1.0, 1.0, 1.0),
+            ],
+        )
+    }
+
+    #[test]
+    fn pixel_centres_are_exact() {
+        let texture = square().with_filter(TextureFilter::Bilinear);
+        // uv (0.25, 0.75) is the centre of the top left pixel
+        let red = texture.lookup(&Point2::new(0.25, 0.75), 0.0);
+        assert_eq!((red.red, red.green, red.blue), (1.0, 0.0, 0.0));
+        let white = texture.lookup(&Point2::new(0.75, 0.25), 0.0);
+        assert_eq!((white.red, white.green, white.blue), (1.0, 1.0, 1.0));
+        // halfway between red and green
+        let mixed = texture.lookup(&Point2::new(0.5, 0.75), 0.0);
+        assert!((mixed.red - 0.5).abs() < 1e-10 && (mixed.green - 0.5).abs() < 1e-10);
+    }
+
+    #[test]
+    fn wrap_modes() {
+        let corner = Point2::new(0.0, 1.0);
+        let repeat = square().lookup(&corner, 0.0);
+        // the top left corner blends all 4 pixels when tiled
+        assert!((repeat.red - 0.5).abs() < 1e-10 && (repeat.blue - 0.5).abs() < 1e-10);
+        let clamp = square().with_wrap(WrapMode::Clamp).lookup(&corner, 0.0);
+        assert_eq!(clamp.red, 1.0);
+        let black = square().with_wrap(WrapMode::Black).lookup(&corner, 0.0);
+        assert!((black.red - 0.25).abs() < 1e-10);
+    }
+
+    #[test]
+    fn wide_lookups_average_the_image() {
+        let texture = square();
+        assert_eq!(texture.levels.len(), 2);
+        let average = texture.lookup(&Point2::new(0.3, 0.8), 1.0);
+        assert!((average.red - 0.5).abs() < 1e-10);
+        assert!((average.blue - 0.5).abs() < 1e-10);
+        // halfway between the levels blends them
+        let between = texture.lookup(&Point2::new(0.25, 0.75), 2f64.sqrt() / 2.0);
+        assert!((between.red - 0.75).abs() < 1e-10);
+        // and bilinear filtering always uses the full size image
+        let sharp = texture
+            .with_filter(TextureFilter::Bilinear)
+            .lookup(&Point2::new(0.25, 0.75), 1.0);
+        assert_eq!(sharp.red, 1.0);
+    }
+
+    #[test]
+    fn odd_sizes_shrink_to_one_pixel() {
+        let texture: ImageTexture<f64> =
+            ImageTexture::new(5, 3, vec![Spec::new(0.5, 0.5, 0.5); 15]);
+        let sizes: Vec<_> = texture.levels.iter().map(|l| (l.width, l.height)).collect();
+        assert_eq!(sizes, vec![(5, 3), (2, 1), (1, 1)]);
+        assert!((texture.levels[2].pixels[0].red - 0.5).abs() < 1e-10);
+    }
+
+    #[test]
+    fn loads_images() {
+        let dir = TempDir::new("load_texture");
+        let path = dir.join("grey.png");
+        image::RgbImage::from_pixel(4, 2, image::Rgb([188, 188, 188]))
+            .save(&path)
+            .unwrap();
+        let texture: ImageTexture<f64> = ImageTexture::load(&path).unwrap();
+        assert_eq!((texture.levels[0].width, texture.levels[0].height), (4, 2));
+        // sRGB 188 is about half as bright as white
+        assert!((texture.levels[0].pixels[0].red - 0.5).abs() < 0.01);
+        assert!(matches!(
+            ImageTexture::<f64>::load(&dir.join("missing.png")),
+            Err(TextureLoadError::OpenError)
+        ));
+    }
+
+    #[test]
+    fn checkerboard_alternates() {
+        let board = Checkerboard::new(ConstantTexture::new(1.0), ConstantTexture::new(0.0), 4.0);
+        let at = |u, v| board.evaluate(&geom_at(Point2::new(u, v), Point3::origin()));
+        assert_eq!(at(0.1, 0.1), 1.0);
+        assert_eq!(at(0.3, 0.1), 0.0);
+        assert_eq!(at(0.3, 0.3), 1.0);
+        assert_eq!(at(-0.1, 0.1), 0.0);
+    }
+
+    #[test]
+    fn procedural_colors_stay_in_range() {
+        let marble = Marble::new(0.5, 8, 0.5, 0.5);
+        let wood = Wood::new(Spec::new(0.8, 0.6, 0.4), Spec::new(0.3, 0.2, 0.1), 0.1, 0.5);
+        for i in 0..50 {
+            let p = Point3::new(f64::from(i) * 0.37, f64::from(i) * -0.21, 1.3);
+            let g = geom_at(Point2::origin(), p);
+            let color = marble.evaluate(&g);
+            assert!(color.red >= 0.0 && color.red <= 1.0);
+            let color = wood.evaluate(&g);
+            assert!(color.red >= 0.3 && color.red <= 0.8);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn noise_is_bounded_and_smooth(p in st_vec3(-100f64..100.0)) {
+            let p = Point3::from(p);
+            let n = noise(&p);
+            prop_assert!(n.abs() <= 1.1);
+            let step = Vector3::new(1e-4, 1e-4, 1e-4);
+            prop_assert!((noise(&(p + step)) - n).abs() < 1e-3);
+            prop_assert!(noise(&Point3::from(p.coords.map(f64::floor))) == 0.0);
+            prop_assert!(fbm(&p, 6, 0.5).abs() <= 2.2);
+            prop_assert!(turbulence(&p, 6, 0.5) >= 0.0);
+        }
+    }
+}
@@ -1,15 +1,35 @@
-use super::plane::Plane;
+use super::aabb::Aabb;
 use super::ray;
+use super::sampling::coordinate_system;
 use super::shape::*;
-use crate::number;
-use alga::general::{RealField, Ring};
-use na::{Point3, Scalar, Vector3};
+use crate::number::{self, Float};
+use alga::general::Ring;
+use na::{Point2, Point3, Scalar, Vector3};
 
-#[derive(Debug)]
+/// Algorithm used to intersect rays with triangles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TriangleIntersection {
+    /// The Möller-Trumbore algorithm. It is fast, but rays can slip through the edge shared
+    /// by two triangles, leaving cracks in meshes.
+    #[default]
+    MollerTrumbore,
+    /// The watertight algorithm of Woop, Benthin and Wald. A ray through an edge shared by two
+    /// triangles always hits at least one of them.
+    Watertight,
+}
+
+#[derive(Debug, Clone)]
 pub struct Triangle<T: Scalar> {
     v1: Point3<T>,
     v2: Point3<T>,
     v3: Point3<T>,
+    // Per vertex normals, interpolated across the triangle for smooth shading.
+    normals: Option<[Vector3<T>; 3]>,
+    // Per vertex texture coordinates.
+    uvs: Option<[Point2<T>; 3]>,
+    // Per vertex tangents, interpolated across the triangle in place of its own dpdu.
+    tangents: Option<[Vector3<T>; 3]>,
+    method: TriangleIntersection,
 }
 
 impl<T: Scalar> Triangle<T> {
@@ -18,8 +38,45 @@ impl<T: Scalar> Triangle<T> {
             v1: Point3::from(vertex1),
             v2: Point3::from(vertex2),
             v3: Point3::from(vertex3),
+            normals: None,
+            uvs: None,
+            tangents: None,
+            method: TriangleIntersection::default(),
+        }
+    }
+
+    /// Sets the normals at each vertex, in the same order as the vertices.
+    /// The normal at a point on the triangle is interpolated from these.
+    pub fn with_normals(self, normals: [Vector3<T>; 3]) -> Triangle<T> {
+        Triangle {
+            normals: Some(normals),
+            ..self
+        }
+    }
+
+    /// Sets the texture coordinates at each vertex, in the same order as the vertices.
+    pub fn with_uvs(self, uvs: [Point2<T>; 3]) -> Triangle<T> {
+        Triangle {
+            uvs: Some(uvs),
+            ..self
+        }
+    }
+
+    /// Sets the tangents at each vertex, the change in position along increasing u, in the same
+    /// order as the vertices. At a point on the triangle `dpdu` is interpolated from these rather
+    /// than taken from the triangle alone, so that normal maps line up across the edges of a
+    /// smooth mesh.
+    pub fn with_tangents(self, tangents: [Vector3<T>; 3]) -> Triangle<T> {
+        Triangle {
+            tangents: Some(tangents),
+            ..self
         }
     }
+
+    /// Sets the algorithm used to intersect rays with the triangle.
+    pub fn with_intersection(self, method: TriangleIntersection) -> Triangle<T> {
+        Triangle { method, ..self }
+    }
 }
 
 impl<T: Scalar + Ring> Triangle<T> {
@@ -32,9 +89,117 @@ impl<T: Scalar + Ring> Triangle<T> {
     }
 }
 
-impl<N: RealField + From<f32>> Shape for Triangle<N> {
+impl<T: Float> Triangle<T> {
+    /// Texture coordinates of the vertices. Without texture coordinates, the barycentric
+    /// coordinates are used.
+    fn corner_uvs(&self) -> [Point2<T>; 3] {
+        self.uvs.unwrap_or([
+            Point2::origin(),
+            Point2::new(T::one(), T::zero()),
+            Point2::new(T::zero(), T::one()),
+        ])
+    }
+
+    /// Change in position along the texture coordinates u and v, across the whole triangle.
+    /// None when the texture coordinates don't span an area.
+    pub fn uv_derivatives(&self) -> Option<(Vector3<T>, Vector3<T>)> {
+        let [uv1, uv2, uv3] = self.corner_uvs();
+        let (du1, du2) = (uv2 - uv1, uv3 - uv1);
+        let (e1, e2) = (self.v2 - self.v1, self.v3 - self.v1);
+        let determinant = du1.x * du2.y - du1.y * du2.x;
+        if determinant.abs() <= T::default_epsilon() {
+            return None;
+        }
+        let inv = T::one() / determinant;
+        Some((
+            (e1 * du2.y - e2 * du1.y) * inv,
+            (e2 * du1.x - e1 * du2.x) * inv,
+        ))
+    }
+
+    /// Builds the interaction for a hit at ray time `t`, with barycentric coordinates `b1` and
+    /// `b2`, the weights of the second and third vertex.
+    fn interaction(&self, t: T, b1: T, b2: T) -> SurfaceInteraction<T> {
+        let b0 = T::one() - b1 - b2;
+        let point = Point3::from(self.v1.coords * b0 + self.v2.coords * b1 + self.v3.coords * b2);
+        let point_error =
+            (self.v1.coords * b0).abs() + (self.v2.coords * b1).abs() + (self.v3.coords * b2).abs();
+        let point_error = point_error * T::gamma_bound(7);
+        let true_normal = self.true_normal();
+        let normal = true_normal.normalize();
+
+        let [uv1, uv2, uv3] = self.corner_uvs();
+        let uv = Point2::from(uv1.coords * b0 + uv2.coords * b1 + uv3.coords * b2);
+        let (dpdu, dpdv) = self
+            .uv_derivatives()
+            // texture coordinates don't span an area, so any axes in the plane will do
+            .unwrap_or_else(|| coordinate_system(&normal));
+        let dpdu = match &self.tangents {
+            Some([t1, t2, t3]) => {
+                let tangent = t1 * b0 + t2 * b1 + t3 * b2;
+                if tangent.norm_squared() > T::zero() {
+                    tangent
+                } else {
+                    dpdu
+                }
+            }
+            None => dpdu,
+        };
+
+        let interaction = SurfaceInteraction::new(t, point, point_error, normal, uv, dpdu, dpdv);
+        match &self.normals {
+            None => interaction,
+            Some([n1, n2, n3]) => {
+                let shading = (n1 * b0 + n2 * b1 + n3 * b2).try_normalize(T::zero());
+                // opposite vertex normals can cancel out, leaving nothing to shade with
+                match shading {
+                    Some(shading) => interaction.with_shading_normal(shading),
+                    None => interaction,
+                }
+            }
+        }
+    }
+}
+
+impl<N: Float> Shape for Triangle<N> {
     type NumTy = N;
-    fn intersection(&self, ray: &ray::Ray<N>) -> Option<N> {
+
+    fn bounds(&self) -> Aabb<N> {
+        Aabb::from_points(&[self.v1, self.v2, self.v3])
+    }
+
+    fn intersection(&self, ray: &ray::Ray<N>) -> Option<SurfaceInteraction<N>> {
+        match self.method {
+            TriangleIntersection::MollerTrumbore => self.moller_trumbore(ray),
+            TriangleIntersection::Watertight => self.watertight(ray),
+        }
+    }
+
+    /// Picks a point uniformly over the triangle.
+    fn sample(&self, u1: N, u2: N) -> Option<SurfaceSample<N>> {
+        let true_normal = self.true_normal();
+        let double_area = true_normal.magnitude();
+        if double_area <= N::zero() {
+            return None;
+        }
+        // the square root keeps the density uniform, rather than bunched up at the first vertex
+        let root = u1.sqrt();
+        let (b0, b1) = (N::one() - root, u2 * root);
+        let b2 = N::one() - b0 - b1;
+        let point = Point3::from(self.v1.coords * b0 + self.v2.coords * b1 + self.v3.coords * b2);
+        let point_error =
+            (self.v1.coords * b0).abs() + (self.v2.coords * b1).abs() + (self.v3.coords * b2).abs();
+        Some(SurfaceSample {
+            point,
+            point_error: point_error * N::gamma_bound(6),
+            normal: true_normal / double_area,
+            pdf: (N::one() + N::one()) / double_area,
+        })
+    }
+}
+
+impl<N: Float> Triangle<N> {
+    fn moller_trumbore(&self, ray: &ray::Ray<N>) -> Option<SurfaceInteraction<N>> {
         let e1: Vector3<N> = self.v2 - self.v1;
         let e2: Vector3<N> = self.v3 - self.v1;
         let s1 = ray.direction.cross(&e2);
@@ -59,31 +224,114 @@ impl<N: RealField + From<f32>> Shape for Triangle<N> {
         }
 
         let t = e2.dot(&s2) * inv_div;
-        if t < N::zero() {
+        if !ray.contains(t) {
             None
         } else {
-            Some(t)
+            Some(self.interaction(t, b1, b2))
         }
     }
 
-    fn normal(&self, _point: &Point3<Self::NumTy>) -> Vector3<Self::NumTy> {
-        self.true_normal()
+    /// Watertight intersection, following Physically Based Rendering.
+    ///
+    /// The vertices are moved into a space where the ray starts at the origin and points down
+    /// the z axis, so the hit test becomes a 2D test of whether the origin is inside the
+    /// projected triangle. Each edge test only depends on the two vertices of that edge, so
+    /// triangles sharing an edge compute exactly opposite results for it and can't both miss.
+    fn watertight(&self, ray: &ray::Ray<N>) -> Option<SurfaceInteraction<N>> {
+        // permute the axes so the largest direction component is z, avoiding a divide by zero
+        let abs_dir = ray.direction.abs();
+        let kz = abs_dir.imax();
+        let kx = (kz + 1) % 3;
+        let ky = (kx + 1) % 3;
+        let permute = |v: Vector3<N>| Vector3::new(v[kx], v[ky], v[kz]);
+        let dir = permute(ray.direction.into_inner());
+        if dir.z == N::zero() {
+            return None;
+        }
+
+        // shear so the ray points down z
+        let shear_x = -dir.x / dir.z;
+        let shear_y = -dir.y / dir.z;
+        let shear_z = N::one() / dir.z;
+        let transform = |v: &Point3<N>| {
+            let p = permute(v - ray.origin);
+            Vector3::new(p.x + shear_x * p.z, p.y + shear_y * p.z, p.z)
+        };
+        let (mut p0, mut p1, mut p2) = (
+            transform(&self.v1),
+            transform(&self.v2),
+            transform(&self.v3),
+        );
+
+        let e0 = edge_function(p1.x, p1.y, p2.x, p2.y);
+        let e1 = edge_function(p2.x, p2.y, p0.x, p0.y);
+        let e2 = edge_function(p0.x, p0.y, p1.x, p1.y);
+        let zero = N::zero();
+        if (e0 < zero || e1 < zero || e2 < zero) && (e0 > zero || e1 > zero || e2 > zero) {
+            return None;
+        }
+        let det = e0 + e1 + e2;
+        if det == zero {
+            return None;
+        }
+
+        // the shear of z is only needed for the hit distance, so it is left until here
+        p0.z *= shear_z;
+        p1.z *= shear_z;
+        p2.z *= shear_z;
+        let inv_det = N::one() / det;
+        let t = (e0 * p0.z + e1 * p1.z + e2 * p2.z) * inv_det;
+        if !ray.contains(t) {
+            return None;
+        }
+
+        // Reject hits so close to the origin that rounding error could put them behind it.
+        let max_x = p0.x.abs().max(p1.x.abs()).max(p2.x.abs());
+        let max_y = p0.y.abs().max(p1.y.abs()).max(p2.y.abs());
+        let max_z = p0.z.abs().max(p1.z.abs()).max(p2.z.abs());
+        let delta_x = N::gamma_bound(5) * (max_x + max_z);
+        let delta_y = N::gamma_bound(5) * (max_y + max_z);
+        let delta_z = N::gamma_bound(3) * max_z;
+        let two = N::one() + N::one();
+        let delta_e = two * (N::gamma_bound(2) * max_x * max_y + delta_y * max_x + delta_x * max_y);
+        let max_e = e0.abs().max(e1.abs()).max(e2.abs());
+        let delta_t = (two + N::one())
+            * (N::gamma_bound(3) * max_e * max_z + delta_e * max_z + delta_z * max_e)
+            * inv_det.abs();
+        if t <= delta_t {
+            return None;
+        }
+
+        Some(self.interaction(t, e1 * inv_det, e2 * inv_det))
     }
 }
 
+/// Twice the signed area of the triangle formed by the origin and the points `a` and `b`.
+fn edge_function<N: Float>(ax: N, ay: N, bx: N, by: N) -> N {
+    let p = ax * by;
+    let q = ay * bx;
+    let e = p - q;
+    if e != N::zero() {
+        return e;
+    }
+    // A zero may just be rounding, which would count a hit just outside the edge. The difference
+    // only rounds to zero when the two rounded products are equal, so the exact result is the
+    // difference of their rounding errors. A fused multiply-add finds each error exactly, and
+    // subtracting them rounds once, which can't change the sign.
+    ax.mul_add(by, -p) - ay.mul_add(bx, -q)
+}
+
 #[cfg(test)]
-mod tests {
+pub mod tests {
+    use super::super::plane::Plane;
     use super::ray::tests::{arb_ray, st_vec3};
     use super::ray::*;
     use super::*;
     use proptest::prelude::*;
 
-    pub fn arb_tri<T: Scalar>(
+    pub fn arb_tri<T: Scalar + Arbitrary>(
         s: impl Strategy<Value = T> + Clone,
-    ) -> impl Strategy<Value = Triangle<T>>
-    where
-        T: Arbitrary,
-    {
+    ) -> impl Strategy<Value = Triangle<T>> {
         (st_vec3(s.clone()), st_vec3(s.clone()), st_vec3(s.clone()))
             .prop_map(|(v1, v2, v3)| Triangle::new(v1, v2, v3))
     }
@@ -108,12 +356,138 @@ mod tests {
 
         let intersection = tri.intersection(&ray);
         match intersection {
-            None => assert!(false),
-            Some(t) => assert!((t - 1.0).abs() < 0.00001),
+            None => panic!("ray should hit the triangle"),
+            Some(hit) => assert!((hit.t - 1.0).abs() < 0.00001),
+        }
+    }
+
+    #[test]
+    fn watertight_matches_moller_trumbore() {
+        let tri: Triangle<f64> = Triangle::new(
+            Vector3::new(0.0, -1.0, 1.0),
+            Vector3::new(0.0, -1.0, -1.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        );
+        let watertight = tri
+            .clone()
+            .with_intersection(TriangleIntersection::Watertight);
+        let ray: Ray<f64> =
+            Ray::new_normalize(Point3::new(-1.0, 0.2, 0.1), Vector3::new(1.0, -0.1, 0.05));
+        let expected = tri.intersection(&ray).expect("ray hits the triangle");
+        let actual = watertight
+            .intersection(&ray)
+            .expect("ray hits the triangle");
+        assert!((expected.t - actual.t).abs() < 0.00001);
+        assert!((expected.point - actual.point).magnitude() < 0.00001);
+        assert!((expected.uv - actual.uv).magnitude() < 0.00001);
+
+        let miss = Ray::new_normalize(Point3::new(-1.0, 2.0, 0.0), Vector3::x());
+        assert!(watertight.intersection(&miss).is_none());
+        let behind = Ray::new_normalize(Point3::new(-1.0, 0.0, 0.0), -Vector3::x());
+        assert!(watertight.intersection(&behind).is_none());
+    }
+
+    #[test]
+    fn interaction_interpolates_vertex_data() {
+        let tri: Triangle<f64> = Triangle::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        )
+        .with_normals([Vector3::x(), Vector3::y(), Vector3::z()])
+        .with_uvs([
+            Point2::new(0.0, 0.0),
+            Point2::new(2.0, 0.0),
+            Point2::new(0.0, 2.0),
+        ]);
+        let ray = Ray::new_normalize(Point3::new(0.25, 0.5, 1.0), Vector3::new(0.0, 0.0, -1.0));
+        let si = tri.intersection(&ray).expect("ray hits the triangle");
+        assert!((si.point - Point3::new(0.25, 0.5, 0.0)).magnitude() < 0.00001);
+        assert!((si.geometric_normal - Vector3::z()).magnitude() < 0.00001);
+        let shading = Vector3::new(0.25, 0.25, 0.5).normalize();
+        assert!((si.shading_normal - shading).magnitude() < 0.00001);
+        assert!((si.uv - Point2::new(0.5, 1.0)).magnitude() < 0.00001);
+        // the texture coordinates are stretched by 2 along both edges
+        assert!((si.dpdu - Vector3::new(0.5, 0.0, 0.0)).magnitude() < 0.00001);
+        assert!((si.dpdv - Vector3::new(0.0, 0.5, 0.0)).magnitude() < 0.00001);
+
+        let tangents = [Vector3::x(), Vector3::x(), Vector3::y()];
+        let si = tri.with_tangents(tangents).intersection(&ray).unwrap();
+        assert!((si.dpdu - Vector3::new(0.5, 0.5, 0.0)).magnitude() < 0.00001);
+    }
+
+    /// Two triangles in the same plane, on either side of the edge from `a` to `c`, and a ray
+    /// from `origin` to a point on the edge. Returns None for thin triangles and rays that nearly
+    /// run along the plane, which can miss in the usual way.
+    fn shared_edge(
+        a: Vector3<f32>,
+        c: Vector3<f32>,
+        normal: Vector3<f32>,
+        along: (f32, f32, f32),
+        widths: (f32, f32),
+        origin: Vector3<f32>,
+    ) -> Option<(Triangle<f32>, Triangle<f32>, Ray<f32>)> {
+        let edge = c - a;
+        let side = normal.cross(&edge);
+        if edge.magnitude() <= 0.1 || side.magnitude() <= 0.1 * edge.magnitude() {
+            return None;
         }
+        let side = side.normalize();
+        let (target, b_along, d_along) = along;
+        let b = a + edge * b_along + side * widths.0;
+        let d = a + edge * d_along - side * widths.1;
+        let first = Triangle::new(a, b, c).with_intersection(TriangleIntersection::Watertight);
+        let second = Triangle::new(a, c, d).with_intersection(TriangleIntersection::Watertight);
+
+        let origin = Point3::from(origin);
+        let target = Point3::from(a + edge * target);
+        let plane_normal = side.cross(&edge).normalize();
+        if (origin - target).normalize().dot(&plane_normal).abs() <= 0.01 {
+            return None;
+        }
+        Some((first, second, Ray::new_normalize(origin, target - origin)))
+    }
+
+    #[test]
+    fn edge_function_sign_survives_rounding() {
+        // (1 + 2^-12)^2 rounds to 1 + 2^-11 in f32, losing the 2^-24 that makes it bigger
+        let x = 1.0 + 2f32.powi(-12);
+        let e = edge_function(x, 1.0, 1.0 + 2f32.powi(-11), x);
+        assert!(e > 0.0);
+        assert!(edge_function(x, 1.0, x, 1.0) == 0.0);
+    }
+
+    #[test]
+    fn watertight_shared_edge_regression() {
+        // a case watertight_shared_edge once failed on
+        let (first, second, ray) = shared_edge(
+            Vector3::new(-26.387146, 62.863205, 93.200165),
+            Vector3::new(-46.005867, -82.21164, 88.74078),
+            Vector3::new(0.5675776, 0.0, 0.0),
+            (0.28024262, 0.0, 0.0),
+            (0.1, 0.1),
+            Vector3::new(28.093307, -10.0464325, -26.692322),
+        )
+        .expect("triangles and ray are well formed");
+        assert!(first.intersection(&ray).is_some() || second.intersection(&ray).is_some());
     }
 
     proptest! {
+        #[test]
+        fn samples_on_triangle(tri in arb_tri(-100f64..100.0),
+                               u1 in 0.05f64..0.95,
+                               u2 in 0.05f64..0.95) {
+            let area = tri.true_normal().magnitude() / 2.0;
+            prop_assume!(area > 0.01);
+            let sample = tri.sample(u1, u2).unwrap();
+            // a ray straight at the sample hits the triangle there
+            let ray = Ray::new_normalize(sample.point + sample.normal, -sample.normal);
+            let hit = tri.intersection(&ray);
+            prop_assert!(hit.is_some());
+            prop_assert!((hit.unwrap().point - sample.point).magnitude() < 0.001);
+            prop_assert!((sample.pdf * area - 1.0).abs() < 0.0001);
+        }
+
         // Removing from actual test cases, until the sample rejection rate is improved
         // #[test]
         fn intersection_on_plane(tri in arb_tri(-1000f64..1000.0),
@@ -124,8 +498,8 @@ mod tests {
 
             match intersection {
                 None => prop_assume!(false), // throw out non-intersecting cases
-                Some (t) => {
-                    let int_point = ray.at_time(t);
+                Some (hit) => {
+                    let int_point = ray.at_time(hit.t);
                     let plane_of_triangle = Plane::new_at_point(tri.v1, tri.true_normal());
                     let dist = plane_of_triangle.distance_to(int_point);
                     prop_assert!(dist.abs() < 0.0001)
@@ -134,5 +508,17 @@ mod tests {
         }
 
         // prop, for any random point on the triange, a ray pointing to that should intersect
+
+        #[test]
+        fn watertight_shared_edge(a in st_vec3(-100f32..100.0), c in st_vec3(-100f32..100.0),
+                                  normal in st_vec3(-1f32..1.0),
+                                  along in (0.1f32..0.9, 0.0f32..1.0, 0.0f32..1.0),
+                                  widths in (0.1f32..10.0, 0.1f32..10.0),
+                                  origin in st_vec3(-100f32..100.0)) {
+            let shared = shared_edge(a, c, normal, along, widths, origin);
+            prop_assume!(shared.is_some());
+            let (first, second, ray) = shared.unwrap();
+            prop_assert!(first.intersection(&ray).is_some() || second.intersection(&ray).is_some());
+        }
     }
 }
@@ -0,0 +1,189 @@
+use na::{Point3, RealField, Vector3};
+
+/// Splits a planar polygon into triangles.
+///
+/// Returns triangles as indices into `points`, wound in the same direction as the polygon.
+/// Convex polygons are split into a fan around the first vertex. Concave polygons are split by
+/// ear clipping, which assumes the polygon is planar and does not intersect itself.
+/// Polygons with fewer than 3 points give no triangles.
+pub fn triangulate<N: RealField>(points: &[Point3<N>]) -> Vec<[usize; 3]> {
+    if points.len() < 3 {
+        return vec![];
+    }
+    let normal = polygon_normal(points);
+    if is_convex(points, &normal) {
+        fan(&(0..points.len()).collect::<Vec<_>>())
+    } else {
+        ear_clip(points, &normal)
+    }
+}
+
+/// Normal of the polygon by Newell's method, which averages over every edge so it is robust
+/// for polygons that are concave or nearly degenerate. Its length is twice the polygon's area.
+fn polygon_normal<N: RealField>(points: &[Point3<N>]) -> Vector3<N> {
+    let mut normal = Vector3::zeros();
+    for (i, current) in points.iter().enumerate() {
+        let next = points[(i + 1) % points.len()];
+        normal += current.coords.cross(&next.coords);
+    }
+    normal
+}
+
+/// True if every corner of the polygon turns the same way around `normal`.
+fn is_convex<N: RealField>(points: &[Point3<N>], normal: &Vector3<N>) -> bool {
+    let n = points.len();
+    (0..n).all(|i| {
+        let prev = points[(i + n - 1) % n];
+        let next = points[(i + 1) % n];
+        corner_turn(&prev, &points[i], &next, normal) >= N::zero()
+    })
+}
+
+/// Positive when the path `prev -> current -> next` turns counter-clockwise around `normal`.
+fn corner_turn<N: RealField>(
+    prev: &Point3<N>,
+    current: &Point3<N>,
+    next: &Point3<N>,
+    normal: &Vector3<N>,
+) -> N {
+    (current - prev).cross(&(next - current)).dot(normal)
+}
+
+/// Splits a polygon into a fan of triangles around its first vertex.
+fn fan(vertices: &[usize]) -> Vec<[usize; 3]> {
+    (1..vertices.len().saturating_sub(1))
+        .map(|i| [vertices[0], vertices[i], vertices[i + 1]])
+        .collect()
+}
+
+/// Repeatedly cuts off an ear, a convex corner whose triangle contains no other vertex of the
+/// polygon, until a single triangle is left.
+fn ear_clip<N: RealField>(points: &[Point3<N>], normal: &Vector3<N>) -> Vec<[usize; 3]> {
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::with_capacity(points.len() - 2);
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = (
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            );
+            corner_turn(&points[a], &points[b], &points[c], normal) > N::zero()
+                && !remaining.iter().any(|&p| {
+                    p != a
+                        && p != b
+                        && p != c
+                        && in_triangle(&points[p], &points[a], &points[b], &points[c], normal)
+                })
+        });
+        match ear {
+            Some(i) => {
+                triangles.push([
+                    remaining[(i + n - 1) % n],
+                    remaining[i],
+                    remaining[(i + 1) % n],
+                ]);
+                remaining.remove(i);
+            }
+            // Only a self intersecting or degenerate polygon has no ears. Fall back to a fan,
+            // which at least covers it.
+            None => break,
+        }
+    }
+    triangles.extend(fan(&remaining));
+    triangles
+}
+
+/// True if `p` lies inside or on the edge of the triangle `abc`, which is wound
+/// counter-clockwise around `normal`.
+fn in_triangle<N: RealField>(
+    p: &Point3<N>,
+    a: &Point3<N>,
+    b: &Point3<N>,
+    c: &Point3<N>,
+    normal: &Vector3<N>,
+) -> bool {
+    let side = |from: &Point3<N>, to: &Point3<N>| (to - from).cross(&(p - from)).dot(normal);
+    side(a, b) >= N::zero() && side(b, c) >= N::zero() && side(c, a) >= N::zero()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn area(points: &[Point3<f64>], triangles: &[[usize; 3]]) -> f64 {
+        let normal = polygon_normal(points).normalize();
+        triangles
+            .iter()
+            .map(|&[a, b, c]| {
+                (points[b] - points[a])
+                    .cross(&(points[c] - points[a]))
+                    .dot(&normal)
+                    / 2.0
+            })
+            .sum()
+    }
+
+    fn xy(coords: &[(f64, f64)]) -> Vec<Point3<f64>> {
+        coords
+            .iter()
+            .map(|&(x, y)| Point3::new(x, y, 0.0))
+            .collect()
+    }
+
+    #[test]
+    fn quad_is_fan() {
+        let quad = xy(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+        assert_eq!(triangulate(&quad), vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn too_few_points() {
+        assert!(triangulate(&xy(&[(0.0, 0.0), (1.0, 0.0)])).is_empty());
+    }
+
+    #[test]
+    fn concave_polygon() {
+        // an L shape, with the reflex corner at (1, 1)
+        let l_shape = xy(&[
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 2.0),
+            (0.0, 2.0),
+        ]);
+        let triangles = triangulate(&l_shape);
+        assert_eq!(triangles.len(), 4);
+        assert!((area(&l_shape, &triangles) - 3.0).abs() < 1e-10);
+        // every triangle keeps the polygon's winding, so none of them fold over
+        let normal = polygon_normal(&l_shape);
+        for &[a, b, c] in &triangles {
+            assert!(corner_turn(&l_shape[a], &l_shape[b], &l_shape[c], &normal) > 0.0);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn star_polygon_area_is_kept(radii in prop::collection::vec(0.2f64..2.0, 3..12),
+                                     tilt in -1f64..1.0) {
+            // A star shaped polygon, possibly concave, in a tilted plane.
+            let n = radii.len();
+            let points: Vec<Point3<f64>> = radii.iter().enumerate().map(|(i, r)| {
+                let angle = 2.0 * std::f64::consts::PI * i as f64 / n as f64;
+                let (x, y) = (r * angle.cos(), r * angle.sin());
+                Point3::new(x, y, x * tilt)
+            }).collect();
+            let triangles = triangulate(&points);
+            prop_assert_eq!(triangles.len(), n - 2);
+            let expected = polygon_normal(&points).magnitude() / 2.0;
+            prop_assert!((area(&points, &triangles) - expected).abs() < 1e-8);
+            let normal = polygon_normal(&points);
+            for &[a, b, c] in &triangles {
+                prop_assert!(corner_turn(&points[a], &points[b], &points[c], &normal) >= -1e-10);
+            }
+        }
+    }
+}